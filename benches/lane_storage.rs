@@ -0,0 +1,73 @@
+//! Measures how much memory the lane definitions of a 100k edge network take.
+//!
+//! Run with `cargo bench --bench lane_storage`.
+#![allow(dead_code)]
+
+#[path = "../src/float.rs"]
+mod float;
+#[path = "../src/math/mod.rs"]
+mod math;
+#[path = "../src/traffic/mod.rs"]
+mod traffic;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use traffic::{LaneDefinition, LaneTypeManager};
+
+const EDGES: usize = 100_000;
+/// Size of the previous representation, an enum of 40 fixed arrays, which was as large as its biggest variant.
+const FIXED_ARRAY_SIZE: usize = 41;
+const LAYOUTS: [&str; 7] = [
+    "Grass|Sidewalk|NormalReverse|NormalForward|Sidewalk|Grass",
+    "Sidewalk|ParkingReverse|NormalReverse|NormalReverse|NormalForward|NormalForward|ParkingForward|Sidewalk",
+    "Sidewalk|BusReverse|NormalReverse|NormalForward|BusForward|Sidewalk",
+    "Grass|DirtReverse|DirtForward|Grass",
+    "Grass|ShoulderReverse|NormalReverse|NormalReverse|Grass|NormalForward|NormalForward|ShoulderForward|Grass",
+    "Grass|Sidewalk|ParkingReverse|BusReverse|NormalReverse|NormalReverse|NormalReverse|Grass|Grass|NormalForward|NormalForward|NormalForward|BusForward|ParkingForward|Sidewalk|Grass",
+    "Grass|Sidewalk|ParkingReverse|BusReverse|NormalReverse|NormalReverse|NormalReverse|NormalReverse|Grass|Grass|NormalForward|NormalForward|NormalForward|NormalForward|BusForward|ParkingForward|Sidewalk|Grass",
+];
+
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn main() {
+    let manager = LaneTypeManager::new();
+    let templates = LAYOUTS.map(|layout| manager.parse(layout).unwrap());
+    for (layout, template) in LAYOUTS.iter().zip(&templates) {
+        println!("{:>2} lanes, size {:>2}: {layout}", template.lane_count(), template.get_size());
+    }
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let network = (0..EDGES).map(|i| templates[i % templates.len()].clone()).collect::<Vec<LaneDefinition>>();
+    let build_time = start.elapsed();
+    let heap = ALLOCATED.load(Ordering::Relaxed) - before - network.capacity() * size_of::<LaneDefinition>();
+    let start = Instant::now();
+    let lanes = network.iter().map(|lane_def| black_box(lane_def).lanes().filter(|lane| lane.direction().is_some()).count()).sum::<usize>();
+    let iterate_time = start.elapsed();
+    println!();
+    println!("Edges:                 {EDGES}");
+    println!("Inline size:           {} bytes", size_of::<LaneDefinition>());
+    println!("Spilled heap:          {heap} bytes");
+    println!("Memory per edge:       {:.2} bytes", (EDGES * size_of::<LaneDefinition>() + heap) as f64 / EDGES as f64);
+    println!("Fixed array per edge:  {FIXED_ARRAY_SIZE} bytes");
+    println!("Build:                 {build_time:?}");
+    println!("Iterate ({lanes} lanes): {iterate_time:?}");
+}
@@ -0,0 +1,176 @@
+use crate::traffic::{LaneBorder, LaneCrossing, LaneFlow, LaneSeparator};
+use ggez::graphics::Color;
+
+const MARKING_WIDTH: f32 = 0.15;
+const CURB_WIDTH: f32 = 0.3;
+const DOUBLE_LINE_GAP: f32 = 0.2;
+const CURB_COLOUR: Color = Color::new(200.0 / 255.0, 200.0 / 255.0, 200.0 / 255.0, 1.0);
+const MARKING_YELLOW: Color = Color::new(250.0 / 255.0, 196.0 / 255.0, 28.0 / 255.0, 1.0);
+const PARKING_DASH: LinePattern = LinePattern::Dashed {
+    len: 0.5,
+    spacing: 5.0,
+};
+const BOUNDS_WIDTH: f32 = 10.0;
+/// The city bounds are dashed like a broken lane line, scaled up to be visible from afar.
+const BOUNDS_DASH_SCALE: f32 = 15.0;
+
+/// Regional convention deciding how the separators between lanes are painted.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum MarkingStyle {
+    /// Yellow lines between opposing traffic and along the inner edge, long gaps between dashes.
+    NorthAmerican,
+    /// White lines everywhere.
+    #[default]
+    European,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LinePattern {
+    Continuous,
+    Dashed {
+        len: f32,
+        spacing: f32,
+    },
+}
+
+impl LinePattern {
+    pub fn scaled(self, factor: f32) -> Self {
+        match self {
+            LinePattern::Continuous => LinePattern::Continuous,
+            LinePattern::Dashed { len, spacing } => LinePattern::Dashed {
+                len: len * factor,
+                spacing: spacing * factor,
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LineStyle {
+    colour: Color,
+    width: f32,
+    pattern: LinePattern,
+}
+
+impl LineStyle {
+    pub fn get_colour(&self) -> Color {
+        self.colour
+    }
+
+    pub fn get_width(&self) -> f32 {
+        self.width
+    }
+
+    pub fn get_pattern(&self) -> LinePattern {
+        self.pattern
+    }
+}
+
+/// The painted form of a [`LaneSeparator`], either a single line or two parallel ones.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SeparatorMarking {
+    line: LineStyle,
+    double: bool,
+}
+
+impl SeparatorMarking {
+    pub fn get_line(&self) -> LineStyle {
+        self.line
+    }
+
+    pub fn is_double(&self) -> bool {
+        self.double
+    }
+
+    /// Distance between the centres of the two lines of a double marking.
+    pub fn get_line_distance(&self) -> f32 {
+        self.line.width + DOUBLE_LINE_GAP
+    }
+}
+
+impl MarkingStyle {
+    pub fn next(self) -> Self {
+        match self {
+            MarkingStyle::NorthAmerican => MarkingStyle::European,
+            MarkingStyle::European => MarkingStyle::NorthAmerican,
+        }
+    }
+
+    /// Dash length and spacing of broken lane lines.
+    pub fn dash(self) -> LinePattern {
+        match self {
+            MarkingStyle::NorthAmerican => LinePattern::Dashed {
+                len: 2.2,
+                spacing: 6.5,
+            },
+            MarkingStyle::European => LinePattern::Dashed {
+                len: 3.0,
+                spacing: 4.5,
+            },
+        }
+    }
+
+    /// The line around the city bounds.
+    pub fn bounds(self) -> LineStyle {
+        LineStyle {
+            colour: Color::WHITE,
+            width: BOUNDS_WIDTH,
+            pattern: self.dash().scaled(BOUNDS_DASH_SCALE),
+        }
+    }
+
+    /// Colour of the lines separating lanes of the given flow.
+    pub fn line_colour(self, flow: LaneFlow) -> Color {
+        match (self, flow) {
+            (MarkingStyle::NorthAmerican, LaneFlow::Divergent) => MARKING_YELLOW,
+            (MarkingStyle::NorthAmerican, LaneFlow::Convergent) | (MarkingStyle::European, _) => Color::WHITE,
+        }
+    }
+
+    /// Colour of the continuous line along an outer or inner edge of the carriageway.
+    pub fn border_colour(self, border: LaneBorder) -> Color {
+        match (self, border) {
+            (MarkingStyle::NorthAmerican, LaneBorder::Middle) => MARKING_YELLOW,
+            (MarkingStyle::NorthAmerican, LaneBorder::Edge) | (MarkingStyle::European, _) => Color::WHITE,
+        }
+    }
+
+    /// How the separator is painted, or [`None`] if it is not painted at all.
+    pub fn marking(self, separator: LaneSeparator) -> Option<SeparatorMarking> {
+        let (line, double) = match separator {
+            LaneSeparator::Nothing => return None,
+            LaneSeparator::Curb => (LineStyle {
+                colour: CURB_COLOUR,
+                width: CURB_WIDTH,
+                pattern: LinePattern::Continuous,
+            }, false),
+            LaneSeparator::BorderStrip(border) => (LineStyle {
+                colour: self.border_colour(border),
+                width: MARKING_WIDTH,
+                pattern: LinePattern::Continuous,
+            }, false),
+            LaneSeparator::SeparationStrip(flow, crossing) => {
+                let (pattern, double) = match crossing {
+                    LaneCrossing::SingleDashed => (self.dash(), false),
+                    LaneCrossing::SingleContinuous => (LinePattern::Continuous, false),
+                    LaneCrossing::DoubleDashed => (self.dash(), true),
+                    LaneCrossing::DoubleContinuous => (LinePattern::Continuous, true),
+                };
+                (LineStyle {
+                    colour: self.line_colour(flow),
+                    width: MARKING_WIDTH,
+                    pattern,
+                }, double)
+            }
+            LaneSeparator::ParkingStrip => (LineStyle {
+                colour: Color::WHITE,
+                width: MARKING_WIDTH,
+                pattern: PARKING_DASH,
+            }, false),
+        };
+        Some(SeparatorMarking {
+            line,
+            double,
+        })
+    }
+}
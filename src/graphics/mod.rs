@@ -0,0 +1,502 @@
+use crate::math::if_else;
+use crate::node::lane_graph::DirectedLane;
+use crate::node::{Edge, EdgeId, NodeId, NodeManager};
+use crate::sim::metrics::EdgeMetric;
+use crate::sim::pedestrian::{crossing_time, CROSSWALK_HALF_WIDTH};
+use crate::sim::parking::{all_parking_lanes, spot_count, spot_distance, ParkingSpot, SPOT_LENGTH};
+use crate::sim::signal::SignalAspect;
+use crate::sim::Simulation;
+use crate::traffic::layout::LaneLayout;
+use crate::graphics::marking::{LinePattern, LineStyle, MarkingStyle, SeparatorMarking};
+use crate::traffic::LaneType;
+use crate::{CITY_WIDTH, TPS};
+use ggez::glam::Vec2;
+use ggez::graphics::{Canvas, Color, DrawMode, DrawParam, Mesh, MeshBuilder, MeshData, Rect, Text, Vertex};
+use ggez::{Context, GameResult};
+use std::f32::consts::PI;
+use tuple_map::TupleMap2;
+
+pub mod marking;
+
+pub struct Graphics {
+    circle: Mesh,
+    bounds: Mesh,
+    vehicle: Mesh,
+    marking_style: MarkingStyle,
+    /// The metric edges are coloured by, [`None`] to show the roads as they are.
+    pub overlay: Option<EdgeMetric>,
+}
+
+impl Graphics {
+    pub fn new(ctx: &Context) -> GameResult<Graphics> {
+        Ok(Graphics {
+            circle: {
+                const CIRCLE_QUALITY: usize = 64;
+                let mut vertices = Vec::with_capacity(CIRCLE_QUALITY + 1);
+                let mut indices = Vec::with_capacity(CIRCLE_QUALITY * 3);
+                vertices.push(Vertex {
+                    position: [0.0, 0.0],
+                    uv: [0.0, 0.0],
+                    color: [1.0, 1.0, 1.0, 1.0],
+                });
+                for i in 0..CIRCLE_QUALITY {
+                    let angle = 2.0 * PI / CIRCLE_QUALITY as f32 * i as f32;
+                    vertices.push(Vertex {
+                        position: [angle.cos(), angle.sin()],
+                        uv: [0.0, 0.0],
+                        color: [1.0, 1.0, 1.0, 1.0],
+                    });
+                }
+                for i in 0..CIRCLE_QUALITY as u32 {
+                    indices.push(0);
+                    indices.push(i + 1);
+                    indices.push(i + 2);
+                }
+                indices.pop();
+                indices.push(1);
+                Mesh::from_data(ctx, MeshData {
+                    indices: &indices,
+                    vertices: &vertices,
+                })
+            },
+            bounds: make_bounds(ctx, MarkingStyle::default())?,
+            vehicle: Mesh::new_rectangle(ctx, DrawMode::fill(), Rect::new(-0.5, -0.5, 1.0, 1.0), Color::WHITE)?,
+            marking_style: MarkingStyle::default(),
+            overlay: None,
+        })
+    }
+
+    pub fn circle(&self) -> &Mesh {
+        &self.circle
+    }
+
+    pub fn bounds(&self) -> &Mesh {
+        &self.bounds
+    }
+
+    pub fn get_marking_style(&self) -> MarkingStyle {
+        self.marking_style
+    }
+
+    /// Switches the markings of the roads and the city bounds to the given style.
+    pub fn set_marking_style(&mut self, ctx: &Context, style: MarkingStyle) -> GameResult {
+        self.bounds = make_bounds(ctx, style)?;
+        self.marking_style = style;
+        Ok(())
+    }
+
+    /// A white unit square centred on the origin, scaled to the size of each vehicle.
+    pub fn vehicle(&self) -> &Mesh {
+        &self.vehicle
+    }
+
+    pub fn draw_ege(&self, canvas: &mut Canvas, ctx: &mut Context, edge: &Edge, node_manager: &NodeManager, current_path: Option<&Vec<EdgeId>>, explored_paths: &Vec<EdgeId>) -> GameResult {
+        let (a, b) = edge.get_nodes().map(|id| node_manager.get_node_pos(id).unwrap());
+        let highlight = if let Some(selected_edge) = node_manager.selected_edge && selected_edge == edge.get_id() {
+            Some(Color::GREEN)
+        } //
+        else if let Some(path) = current_path && path.contains(&edge.get_id()) {
+            Some(Color::YELLOW)
+        } //
+        else if explored_paths.contains(&edge.get_id()) {
+            Some(Color::WHITE)
+        } //
+        else {
+            None
+        };
+        let mut builder = MeshBuilder::new();
+        make_edge_mesh(&mut builder, a, b, &edge.get_lane_def().layout(node_manager.get_traffic_side()), self.marking_style, highlight)?;
+        canvas.draw(&Mesh::from_data(ctx, builder.build()), DrawParam::new());
+        Ok(())
+    }
+
+    pub fn draw_signals(&self, canvas: &mut Canvas, ctx: &mut Context, node_manager: &NodeManager, simulation: &Simulation) -> GameResult {
+        let mut builder = MeshBuilder::new();
+        make_signal_heads(&mut builder, node_manager, simulation)?;
+        let data = builder.build();
+        if !data.vertices.is_empty() {
+            canvas.draw(&Mesh::from_data(ctx, data), DrawParam::new());
+        }
+        Ok(())
+    }
+
+    /// Draws the spots of every parking lane: free spots as outlines, spots a driver is heading for in yellow and
+    /// parked cars filled.
+    pub fn draw_parking(&self, canvas: &mut Canvas, ctx: &mut Context, node_manager: &NodeManager, simulation: &Simulation) -> GameResult {
+        let mut builder = MeshBuilder::new();
+        let mut empty = true;
+        for lane in all_parking_lanes(node_manager) {
+            let (from, to) = lane.get_centre_line(node_manager);
+            let along = (to - from).normalize_or_zero();
+            let spots = simulation.get_parking_spots(lane);
+            for index in 0..spot_count(node_manager, lane) {
+                let centre = from + along * spot_distance(index);
+                let corners = |length: f32, width: f32| {
+                    let (half_length, half_width) = (along * length / 2.0, along.perp() * width / 2.0);
+                    [centre - half_length - half_width, centre + half_length - half_width, centre + half_length + half_width, centre - half_length + half_width]
+                };
+                match spots.map_or(ParkingSpot::Free, |spots| spots[index]) {
+                    ParkingSpot::Free => builder.polygon(DrawMode::stroke(SPOT_LINE_WIDTH), &corners(SPOT_LENGTH - SPOT_INSET, SPOT_WIDTH), Color::new(1.0, 1.0, 1.0, 0.35))?,
+                    ParkingSpot::Reserved(_) => builder.polygon(DrawMode::stroke(SPOT_LINE_WIDTH), &corners(SPOT_LENGTH - SPOT_INSET, SPOT_WIDTH), Color::YELLOW)?,
+                    ParkingSpot::Occupied { .. } => builder.polygon(DrawMode::fill(), &corners(PARKED_CAR_LENGTH, PARKED_CAR_WIDTH), Color::from_rgb(150, 150, 165))?,
+                };
+                empty = false;
+            }
+        }
+        if !empty {
+            canvas.draw(&Mesh::from_data(ctx, builder.build()), DrawParam::new());
+        }
+        Ok(())
+    }
+
+    /// Draws a marker at every stop of the bus lines, on the lane the stop is served from.
+    pub fn draw_bus_stops(&self, canvas: &mut Canvas, ctx: &mut Context, node_manager: &NodeManager, simulation: &Simulation) -> GameResult {
+        let mut builder = MeshBuilder::new();
+        let mut empty = true;
+        for (_, line) in simulation.get_lines() {
+            for (lane, distance) in line.get_stop_positions() {
+                if node_manager.get_edge(lane.get_edge()).is_none() {
+                    continue;
+                }
+                let (from, to) = lane.get_centre_line(node_manager);
+                let centre = from + (to - from).normalize_or_zero() * distance;
+                builder.circle(DrawMode::fill(), centre, BUS_STOP_RADIUS, 0.1, Color::from_rgb(40, 110, 230))?;
+                builder.circle(DrawMode::stroke(SPOT_LINE_WIDTH), centre, BUS_STOP_RADIUS, 0.1, Color::WHITE)?;
+                empty = false;
+            }
+        }
+        if !empty {
+            canvas.draw(&Mesh::from_data(ctx, builder.build()), DrawParam::new());
+        }
+        Ok(())
+    }
+
+    /// Draws every crosswalk as zebra stripes. Crosswalks at signals get a walk or don't walk light at both ends.
+    pub fn draw_crosswalks(&self, canvas: &mut Canvas, ctx: &mut Context, node_manager: &NodeManager, simulation: &Simulation) -> GameResult {
+        let mut builder = MeshBuilder::new();
+        let mut empty = true;
+        for (crosswalk, a, b) in node_manager.get_sidewalk_graph().get_crosswalks() {
+            let across = (*b - *a).normalize_or_zero();
+            let along = across.perp() * CROSSWALK_HALF_WIDTH;
+            let length = a.distance(*b);
+            for stripe in 0..(length / CROSSWALK_STRIPE_SPACING) as u32 {
+                let centre = *a + across * (stripe as f32 + 0.5) * CROSSWALK_STRIPE_SPACING;
+                builder.line(&[centre - along, centre + along], CROSSWALK_STRIPE_WIDTH, Color::new(1.0, 1.0, 1.0, 0.7))?;
+            }
+            if let Some(signal) = simulation.get_signal(crosswalk.get_node()) {
+                let colour = if_else!(signal.permits_crossing(crosswalk.get_edge(), crossing_time(length)) => Color::GREEN ; Color::RED);
+                for end in [*a, *b] {
+                    builder.circle(DrawMode::fill(), end, WALK_LIGHT_RADIUS, 0.1, colour)?;
+                }
+            }
+            empty = false;
+        }
+        if !empty {
+            canvas.draw(&Mesh::from_data(ctx, builder.build()), DrawParam::new());
+        }
+        Ok(())
+    }
+
+    /// Colours every edge vehicles used recently by the overlay metric. Edges without a value are left as they are.
+    pub fn draw_overlay(&self, canvas: &mut Canvas, ctx: &mut Context, node_manager: &NodeManager, simulation: &Simulation) -> GameResult {
+        let Some(metric) = self.overlay else {
+            return Ok(());
+        };
+        let mut builder = MeshBuilder::new();
+        let mut empty = true;
+        for (edge, metrics) in simulation.get_metrics().get_edges(node_manager) {
+            let Some(value) = metric.value(&metrics, TPS as f32) else {
+                continue;
+            };
+            let edge = node_manager.get_edge(edge).unwrap();
+            let (a, b) = edge.get_nodes().map(|node| node_manager.get_node_pos(node).unwrap());
+            let (low, high) = metric.get_scale();
+            builder.line(&[a, b], edge.get_lane_def().layout(node_manager.get_traffic_side()).get_width(), heat_colour((value - low) / (high - low), OVERLAY_ALPHA))?;
+            empty = false;
+        }
+        if !empty {
+            canvas.draw(&Mesh::from_data(ctx, builder.build()), DrawParam::new());
+        }
+        Ok(())
+    }
+
+    /// Draws the colour scale of the overlay metric with its name and the values at both ends, in screen space.
+    pub fn draw_overlay_legend(&self, canvas: &mut Canvas, ctx: &mut Context, pos: Vec2) -> GameResult {
+        let Some(metric) = self.overlay else {
+            return Ok(());
+        };
+        let mut builder = MeshBuilder::new();
+        let step = LEGEND_WIDTH / LEGEND_STEPS as f32;
+        for i in 0..LEGEND_STEPS {
+            let rect = Rect::new(pos.x + i as f32 * step, pos.y + LEGEND_TEXT_HEIGHT, step, LEGEND_HEIGHT);
+            builder.rectangle(DrawMode::fill(), rect, heat_colour(i as f32 / (LEGEND_STEPS - 1) as f32, 1.0))?;
+        }
+        canvas.draw(&Mesh::from_data(ctx, builder.build()), DrawParam::new());
+        let (low, high) = metric.get_scale();
+        let below = pos.y + LEGEND_TEXT_HEIGHT + LEGEND_HEIGHT + 2.0;
+        canvas.draw(&Text::new(format!("{metric:?} ({})", metric.get_unit())), DrawParam::new().dest(pos).color(Color::WHITE));
+        canvas.draw(&Text::new(format!("{low:.0}")), DrawParam::new().dest(Vec2::new(pos.x, below)).color(Color::WHITE));
+        let high = Text::new(format!("{high:.0}"));
+        let high_width = high.measure(ctx)?.x;
+        canvas.draw(&high, DrawParam::new().dest(Vec2::new(pos.x + LEGEND_WIDTH - high_width, below)).color(Color::WHITE));
+        Ok(())
+    }
+
+    /// Draws the lane connections of a junction, highlighting the lanes selected for editing.
+    pub fn draw_junction(&self, canvas: &mut Canvas, ctx: &mut Context, node: NodeId, node_manager: &NodeManager) -> GameResult {
+        let lane_graph = node_manager.get_lane_graph();
+        let mut builder = MeshBuilder::new();
+        let mut empty = true;
+        for incoming in lane_graph.get_arrivals(node) {
+            let selected = node_manager.selected_incoming == Some(*incoming);
+            let colour = if_else!(selected => Color::YELLOW ; Color::new(1.0, 1.0, 1.0, 0.4));
+            let from = lane_point(node_manager, *incoming, node);
+            for outgoing in lane_graph.get_successors(*incoming) {
+                builder.line(&[from, lane_point(node_manager, *outgoing, node)], CONNECTION_WIDTH, colour)?;
+                empty = false;
+            }
+            if selected {
+                builder.circle(DrawMode::fill(), from, LANE_MARKER_RADIUS, 0.1, Color::YELLOW)?;
+                empty = false;
+            }
+        }
+        if let Some(outgoing) = node_manager.selected_outgoing && lane_graph.get_departures(node).contains(&outgoing) {
+            builder.circle(DrawMode::fill(), lane_point(node_manager, outgoing, node), LANE_MARKER_RADIUS, 0.1, Color::CYAN)?;
+            empty = false;
+        }
+        if !empty {
+            canvas.draw(&Mesh::from_data(ctx, builder.build()), DrawParam::new());
+        }
+        Ok(())
+    }
+}
+
+/// Draws a signal head for every signalled lane, at the end of the lane.
+pub fn make_signal_heads(builder: &mut MeshBuilder, node_manager: &NodeManager, simulation: &Simulation) -> GameResult {
+    for (node, signal) in simulation.get_signals() {
+        for lane in node_manager.get_lane_graph().get_arrivals(node) {
+            let Some(aspect) = signal.get_aspect(*lane) else {
+                continue;
+            };
+            let colour = match aspect {
+                SignalAspect::Green => Color::GREEN,
+                SignalAspect::Amber => Color::from_rgb(255, 176, 0),
+                SignalAspect::Red => Color::RED,
+            };
+            let pos = lane_point(node_manager, *lane, node);
+            builder.circle(DrawMode::fill(), pos, SIGNAL_HEAD_RADIUS + 0.3, 0.1, Color::BLACK)?;
+            builder.circle(DrawMode::fill(), pos, SIGNAL_HEAD_RADIUS, 0.1, colour)?;
+        }
+    }
+    Ok(())
+}
+
+/// The centre of a lane where it meets the junction at `node`, set back from the node centre.
+fn lane_point(node_manager: &NodeManager, lane: DirectedLane, node: NodeId) -> Vec2 {
+    let edge = node_manager.get_edge(lane.get_edge()).unwrap();
+    let (a, b) = edge.get_nodes().map(|id| node_manager.get_node_pos(id).unwrap());
+    let offset = edge.get_lane_def().layout(node_manager.get_traffic_side()).get(lane.get_lane().get_index()).map_or(0.0, |slot| slot.get_offset());
+    let pos = node_manager.get_node_pos(node).unwrap();
+    let towards_edge = if_else!(pos == a => b - a ; a - b).normalize();
+    pos + towards_edge * CONNECTION_SETBACK + offset * (b - a).normalize().perp()
+}
+
+const HIGHLIGHT_ALPHA: f32 = 0.5;
+/// How far from the node centre the connections of a junction start and end.
+const CONNECTION_SETBACK: f32 = 12.0;
+const CONNECTION_WIDTH: f32 = 0.4;
+const LANE_MARKER_RADIUS: f32 = 0.8;
+const SIGNAL_HEAD_RADIUS: f32 = 0.9;
+const SPOT_INSET: f32 = 0.6;
+const SPOT_WIDTH: f32 = 2.0;
+const SPOT_LINE_WIDTH: f32 = 0.15;
+const PARKED_CAR_LENGTH: f32 = 4.5;
+const PARKED_CAR_WIDTH: f32 = 1.8;
+const BUS_STOP_RADIUS: f32 = 1.6;
+const CROSSWALK_STRIPE_SPACING: f32 = 1.0;
+const CROSSWALK_STRIPE_WIDTH: f32 = 0.5;
+const WALK_LIGHT_RADIUS: f32 = 0.5;
+const OVERLAY_ALPHA: f32 = 0.75;
+pub const LEGEND_WIDTH: f32 = 200.0;
+const LEGEND_HEIGHT: f32 = 12.0;
+const LEGEND_TEXT_HEIGHT: f32 = 18.0;
+const LEGEND_STEPS: u32 = 20;
+
+/// Adds the lane surfaces and markings of an edge going from `a` to `b` to the builder, without touching the GPU.
+pub fn make_edge_mesh(builder: &mut MeshBuilder, a: Vec2, b: Vec2, layout: &LaneLayout, style: MarkingStyle, highlight: Option<Color>) -> GameResult {
+    let perp = (b - a).normalize().perp();
+    for lane in layout.lanes() {
+        let (centre, half_width) = (lane.get_offset() * perp, lane.get_width() / 2.0 * perp);
+        let (left, right) = (centre + half_width, centre - half_width);
+        builder.polygon(DrawMode::fill(), &[a + left, a + right, b + right, b + left], lane_colour(lane.get_type()))?;
+    }
+    for (offset, separator) in layout.separators() {
+        if let Some(marking) = style.marking(separator) {
+            make_separator(builder, a + offset * perp, b + offset * perp, perp, marking)?;
+        }
+    }
+    if let Some(mut colour) = highlight {
+        colour.a = HIGHLIGHT_ALPHA;
+        let half_width = layout.get_width() / 2.0 * perp;
+        builder.polygon(DrawMode::fill(), &[a + half_width, a - half_width, b - half_width, b + half_width], colour)?;
+    }
+    Ok(())
+}
+
+fn make_separator(builder: &mut MeshBuilder, from: Vec2, to: Vec2, perp: Vec2, marking: SeparatorMarking) -> GameResult {
+    if marking.is_double() {
+        let gap = marking.get_line_distance() / 2.0 * perp;
+        make_marking_line(builder, from + gap, to + gap, marking.get_line())?;
+        make_marking_line(builder, from - gap, to - gap, marking.get_line())
+    } //
+    else {
+        make_marking_line(builder, from, to, marking.get_line())
+    }
+}
+
+fn make_marking_line(builder: &mut MeshBuilder, from: Vec2, to: Vec2, line: LineStyle) -> GameResult {
+    match line.get_pattern() {
+        LinePattern::Continuous => {
+            builder.line(&[from, to], line.get_width(), line.get_colour())?;
+            Ok(())
+        }
+        LinePattern::Dashed { .. } => make_dashed_line(builder, from, to, line, false, false),
+    }
+}
+
+fn make_bounds(ctx: &Context, style: MarkingStyle) -> GameResult<Mesh> {
+    let mut builder = MeshBuilder::new();
+    let line = style.bounds();
+    let (low, high) = (-CITY_WIDTH / 2.0, CITY_WIDTH / 2.0);
+    make_dashed_line(&mut builder, Vec2::new(low, high), Vec2::new(high, high), line, true, true)?;
+    make_dashed_line(&mut builder, Vec2::new(low, low), Vec2::new(high, low), line, true, true)?;
+    make_dashed_line(&mut builder, Vec2::new(high, low), Vec2::new(high, high), line, true, true)?;
+    make_dashed_line(&mut builder, Vec2::new(low, low), Vec2::new(low, high), line, true, true)?;
+    Ok(Mesh::from_data(ctx, builder.build()))
+}
+
+/// Green for 0, through yellow, to red for 1 and above.
+fn heat_colour(t: f32, alpha: f32) -> Color {
+    let t = t.clamp(0.0, 1.0);
+    Color::new((2.0 * t).min(1.0), (2.0 - 2.0 * t).min(1.0), 0.0, alpha)
+}
+
+fn lane_colour(lane: LaneType) -> Color {
+    match lane {
+        LaneType::Grass => Color::from_rgb(62, 122, 52),
+        LaneType::Sidewalk => Color::from_rgb(168, 168, 160),
+        LaneType::NormalForward | LaneType::NormalReverse => Color::from_rgb(64, 64, 64),
+        LaneType::DirtForward | LaneType::DirtReverse => Color::from_rgb(122, 94, 62),
+        LaneType::BusForward | LaneType::BusReverse => Color::from_rgb(150, 42, 42),
+        LaneType::ParkingForward | LaneType::ParkingReverse => Color::from_rgb(84, 84, 92),
+        LaneType::ShoulderForward | LaneType::ShoulderReverse => Color::from_rgb(78, 78, 78),
+        LaneType::BikeForward | LaneType::BikeReverse => Color::from_rgb(38, 140, 104),
+        LaneType::TramForward | LaneType::TramReverse => Color::from_rgb(92, 84, 76),
+        LaneType::Median => Color::from_rgb(74, 108, 56),
+        LaneType::TurnLane => Color::from_rgb(64, 64, 64),
+    }
+}
+
+/// Draws the line dashed by the length and spacing of its pattern, or continuous if it has no dashes.
+fn make_dashed_line(builder: &mut MeshBuilder, from: Vec2, to: Vec2, line: LineStyle, butt: bool, greedy: bool) -> GameResult {
+    let (width, colour) = (line.get_width(), line.get_colour());
+    let LinePattern::Dashed { len: dash_len, spacing } = line.get_pattern() else {
+        builder.line(&[from, to], width, colour)?;
+        return Ok(());
+    };
+    let mut len = (to - from).length();
+    if butt {
+        len += width / 2.0;
+    }
+    let inner_len = len - 2.0 * dash_len;
+    if inner_len <= 0.0 {
+        //No space for dashing, just draw a line
+        builder.line(&[from, to], width, colour)?;
+    } //
+    else if inner_len <= spacing {
+        //Just enough space for two dashes and a little space in between
+        builder.line(&[from, from + (to - from).normalize() * dash_len], width, colour)?;
+        builder.line(&[to - (to - from).normalize() * dash_len, to], width, colour)?;
+    } //
+    else {
+        //We have enough space
+        let rem_len = len - dash_len;
+        let mut times = rem_len / (dash_len + spacing);
+        if greedy {
+            times = times.ceil();
+        } //
+        else {
+            times = times.floor();
+        }
+        let actual_spacing = (rem_len - times * dash_len) / times;
+        let dir = (to - from).normalize();
+        builder.line(&[from, from + dir * dash_len], width, colour)?;
+        for i in 1..=times as i32 {
+            builder.line(&[from + dir * (i as f32 * (dash_len + actual_spacing)), from + dir * (i as f32 * (dash_len + actual_spacing) + dash_len)], width, colour)?;
+        }
+    }
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traffic::side::TrafficSide;
+    use crate::traffic::{LaneDefinition, LaneFlow, LaneSeparator};
+    use crate::traffic::LaneType::{NormalForward, NormalReverse, Sidewalk};
+    use ggez::graphics::LinearColor;
+
+    /// The mesh of a 100 long edge along the x axis, so the lateral offset of every vertex is its y coordinate.
+    fn edge_mesh(lanes: &[LaneType], style: MarkingStyle) -> MeshBuilder {
+        let layout = LaneDefinition::from_lanes(lanes).unwrap().layout(TrafficSide::Right);
+        let mut builder = MeshBuilder::new();
+        make_edge_mesh(&mut builder, Vec2::ZERO, Vec2::new(100.0, 0.0), &layout, style, None).unwrap();
+        builder
+    }
+
+    /// The triangles drawn in the colour, as the lateral offsets of their vertices.
+    fn triangles(builder: &MeshBuilder, colour: Color) -> Vec<[f32; 3]> {
+        let colour: [f32; 4] = LinearColor::from(colour).into();
+        let data = builder.build();
+        data.indices.chunks(3)
+            .map(|triangle| triangle.iter().map(|index| data.vertices[*index as usize]).collect::<Vec<_>>())
+            .filter(|vertices| vertices[0].color == colour)
+            .map(|vertices| [0, 1, 2].map(|i| vertices[i].position[1]))
+            .collect()
+    }
+
+    /// The distinct lateral offsets of the vertices drawn in the colour, rounded to a thousandth, in ascending order.
+    fn offsets(builder: &MeshBuilder, colour: Color) -> Vec<f32> {
+        let mut offsets = triangles(builder, colour).into_iter().flatten().map(|offset| (offset * 1000.0).round() / 1000.0).collect::<Vec<_>>();
+        offsets.sort_by(f32::total_cmp);
+        offsets.dedup();
+        offsets
+    }
+
+    #[test]
+    fn lane_surfaces_cover_their_slots() {
+        let builder = edge_mesh(&[Sidewalk, NormalReverse, NormalForward, Sidewalk], MarkingStyle::European);
+        //Every lane is a quad of two triangles
+        assert_eq!(triangles(&builder, lane_colour(Sidewalk)).len(), 4);
+        assert_eq!(triangles(&builder, lane_colour(NormalForward)).len(), 4);
+        assert_eq!(offsets(&builder, lane_colour(Sidewalk)), [-3.75, -2.5, 2.5, 3.75]);
+        assert_eq!(offsets(&builder, lane_colour(NormalForward)), [-2.5, 0.0, 2.5]);
+    }
+
+    #[test]
+    fn separators_lie_on_lane_boundaries() {
+        let builder = edge_mesh(&[Sidewalk, NormalReverse, NormalForward, Sidewalk], MarkingStyle::European);
+        //The border strip at 2.5 and the double line around 0, 0.35 apart, each 0.15 wide
+        assert_eq!(offsets(&builder, Color::WHITE), [-0.25, -0.1, 0.1, 0.25, 2.425, 2.575]);
+        //The curb between the carriageway and the right sidewalk, 0.3 wide
+        let curb = MarkingStyle::European.marking(LaneSeparator::Curb).unwrap().get_line().get_colour();
+        assert_eq!(offsets(&builder, curb), [-2.65, -2.35]);
+    }
+
+    #[test]
+    fn marking_colours_follow_the_style() {
+        let builder = edge_mesh(&[Sidewalk, NormalReverse, NormalForward, Sidewalk], MarkingStyle::NorthAmerican);
+        let yellow = MarkingStyle::NorthAmerican.line_colour(LaneFlow::Divergent);
+        assert_eq!(offsets(&builder, yellow), [-0.25, -0.1, 0.1, 0.25]);
+        assert_eq!(offsets(&builder, Color::WHITE), [2.425, 2.575]);
+    }
+}
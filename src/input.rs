@@ -1,13 +1,29 @@
 use crate::camera::Camera;
-use crate::input::BindingType::{Backward, Forward, Left, Pathfind, PlaceNode, Right, RotateLeft, RotateRight, SelectEdge, SelectNode, SetEnd, SetStart};
-use crate::node::{EdgeId, NodeManager};
+use crate::input::BindingType::{Backward, BuildRoad, CycleProfile, Forward, Left, NextPreset, Pathfind, PlaceNode, Right, RotateLeft, RotateRight, SelectEdge, SelectNode, SetEnd, SetStart, ToggleTrafficSide, CycleMarkingStyle, CycleIncoming, CycleOutgoing, ToggleConnection, ResetConnections, SpawnVehicle, ToggleSignal, CycleSignalMode, CycleJunctionControl, OptimiseSignal, CoordinateSignals, ToggleDemand, AddBusLine, CycleOverlay, ToggleDynamicRouting, RunAssignment};
+use crate::math::if_else;
+use crate::node::lane_graph::DirectedLane;
+use crate::node::junction::JunctionControl;
+use crate::node::{EdgeId, NodeId, NodeManager};
+use crate::sim::signal::{SignalController, SignalMode, SignalPlan};
+use crate::sim::timing::{TimingError, TimingReport};
+use crate::sim::transit::{BusStop, LineError, Schedule};
+use crate::sim::{EdgeInUse, Simulation};
+use crate::traffic::preset::{RoadPreset, RoadPresetLibrary};
+use crate::traffic::{LaneDirection, LaneError, LaneType, RoutingProfile};
 use enum_map::{Enum, EnumMap};
 use ggez::glam::{Vec2, Vec4};
 use ggez::input::keyboard::KeyCode;
-use ggez::input::keyboard::KeyCode::{KeyA, KeyD, KeyE, KeyQ, KeyS, KeyW, KeyX, KeyZ};
+use ggez::input::keyboard::KeyCode::{KeyA, KeyB, KeyC, KeyD, KeyE, KeyF, KeyG, KeyH, KeyI, KeyJ, KeyK, KeyL, KeyM, KeyN, KeyO, KeyP, KeyQ, KeyR, KeyS, KeyT, KeyU, KeyV, KeyW, KeyX, KeyY, KeyZ, Tab};
 use ggez::input::mouse::MouseButton;
 use ggez::winit::keyboard::PhysicalKey;
 use rustc_hash::FxHashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// Ticks between the buses of lines added along the current path, one minute.
+const BUS_HEADWAY: u32 = 1200;
+/// Ticks the buses of lines added along the current path stand at every stop.
+const BUS_DWELL_TIME: u32 = 200;
 
 pub struct KeyBinding {
     click_count: u16,
@@ -75,6 +91,35 @@ pub enum BindingType {
     SelectEdge,
     SetStart,
     SetEnd,
+    CycleProfile,
+    BuildRoad,
+    NextPreset,
+    ToggleTrafficSide,
+    CycleMarkingStyle,
+    CycleIncoming,
+    CycleOutgoing,
+    ToggleConnection,
+    ResetConnections,
+    SpawnVehicle,
+    ToggleSignal,
+    CycleSignalMode,
+    CycleJunctionControl,
+    OptimiseSignal,
+    CoordinateSignals,
+    ToggleDemand,
+    AddBusLine,
+    CycleOverlay,
+    ToggleDynamicRouting,
+    RunAssignment,
+}
+
+/// The state of the game the input acts on.
+pub struct World<'a> {
+    pub node_manager: &'a mut NodeManager,
+    pub road_presets: &'a RoadPresetLibrary,
+    pub simulation: &'a mut Simulation,
+    pub current_path: &'a mut Option<Vec<EdgeId>>,
+    pub explored_paths: &'a mut Vec<EdgeId>,
 }
 
 pub struct Input {
@@ -82,16 +127,24 @@ pub struct Input {
     bindings: EnumMap<BindingType, KeyBinding>,
     pub scroll: Vec2,
     mouse_pos: Vec2,
+    selected_preset: usize,
+    /// Outcome of the last signal retiming or coordination.
+    pub timing_report: Option<Result<TimingReport, TimingError>>,
+    /// Why the lanes of the last edge built over could not be replaced.
+    pub lane_error: Option<RestripeError>,
+    /// Why the last bus line could not be added.
+    pub line_error: Option<LineError>,
 }
 
 impl Input {
-    pub fn tick(&mut self, window_size: Vec2, camera: &Camera, node_manager: &mut NodeManager, current_path: &mut Option<Vec<EdgeId>>, explored_paths: &mut Vec<EdgeId>) {
+    pub fn tick(&mut self, window_size: Vec2, camera: &Camera, world: World) {
+        let World { node_manager, road_presets, simulation, current_path, explored_paths } = world;
         while self.get_mut(PlaceNode).consume_click() {
             node_manager.add_node(self.get_world_pos_from_screen_pos(window_size, &camera));
         }
         if self.get_mut(Pathfind).consume_all_clicks() {
             if let Some(start) = node_manager.start_node && let Some(end) = node_manager.end_node {
-                let (path, explored) = node_manager.a_star(start, end, |a, b| a.distance(b) / 2.0);
+                let (path, explored) = node_manager.a_star(start, end, node_manager.routing_profile, |a, b| node_manager.min_travel_time(a, b));
                 *current_path = path;
                 *explored_paths = explored;
             }
@@ -99,6 +152,8 @@ impl Input {
         if self.get_mut(SelectNode).consume_all_clicks() {
             if let Some(id) = node_manager.try_node_collision(self.get_world_pos_from_screen_pos(window_size, &camera)) {
                 node_manager.selected_node = Some(id);
+                node_manager.selected_incoming = None;
+                node_manager.selected_outgoing = None;
             }
         }
         if self.get_mut(SelectEdge).consume_all_clicks() {
@@ -116,6 +171,112 @@ impl Input {
                 node_manager.end_node = Some(selected);
             }
         }
+        while self.get_mut(CycleProfile).consume_click() {
+            node_manager.routing_profile = node_manager.routing_profile.next();
+        }
+        if self.get_mut(ToggleTrafficSide).consume_all_clicks() {
+            node_manager.set_traffic_side(node_manager.get_traffic_side().other());
+        }
+        while self.get_mut(NextPreset).consume_click() {
+            self.selected_preset = (self.selected_preset + 1) % road_presets.len().max(1);
+        }
+        if self.get_mut(BuildRoad).consume_all_clicks()
+            && let Some(from) = node_manager.selected_node
+            && let Some(preset) = road_presets.get(self.selected_preset)
+            && let Some(to) = node_manager.try_node_collision(self.get_world_pos_from_screen_pos(window_size, camera))
+            && to != from {
+            match node_manager.get_node(from).unwrap().find_edge(to, node_manager) {
+                Some(edge) => self.lane_error = restripe(node_manager, simulation, edge, from, preset).err(),
+                None => {
+                    node_manager.make_edge(from, to, preset.get_speed(), preset.get_lane_def().clone());
+                    node_manager.selected_node = Some(to);
+                }
+            }
+        }
+        while self.get_mut(CycleIncoming).consume_click() {
+            if let Some(node) = node_manager.selected_node {
+                node_manager.selected_incoming = next_lane(node_manager.get_lane_graph().get_arrivals(node), node_manager.selected_incoming);
+                node_manager.selected_outgoing = None;
+            }
+        }
+        while self.get_mut(CycleOutgoing).consume_click() {
+            if let Some(node) = node_manager.selected_node && let Some(incoming) = node_manager.selected_incoming {
+                let departures = node_manager.get_lane_graph().get_departures(node).iter().copied().filter(|lane| lane.get_edge() != incoming.get_edge()).collect::<Vec<_>>();
+                node_manager.selected_outgoing = next_lane(&departures, node_manager.selected_outgoing);
+            }
+        }
+        if self.get_mut(ToggleConnection).consume_all_clicks()
+            && let Some(incoming) = node_manager.selected_incoming
+            && let Some(outgoing) = node_manager.selected_outgoing
+            && node_manager.toggle_connection(incoming, outgoing).is_err() {
+            //The lanes changed since they were selected
+            node_manager.selected_incoming = None;
+            node_manager.selected_outgoing = None;
+        }
+        while self.get_mut(SpawnVehicle).consume_click() {
+            if let Some(start) = node_manager.start_node && let Some(end) = node_manager.end_node {
+                if node_manager.routing_profile == RoutingProfile::Pedestrian {
+                    simulation.spawn_pedestrian(node_manager, start, end);
+                } //
+                else {
+                    simulation.spawn(node_manager, start, end, node_manager.routing_profile);
+                }
+            }
+        }
+        if self.get_mut(ToggleDynamicRouting).consume_all_clicks() {
+            simulation.set_dynamic_routing(!simulation.is_dynamic_routing());
+        }
+        if self.get_mut(ToggleSignal).consume_all_clicks()
+            && let Some(node) = node_manager.selected_node
+            && simulation.detach_signal(node).is_none()
+            && let Ok(plan) = SignalPlan::default_for(node_manager, node) {
+            simulation.attach_signal(node, SignalController::new(plan, SignalMode::FixedTime));
+        }
+        if self.get_mut(CycleSignalMode).consume_all_clicks()
+            && let Some(node) = node_manager.selected_node
+            && let Some(signal) = simulation.get_signal_mut(node) {
+            signal.set_mode(signal.get_mode().next());
+        }
+        if self.get_mut(OptimiseSignal).consume_all_clicks() && let Some(node) = node_manager.selected_node {
+            self.timing_report = Some(simulation.optimise_signal(node));
+        }
+        if self.get_mut(CoordinateSignals).consume_all_clicks() && let Some(start) = node_manager.start_node && let Some(path) = current_path {
+            //Paths are stored from the goal back to the start
+            let path = path.iter().rev().copied().collect::<Vec<_>>();
+            self.timing_report = Some(simulation.coordinate_signals(node_manager, start, &path));
+        }
+        if self.get_mut(AddBusLine).consume_all_clicks() && let Some(start) = node_manager.start_node && let Some(path) = current_path {
+            //A stop halfway along every edge of the path, which is stored from the goal back to the start
+            let mut node = start;
+            let stops = path.iter().rev().filter_map(|edge_id| {
+                let edge = node_manager.get_edge(*edge_id)?;
+                let direction = if_else!(edge.get_nodes().0 == node => LaneDirection::Forward ; LaneDirection::Reverse);
+                node = edge.get_other_node(node);
+                Some(BusStop {
+                    edge: *edge_id,
+                    direction,
+                    position: 0.5,
+                })
+            }).collect::<Vec<_>>();
+            let name = (simulation.get_lines().count() + 1).to_string();
+            let schedule = Schedule::Headway {
+                first: simulation.get_ticks(),
+                headway: BUS_HEADWAY,
+            };
+            self.line_error = simulation.add_line(node_manager, name, &stops, schedule, BUS_DWELL_TIME).err();
+        }
+        if self.get_mut(CycleJunctionControl).consume_all_clicks() && let Some(node) = node_manager.selected_node {
+            //Goes through every control once, then back to following the road hierarchy
+            let control = match node_manager.get_node(node).and_then(|node| node.get_control_override()) {
+                None => Some(JunctionControl::Uncontrolled),
+                Some(JunctionControl::AllWayStop) => None,
+                Some(control) => Some(control.next()),
+            };
+            node_manager.set_junction_control(node, control);
+        }
+        if self.get_mut(ResetConnections).consume_all_clicks() && let Some(node) = node_manager.selected_node {
+            node_manager.reset_connections(node);
+        }
     }
 
     pub fn handle_mouse_pos(&mut self, x: f32, y: f32) {
@@ -132,6 +293,10 @@ impl Input {
             bindings: EnumMap::from_fn(|_| KeyBinding::new()),
             scroll: Vec2::ZERO,
             mouse_pos: Vec2::ZERO,
+            selected_preset: 0,
+            timing_report: None,
+            lane_error: None,
+            line_error: None,
         };
         input.bind(keyboard(KeyQ), RotateLeft);
         input.bind(keyboard(KeyE), RotateRight);
@@ -145,6 +310,26 @@ impl Input {
         input.bind(mouse(MouseButton::Middle), SelectEdge);
         input.bind(keyboard(KeyZ), SetStart);
         input.bind(keyboard(KeyX), SetEnd);
+        input.bind(keyboard(KeyP), CycleProfile);
+        input.bind(keyboard(KeyR), BuildRoad);
+        input.bind(keyboard(Tab), NextPreset);
+        input.bind(keyboard(KeyT), ToggleTrafficSide);
+        input.bind(keyboard(KeyM), CycleMarkingStyle);
+        input.bind(keyboard(KeyC), CycleIncoming);
+        input.bind(keyboard(KeyV), CycleOutgoing);
+        input.bind(keyboard(KeyB), ToggleConnection);
+        input.bind(keyboard(KeyN), ResetConnections);
+        input.bind(keyboard(KeyG), SpawnVehicle);
+        input.bind(keyboard(KeyL), ToggleSignal);
+        input.bind(keyboard(KeyK), CycleSignalMode);
+        input.bind(keyboard(KeyJ), CycleJunctionControl);
+        input.bind(keyboard(KeyO), OptimiseSignal);
+        input.bind(keyboard(KeyU), CoordinateSignals);
+        input.bind(keyboard(KeyH), ToggleDemand);
+        input.bind(keyboard(KeyY), AddBusLine);
+        input.bind(keyboard(KeyF), CycleOverlay);
+        input.bind(keyboard(KeyI), ToggleDynamicRouting);
+        input.bind(keyboard(KeyCode::F1), RunAssignment);
         input
     }
 
@@ -156,6 +341,10 @@ impl Input {
         &mut self.bindings[binding]
     }
 
+    pub fn get_selected_preset(&self) -> usize {
+        self.selected_preset
+    }
+
     fn bind(&mut self, bind: PhysicalBinding, binding: BindingType) {
         self.bindings_by_key.entry(bind).or_insert_with(|| Vec::new()).push(binding);
     }
@@ -195,6 +384,66 @@ impl Input {
     }
 }
 
+/// Replaces the lanes of the edge with the lanes of the preset as seen when driving away from `from`, keeping the speed
+/// of the edge. Fails if the preset is not as wide as the edge, or if the simulation still refers to its lanes.
+fn restripe(node_manager: &mut NodeManager, simulation: &mut Simulation, edge: EdgeId, from: NodeId, preset: &RoadPreset) -> Result<(), RestripeError> {
+    simulation.check_edge_unused(edge)?;
+    let mut lanes = preset.get_lane_def().lanes().collect::<Vec<_>>();
+    if node_manager.get_edge(edge).unwrap().get_nodes().0 != from {
+        lanes = lanes.into_iter().rev().map(LaneType::opposite).collect();
+    }
+    let mut lane_def = node_manager.lane_def_mut(edge).unwrap();
+    while lane_def.lanes().len() > lanes.len() {
+        lane_def.remove(lane_def.lanes().len() - 1)?;
+    }
+    for (index, lane) in lanes.into_iter().enumerate() {
+        if index < lane_def.lanes().len() {
+            lane_def.set(index, lane)?;
+        } //
+        else {
+            lane_def.insert(index, lane)?;
+        }
+    }
+    lane_def.commit()?;
+    simulation.forget_lanes(edge);
+    Ok(())
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum RestripeError {
+    InvalidLanes(LaneError),
+    InUse(EdgeInUse),
+}
+
+impl From<LaneError> for RestripeError {
+    fn from(error: LaneError) -> Self {
+        RestripeError::InvalidLanes(error)
+    }
+}
+
+impl From<EdgeInUse> for RestripeError {
+    fn from(error: EdgeInUse) -> Self {
+        RestripeError::InUse(error)
+    }
+}
+
+impl Display for RestripeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestripeError::InvalidLanes(error) => write!(f, "{error}"),
+            RestripeError::InUse(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl Error for RestripeError {}
+
+/// The lane after `current` in `lanes`, wrapping around, or the first lane if `current` is not one of them.
+fn next_lane(lanes: &[DirectedLane], current: Option<DirectedLane>) -> Option<DirectedLane> {
+    let index = current.and_then(|current| lanes.iter().position(|lane| *lane == current)).map_or(0, |index| (index + 1) % lanes.len());
+    lanes.get(index).copied()
+}
+
 #[inline]
 fn keyboard(key: KeyCode) -> PhysicalBinding {
     PhysicalBinding::Keyboard(PhysicalKey::Code(key))
@@ -203,4 +452,35 @@ fn keyboard(key: KeyCode) -> PhysicalBinding {
 #[inline]
 fn mouse(button: MouseButton) -> PhysicalBinding {
     PhysicalBinding::Mouse(button)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traffic::LaneTypeManager;
+    use crate::traffic::LaneType::{NormalForward, NormalReverse, Sidewalk};
+
+    #[test]
+    fn restriping_an_occupied_edge_is_refused() {
+        let lane_types = LaneTypeManager::new();
+        let mut presets = RoadPresetLibrary::new(&lane_types);
+        presets.load("[one_way]\nname = One-way\nspeed = 1.0\nlanes = Sidewalk|NormalForward|NormalForward|Sidewalk", &lane_types).unwrap();
+        let preset = presets.get(presets.len() - 1).unwrap();
+        let mut node_manager = NodeManager::new();
+        let a = node_manager.add_node(Vec2::new(0.0, 5000.0));
+        let b = node_manager.add_node(Vec2::new(100.0, 5000.0));
+        let edge = node_manager.make_edge(a, b, preset.get_speed(), preset.get_lane_def().clone());
+        let mut simulation = Simulation::new();
+        simulation.spawn(&node_manager, a, b, RoutingProfile::Car).unwrap();
+        assert_eq!(restripe(&mut node_manager, &mut simulation, edge, b, preset), Err(RestripeError::InUse(EdgeInUse::Traffic)));
+        assert_eq!(node_manager.get_edge(edge).unwrap().get_lane_def().lanes().collect::<Vec<_>>(), [Sidewalk, NormalForward, NormalForward, Sidewalk]);
+        for _ in 0..10000 {
+            if simulation.vehicle_count() == 0 {
+                break;
+            }
+            simulation.tick(&node_manager);
+        }
+        assert_eq!(simulation.vehicle_count(), 0);
+        assert_eq!(restripe(&mut node_manager, &mut simulation, edge, b, preset), Ok(()));
+        assert_eq!(node_manager.get_edge(edge).unwrap().get_lane_def().lanes().collect::<Vec<_>>(), [Sidewalk, NormalReverse, NormalReverse, Sidewalk]);
+    }
+}
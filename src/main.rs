@@ -3,14 +3,25 @@ mod camera;
 mod input;
 mod graphics;
 mod node;
+mod sim;
 mod float;
 mod math;
 mod traffic;
 
 use crate::camera::Camera;
-use crate::graphics::Graphics;
-use crate::input::Input;
+use crate::graphics::{Graphics, LEGEND_WIDTH};
+use crate::input::{BindingType, Input, World};
+use crate::math::if_else;
 use crate::node::{Edge, EdgeId, Node, NodeManager};
+use crate::sim::vehicle::Vehicle;
+use crate::sim::metrics::EdgeMetric;
+use crate::sim::assignment::{assign, Assignment, AssignmentError};
+use crate::sim::demand::{DemandMatrix, DemandModel, COMMUTER_PROFILE};
+use crate::sim::parking::ParkingTrip;
+use crate::sim::pedestrian::Pedestrian;
+use crate::sim::Simulation;
+use crate::traffic::preset::RoadPresetLibrary;
+use crate::traffic::{LaneTypeManager, RoutingProfile};
 use ggez::conf::{NumSamples, WindowMode, WindowSetup};
 use ggez::event::EventHandler;
 use ggez::glam::Vec2;
@@ -25,12 +36,22 @@ use tuple_map::TupleMap2;
 
 const CITY_WIDTH: f32 = 100_000.0;
 const TPS: u32 = 20;
+const ROAD_PRESETS_PATH: &str = "/road_presets.txt";
+const DEMAND_PATH: &str = "/demand.csv";
+const BUS_LINES_PATH: &str = "/bus_lines.txt";
+const PEDESTRIAN_RADIUS: f32 = 0.4;
 
 struct Game {
     camera: Camera,
     input: Input,
     graphics: Graphics,
     node_manager: NodeManager,
+    lane_types: LaneTypeManager,
+    road_presets: RoadPresetLibrary,
+    simulation: Simulation,
+    demand: DemandModel,
+    /// Outcome of the last static assignment of the demand matrix.
+    assignment: Option<Result<Assignment, AssignmentError>>,
     current_path: Option<Vec<EdgeId>>,
     explored_paths: Vec<EdgeId>,
 }
@@ -41,8 +62,7 @@ trait Lerp {
     #[inline(always)]
     fn get_alpha(time: &TimeContext) -> f32 {
         let remainder = time.remaining_update_time().as_secs_f32();
-        let alpha = remainder / TPS as f32;
-        alpha
+        remainder * TPS as f32
     }
 
     fn lerp(self, time: &TimeContext) -> Self::Output;
@@ -56,13 +76,43 @@ impl Lerp for (f32, f32) {
     }
 }
 
+impl Lerp for (Vec2, Vec2) {
+    type Output = Vec2;
+
+    fn lerp(self, time: &TimeContext) -> Self::Output {
+        self.0.lerp(self.1, Self::get_alpha(time))
+    }
+}
+
 impl Game {
     fn new(ctx: &Context, window_size: Vec2) -> GameResult<Self> {
+        let lane_types = LaneTypeManager::new();
+        let mut road_presets = RoadPresetLibrary::new(&lane_types);
+        if ctx.fs.exists(ROAD_PRESETS_PATH) {
+            let source = ctx.fs.read_to_string(ROAD_PRESETS_PATH)?;
+            road_presets.load(&source, &lane_types).map_err(|e| GameError::ResourceLoadError(format!("{ROAD_PRESETS_PATH}: {e}")))?;
+        }
+        let node_manager = NodeManager::new();
+        let mut demand = DemandMatrix::default();
+        if ctx.fs.exists(DEMAND_PATH) {
+            let source = ctx.fs.read_to_string(DEMAND_PATH)?;
+            demand = DemandMatrix::from_csv(&source, &node_manager).map_err(|e| GameError::ResourceLoadError(format!("{DEMAND_PATH}: {e}")))?;
+        }
+        let mut simulation = Simulation::new();
+        if ctx.fs.exists(BUS_LINES_PATH) {
+            let source = ctx.fs.read_to_string(BUS_LINES_PATH)?;
+            simulation.load_lines(&node_manager, &source, TPS).map_err(|e| GameError::ResourceLoadError(format!("{BUS_LINES_PATH}: {e}")))?;
+        }
         Ok(Game {
             camera: Camera::new(window_size),
             input: Input::new(),
             graphics: Graphics::new(ctx)?,
-            node_manager: NodeManager::new(),
+            node_manager,
+            lane_types,
+            road_presets,
+            simulation,
+            demand: DemandModel::new(demand, COMMUTER_PROFILE, 7.0, TPS as u64 * 3600),
+            assignment: None,
             current_path: None,
             explored_paths: vec![],
         })
@@ -91,6 +141,28 @@ impl Game {
         }
     }
 
+    fn draw_vehicle(&self, vehicle: &Vehicle, canvas: &mut Canvas, time: &TimeContext) {
+        let colour = match vehicle.get_profile() {
+            RoutingProfile::Car => Color::from_rgb(70, 130, 220),
+            RoutingProfile::Bus => Color::from_rgb(220, 60, 50),
+            RoutingProfile::Pedestrian => Color::WHITE,
+            RoutingProfile::Cyclist => Color::from_rgb(60, 200, 90),
+            RoutingProfile::Truck => Color::from_rgb(230, 140, 40),
+        };
+        let pos = (vehicle.get_previous_pos(), vehicle.get_pos()).lerp(time);
+        let param = DrawParam::new()
+            .dest(pos)
+            .rotation(vehicle.get_heading().to_angle())
+            .scale(Vec2::new(vehicle.get_length(), vehicle.get_width()))
+            .color(colour);
+        canvas.draw(self.graphics.vehicle(), param);
+    }
+
+    fn draw_pedestrian(&self, pedestrian: &Pedestrian, canvas: &mut Canvas, time: &TimeContext) {
+        let pos = (pedestrian.get_previous_pos(), pedestrian.get_pos()).lerp(time);
+        canvas.draw(self.graphics.circle(), DrawParam::new().scale(Vec2::splat(PEDESTRIAN_RADIUS)).dest(pos).color(Color::WHITE));
+    }
+
     fn draw_node_internal(&self, node: &Node, canvas: &mut Canvas, radius: f32, colour: Color) {
         canvas.draw(self.graphics.circle(), DrawParam::new().scale(Vec2::splat(radius)).dest(node.get_pos()).color(colour));
     }
@@ -98,28 +170,132 @@ impl Game {
 
 impl EventHandler for Game {
     fn update(&mut self, ctx: &mut Context) -> GameResult {
-        while ctx.time.check_update_time(TPS) {}
+        while ctx.time.check_update_time(TPS) {
+            self.demand.tick(&self.node_manager, &mut self.simulation);
+            self.simulation.tick(&self.node_manager);
+        }
         Ok(())
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
         self.camera.tick(&self.input, ctx.gfx.drawable_size().into(), ctx.time.delta().as_secs_f32());
-        self.input.tick(ctx.gfx.drawable_size().into(), &self.camera, &mut self.node_manager, &mut self.current_path, &mut self.explored_paths);
+        self.input.tick(ctx.gfx.drawable_size().into(), &self.camera, World {
+            node_manager: &mut self.node_manager,
+            road_presets: &self.road_presets,
+            simulation: &mut self.simulation,
+            current_path: &mut self.current_path,
+            explored_paths: &mut self.explored_paths,
+        });
+        while self.input.get_mut(BindingType::CycleMarkingStyle).consume_click() {
+            self.graphics.set_marking_style(ctx, self.graphics.get_marking_style().next())?;
+        }
+        while self.input.get_mut(BindingType::CycleOverlay).consume_click() {
+            self.graphics.overlay = self.graphics.overlay.map_or(Some(EdgeMetric::Flow), EdgeMetric::next);
+        }
+        if self.input.get_mut(BindingType::ToggleDemand).consume_all_clicks() {
+            self.demand.set_enabled(!self.demand.is_enabled());
+        }
+        if self.input.get_mut(BindingType::RunAssignment).consume_all_clicks() {
+            self.assignment = Some(assign(&self.node_manager, self.demand.get_matrix(), self.node_manager.routing_profile));
+        }
         ctx.gfx.set_window_title(&format!("{} FPS", ctx.time.fps() as u32));
         let mut canvas = Canvas::from_frame(ctx, Color::BLACK);
         canvas.set_projection(self.camera.get_proj_matrix() * self.camera.get_view_matrix());
         for edge in self.node_manager.get_edges() {
             self.draw_edge(edge, &mut canvas, ctx)?;
         }
+        self.graphics.draw_overlay(&mut canvas, ctx, &self.node_manager, &self.simulation)?;
         for node in self.node_manager.get_nodes() {
             self.draw_node(node, &mut canvas);
         }
+        self.graphics.draw_parking(&mut canvas, ctx, &self.node_manager, &self.simulation)?;
+        self.graphics.draw_crosswalks(&mut canvas, ctx, &self.node_manager, &self.simulation)?;
+        self.graphics.draw_bus_stops(&mut canvas, ctx, &self.node_manager, &self.simulation)?;
+        self.graphics.draw_signals(&mut canvas, ctx, &self.node_manager, &self.simulation)?;
+        for (_, vehicle) in self.simulation.get_vehicles() {
+            self.draw_vehicle(vehicle, &mut canvas, &ctx.time);
+        }
+        for (_, pedestrian) in self.simulation.get_pedestrians() {
+            self.draw_pedestrian(pedestrian, &mut canvas, &ctx.time);
+        }
+        if let Some(node) = self.node_manager.selected_node {
+            self.graphics.draw_junction(&mut canvas, ctx, node, &self.node_manager)?;
+        }
         canvas.draw(self.graphics.bounds(), DrawParam::new());
         canvas.finish(ctx)?;
         let mut canvas = Canvas::from_frame(ctx, None);
         canvas.draw(&Text::new(format!("X: {:.1}", self.camera.get_pos().x)), DrawParam::new().dest(Vec2::new(5.0, 5.0)).color(Color::WHITE));
+        let legend_pos = Vec2::new(ctx.gfx.drawable_size().0 - LEGEND_WIDTH - 5.0, 5.0);
+        self.graphics.draw_overlay_legend(&mut canvas, ctx, legend_pos)?;
         canvas.draw(&Text::new(format!("Y: {:.1}", self.camera.get_pos().y)), DrawParam::new().dest(Vec2::new(5.0, 20.0)).color(Color::WHITE));
         canvas.draw(&Text::new(format!("Zoom x{}", 1.0 / self.camera.get_zoom())), DrawParam::new().dest(Vec2::new(5.0, 35.0)).color(Color::WHITE));
+        canvas.draw(&Text::new(format!("Profile: {:?}", self.node_manager.routing_profile)), DrawParam::new().dest(Vec2::new(5.0, 50.0)).color(Color::WHITE));
+        if let Some(preset) = self.road_presets.get(self.input.get_selected_preset()) {
+            canvas.draw(&Text::new(format!("Road: {}", preset.get_display_name())), DrawParam::new().dest(Vec2::new(5.0, 65.0)).color(Color::WHITE));
+        }
+        canvas.draw(&Text::new(format!("Traffic: {:?}-hand", self.node_manager.get_traffic_side())), DrawParam::new().dest(Vec2::new(5.0, 80.0)).color(Color::WHITE));
+        canvas.draw(&Text::new(format!("Markings: {:?}", self.graphics.get_marking_style())), DrawParam::new().dest(Vec2::new(5.0, 95.0)).color(Color::WHITE));
+        let routing = if_else!(self.simulation.is_dynamic_routing() => "measured travel times" ; "static travel times");
+        canvas.draw(&Text::new(format!("Vehicles: {}, routing by {routing}, {} reroutes", self.simulation.vehicle_count(), self.simulation.get_reroutes())), DrawParam::new().dest(Vec2::new(5.0, 110.0)).color(Color::WHITE));
+        if let Some(node) = self.node_manager.selected_node {
+            let text = if let Some(signal) = self.simulation.get_signal(node) {
+                format!("Signal: phase {}/{}, {:?}, cycle {} ticks", signal.get_phase() + 1, signal.get_plan().get_phases().len(), signal.get_mode(), signal.get_plan().cycle_length())
+            } //
+            else {
+                let chosen = self.node_manager.get_node(node).is_some_and(|node| node.get_control_override().is_some());
+                format!("Junction: {:?}{}", self.node_manager.get_junction_control(node), if_else!(chosen => "" ; " (road hierarchy)"))
+            };
+            canvas.draw(&Text::new(text), DrawParam::new().dest(Vec2::new(5.0, 125.0)).color(Color::WHITE));
+        }
+        let hour = self.demand.hour_of_day(self.simulation.get_ticks());
+        let text = format!("Demand: {}, {:02}:{:02}, {:.0} trips/h at peak, {} spawned, {} unroutable", if_else!(self.demand.is_enabled() => "on" ; "off"), hour as u32, (hour.fract() * 60.0) as u32, self.demand.get_matrix().total_trips_per_hour(), self.demand.get_spawned(), self.demand.get_failed());
+        canvas.draw(&Text::new(text), DrawParam::new().dest(Vec2::new(5.0, 155.0)).color(Color::WHITE));
+        let (occupied, capacity) = self.simulation.parking_occupancy(&self.node_manager);
+        let stats = self.simulation.get_parking_stats();
+        let searching = self.simulation.get_vehicles().filter(|(_, vehicle)| vehicle.get_parking().is_some_and(ParkingTrip::is_searching)).count();
+        let text = format!("Parking: {occupied}/{capacity} occupied, {searching} searching, {} parked, {} gave up, {:.0} ticks mean search", stats.parked, stats.gave_up, stats.mean_search_time());
+        canvas.draw(&Text::new(text), DrawParam::new().dest(Vec2::new(5.0, 170.0)).color(Color::WHITE));
+        let walking = self.simulation.get_pedestrians().filter(|(_, pedestrian)| !pedestrian.is_waiting()).count();
+        let stats = self.simulation.get_pedestrian_stats();
+        let text = format!("Pedestrians: {walking} walking, {} waiting, {} crossings, {:.0} ticks mean wait, {} arrived", self.simulation.get_pedestrians().count() - walking, stats.crossings, stats.mean_wait(), stats.arrived);
+        canvas.draw(&Text::new(text), DrawParam::new().dest(Vec2::new(5.0, 185.0)).color(Color::WHITE));
+        let mut y = 200.0;
+        for (id, line) in self.simulation.get_lines() {
+            let stats = line.get_stats();
+            let text = format!("Line {}: {} buses, {} departed, {:.0}% on time, {:+.0} ticks mean deviation, {:.0}% bunched, {} stops missed", line.get_name(), self.simulation.buses_in_service(id), stats.departures, stats.on_time_share() * 100.0, stats.mean_deviation(), stats.bunching_share() * 100.0, stats.missed);
+            canvas.draw(&Text::new(text), DrawParam::new().dest(Vec2::new(5.0, y)).color(Color::WHITE));
+            y += 15.0;
+        }
+        if let Some(edge) = self.node_manager.selected_edge.and_then(|edge| self.node_manager.get_edge(edge)) {
+            let text = format!("Edge: {}, {}, speed {:.1}", self.lane_types.serialize(edge.get_lane_def()), if_else!(edge.get_directionality().is_one_way() => "one-way" ; "two-way"), edge.get_speed());
+            canvas.draw(&Text::new(text), DrawParam::new().dest(Vec2::new(5.0, y)).color(Color::WHITE));
+            y += 15.0;
+        }
+        if let Some(error) = &self.input.lane_error {
+            canvas.draw(&Text::new(format!("Lanes: {error}")), DrawParam::new().dest(Vec2::new(5.0, y)).color(Color::WHITE));
+            y += 15.0;
+        }
+        if let Some(error) = &self.input.line_error {
+            canvas.draw(&Text::new(format!("Bus line: {error}")), DrawParam::new().dest(Vec2::new(5.0, y)).color(Color::WHITE));
+            y += 15.0;
+        }
+        if let Some(assignment) = &self.assignment {
+            let text = match assignment {
+                Ok(assignment) => {
+                    let busiest = assignment.get_links().iter().max_by(|a, b| a.volume_capacity_ratio().total_cmp(&b.volume_capacity_ratio()));
+                    format!("Assignment: {} iterations, {:.4} relative gap, {:.2} max V/C, {:.0} trips/h unroutable", assignment.get_iterations(), assignment.get_relative_gap(), busiest.map_or(0.0, |link| link.volume_capacity_ratio()), assignment.get_unroutable())
+                }
+                Err(error) => format!("Assignment: {error}"),
+            };
+            canvas.draw(&Text::new(text), DrawParam::new().dest(Vec2::new(5.0, y)).color(Color::WHITE));
+        }
+        if let Some(report) = &self.input.timing_report {
+            let text = match report {
+                Ok(report) => format!("Timing: delay {:.0} -> {:.0} ticks per vehicle", report.before, report.after),
+                Err(error) => format!("Timing: {error}"),
+            };
+            canvas.draw(&Text::new(text), DrawParam::new().dest(Vec2::new(5.0, 140.0)).color(Color::WHITE));
+        }
         canvas.finish(ctx)?;
         self.input.end_tick();
         Ok(())
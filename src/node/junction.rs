@@ -0,0 +1,234 @@
+use crate::math::if_else;
+use crate::node::lane_graph::DirectedLane;
+use crate::node::{EdgeId, NodeId, NodeManager};
+use crate::traffic::side::Turn;
+use crate::traffic::{LaneDirection, RoutingProfile};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// The lanes leaving a junction that `incoming` connects to when its node has no override for it.
+///
+/// Going straight keeps the lane's position across the road, spreading or merging lanes when the number of lanes
+/// changes. Only the lane nearest to the kerb turns onto the near side and only the lane nearest to the opposing
+/// traffic turns across it, each into the matching outermost lane. Lanes without a direction, like sidewalks, connect
+/// to every compatible lane. Turn-only lanes are never connected to and only turn across the opposing traffic.
+pub fn default_connections(node_manager: &NodeManager, incoming: DirectedLane, arrivals: &[DirectedLane], departures: &[DirectedLane]) -> Vec<DirectedLane> {
+    let lane_type = incoming.get_type(node_manager);
+    let near_turn = node_manager.get_traffic_side().near_turn();
+    let group = far_to_near(arrivals.iter().copied().filter(|lane| lane.get_edge() == incoming.get_edge() && lane.get_direction() == incoming.get_direction() && lane.get_type(node_manager) == lane_type));
+    let rank = group.iter().position(|lane| *lane == incoming).unwrap_or(0);
+    let count = group.len().max(1);
+    let mut edges = Vec::<EdgeId>::new();
+    for lane in departures {
+        if lane.get_edge() != incoming.get_edge() && !edges.contains(&lane.get_edge()) {
+            edges.push(lane.get_edge());
+        }
+    }
+    let mut connections = vec![];
+    for edge in edges {
+        let candidates = departures.iter().copied().filter(|lane| lane.get_edge() == edge && !lane.get_type(node_manager).is_turn_only() && RoutingProfile::any_connects(lane_type, lane.get_type(node_manager))).collect::<Vec<_>>();
+        if candidates.is_empty() {
+            continue;
+        }
+        if lane_type.direction().is_none() && !lane_type.is_turn_only() {
+            connections.extend(candidates);
+            continue;
+        }
+        let same_type = candidates.iter().copied().filter(|lane| lane.get_type(node_manager) == lane_type).collect::<Vec<_>>();
+        let targets = far_to_near(if_else!(same_type.is_empty() => candidates ; same_type).into_iter());
+        let target_count = targets.len();
+        let turn = node_manager.classify_turn(incoming, targets[0]);
+        if lane_type.is_turn_only() {
+            if turn.crosses_traffic(node_manager.get_traffic_side()) {
+                connections.push(targets[0]);
+            }
+            continue;
+        }
+        match turn {
+            Turn::Straight => {
+                connections.extend(targets.iter().enumerate().filter(|(target_rank, _)| target_rank * count / target_count == rank || rank * target_count / count == *target_rank).map(|(_, lane)| *lane));
+            }
+            turn if turn == near_turn => {
+                if rank == count - 1 {
+                    connections.push(targets[target_count - 1]);
+                }
+            }
+            _ => {
+                if rank == 0 {
+                    connections.push(targets[0]);
+                }
+            }
+        }
+    }
+    connections
+}
+
+/// Sorts the lanes from the side of the opposing traffic to the kerb. Lanes are listed to the left of the forward
+/// direction first and the whole cross-section is mirrored under left-hand traffic, so this does not depend on the
+/// traffic side.
+fn far_to_near(lanes: impl Iterator<Item=DirectedLane>) -> Vec<DirectedLane> {
+    let mut lanes = lanes.collect::<Vec<_>>();
+    lanes.sort_by_key(|lane| match lane.get_direction() {
+        LaneDirection::Forward => lane.get_lane().get_index() as i32,
+        LaneDirection::Reverse => -(lane.get_lane().get_index() as i32),
+    });
+    lanes
+}
+
+/// How right of way is decided at a junction without signals.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum JunctionControl {
+    /// Vehicles give way to traffic from the near side, and turns across traffic give way to oncoming traffic.
+    Uncontrolled,
+    /// The major road has right of way, the minor approaches give way.
+    PriorityRoad,
+    /// Like [`JunctionControl::PriorityRoad`], but the minor approaches first have to stop.
+    Stop,
+    /// Like [`JunctionControl::PriorityRoad`], with a longer look at the major road before entering.
+    Yield,
+    /// Everyone stops, then vehicles enter in the order they stopped.
+    AllWayStop,
+}
+
+impl JunctionControl {
+    pub fn next(self) -> Self {
+        match self {
+            JunctionControl::Uncontrolled => JunctionControl::PriorityRoad,
+            JunctionControl::PriorityRoad => JunctionControl::Stop,
+            JunctionControl::Stop => JunctionControl::Yield,
+            JunctionControl::Yield => JunctionControl::AllWayStop,
+            JunctionControl::AllWayStop => JunctionControl::Uncontrolled,
+        }
+    }
+
+    /// Whether the control distinguishes between a major road and minor approaches.
+    pub fn has_major_road(self) -> bool {
+        match self {
+            JunctionControl::PriorityRoad | JunctionControl::Stop | JunctionControl::Yield => true,
+            JunctionControl::Uncontrolled | JunctionControl::AllWayStop => false,
+        }
+    }
+}
+
+/// The edges forming the major road through a junction: the fastest edges, and if there is only one, the edge most
+/// directly opposite it among the next fastest. When more than two edges are equally fast, the pair closest to a
+/// straight line wins.
+pub fn major_approaches(node_manager: &NodeManager, node: NodeId) -> Vec<EdgeId> {
+    let junction = node_manager.get_node(node).unwrap();
+    let pos = junction.get_pos();
+    let direction = |edge: EdgeId| (node_manager.get_node_pos(node_manager.get_edge(edge).unwrap().get_other_node(node)).unwrap() - pos).normalize_or_zero();
+    let straightness = |a: EdgeId, b: EdgeId| -direction(a).dot(direction(b));
+    let speed = |edge: EdgeId| node_manager.get_edge(edge).unwrap().get_speed();
+    let mut edges = junction.get_edges().to_vec();
+    edges.sort_by(|a, b| speed(*b).total_cmp(&speed(*a)));
+    let Some(fastest) = edges.first().map(|edge| speed(*edge)) else {
+        return edges;
+    };
+    let top = edges.iter().copied().filter(|edge| speed(*edge) == fastest).collect::<Vec<_>>();
+    match top.len() {
+        1 => {
+            let next_speed = edges.get(1).map(|edge| speed(*edge));
+            let opposite = edges.iter().copied().skip(1).filter(|edge| Some(speed(*edge)) == next_speed).max_by(|a, b| straightness(top[0], *a).total_cmp(&straightness(top[0], *b)));
+            top.into_iter().chain(opposite).collect()
+        }
+        2 => top,
+        _ => {
+            let mut best = (top[0], top[1]);
+            for (index, a) in top.iter().enumerate() {
+                for b in &top[index + 1..] {
+                    if straightness(*a, *b) > straightness(best.0, best.1) {
+                        best = (*a, *b);
+                    }
+                }
+            }
+            vec![best.0, best.1]
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ConnectionError {
+    /// The lane does not arrive at any junction a lane could be connected at.
+    NotArriving(DirectedLane),
+    /// The lane does not leave the junction the incoming lane arrives at.
+    NotDeparting(DirectedLane),
+    /// Both lanes belong to the same edge.
+    SameEdge(DirectedLane),
+}
+
+impl Display for ConnectionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionError::NotArriving(lane) => write!(f, "{lane:?} does not arrive at a junction"),
+            ConnectionError::NotDeparting(lane) => write!(f, "{lane:?} does not leave the junction"),
+            ConnectionError::SameEdge(lane) => write!(f, "{lane:?} is on the edge it would be entered from"),
+        }
+    }
+}
+
+impl Error for ConnectionError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::lane_graph::LaneId;
+    use crate::traffic::LaneDefinition;
+    use crate::traffic::LaneType::{BikeForward, BikeReverse, NormalForward, NormalReverse, Sidewalk, TurnLane};
+    use crate::traffic::side::TrafficSide;
+    use ggez::glam::Vec2;
+
+    #[test]
+    fn turn_lanes_only_turn_across_traffic() {
+        let mut node_manager = NodeManager::new();
+        let west = node_manager.add_node(Vec2::new(-500.0, 5000.0));
+        let centre = node_manager.add_node(Vec2::new(0.0, 5000.0));
+        let street = node_manager.make_edge(west, centre, 1.0, LaneDefinition::from_lanes(&[Sidewalk, BikeReverse, NormalReverse, TurnLane, NormalForward, BikeForward, Sidewalk]).unwrap());
+        for pos in [Vec2::new(500.0, 5000.0), Vec2::new(0.0, 5500.0), Vec2::new(0.0, 4500.0)] {
+            let node = node_manager.add_node(pos);
+            node_manager.make_edge(centre, node, 1.0, LaneDefinition::from_lanes(&[Sidewalk, NormalReverse, NormalForward, Sidewalk]).unwrap());
+        }
+        let lane_graph = node_manager.get_lane_graph();
+        let turn_lane = DirectedLane::new(LaneId::new(street, 3), LaneDirection::Forward);
+        let successors = lane_graph.get_successors(turn_lane);
+        assert_eq!(successors.len(), 1);
+        assert!(node_manager.classify_turn(turn_lane, successors[0]).crosses_traffic(node_manager.get_traffic_side()));
+        //The turn lane is never entered from a junction, nor where routes start
+        for node in [west, centre] {
+            for lane in lane_graph.get_arrivals(node) {
+                assert!(lane_graph.get_successors(*lane).iter().all(|next| !next.get_type(&node_manager).is_turn_only()), "{lane:?}");
+            }
+        }
+        let route = node_manager.lane_route(west, centre, RoutingProfile::Car, None, |_, _| 0.0).unwrap();
+        assert_eq!(route, [DirectedLane::new(LaneId::new(street, 4), LaneDirection::Forward)]);
+    }
+
+    #[test]
+    fn lanes_merge_and_spread_going_straight_and_only_outer_lanes_turn() {
+        for side in [TrafficSide::Right, TrafficSide::Left] {
+            let mut node_manager = NodeManager::new();
+            node_manager.set_traffic_side(side);
+            let west = node_manager.add_node(Vec2::new(-500.0, 5000.0));
+            let centre = node_manager.add_node(Vec2::new(0.0, 5000.0));
+            let wide = node_manager.make_edge(west, centre, 1.0, LaneDefinition::from_lanes(&[Sidewalk, NormalReverse, NormalReverse, NormalForward, NormalForward, Sidewalk]).unwrap());
+            let [narrow, north, south] = [Vec2::new(500.0, 5000.0), Vec2::new(0.0, 4500.0), Vec2::new(0.0, 5500.0)].map(|pos| {
+                let node = node_manager.add_node(pos);
+                node_manager.make_edge(centre, node, 1.0, LaneDefinition::from_lanes(&[Sidewalk, NormalReverse, NormalForward, Sidewalk]).unwrap())
+            });
+            let lane = |edge, index, direction| DirectedLane::new(LaneId::new(edge, index), direction);
+            let successors = |incoming: DirectedLane, edge: EdgeId| node_manager.get_lane_graph().get_successors(incoming).iter().copied().filter(|lane| lane.get_edge() == edge).collect::<Vec<_>>();
+            //The cross-section is mirrored under left-hand traffic, so the first forward lane is next to the opposing
+            //traffic on either side
+            let (far, near) = (lane(wide, 3, LaneDirection::Forward), lane(wide, 4, LaneDirection::Forward));
+            let onto_narrow = lane(narrow, 2, LaneDirection::Forward);
+            assert_eq!(successors(far, narrow), [onto_narrow], "{side:?}");
+            assert_eq!(successors(near, narrow), [onto_narrow], "{side:?}");
+            //The single lane coming back spreads onto both lanes
+            let mut spread = successors(lane(narrow, 1, LaneDirection::Reverse), wide);
+            spread.sort_by_key(|lane| lane.get_lane().get_index());
+            assert_eq!(spread, [lane(wide, 1, LaneDirection::Reverse), lane(wide, 2, LaneDirection::Reverse)], "{side:?}");
+            let (near_arm, far_arm) = if_else!(node_manager.classify_turn(far, lane(north, 2, LaneDirection::Forward)) == side.near_turn() => (north, south) ; (south, north));
+            assert!(successors(far, near_arm).is_empty() && successors(far, far_arm).len() == 1, "{side:?}");
+            assert!(successors(near, near_arm).len() == 1 && successors(near, far_arm).is_empty(), "{side:?}");
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use crate::node::junction::default_connections;
+use crate::node::{EdgeId, NodeId, NodeManager};
+use crate::traffic::layout::LaneSlot;
+use crate::traffic::{LaneDirection, LaneType};
+use ggez::glam::Vec2;
+use rustc_hash::FxHashMap;
+use tuple_map::TupleMap2;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct LaneId {
+    edge: EdgeId,
+    index: u8,
+}
+
+impl LaneId {
+    pub fn new(edge: EdgeId, index: u8) -> Self {
+        LaneId {
+            edge,
+            index,
+        }
+    }
+
+    pub fn get_edge(self) -> EdgeId {
+        self.edge
+    }
+
+    pub fn get_index(self) -> u8 {
+        self.index
+    }
+}
+
+/// A lane together with the direction it is travelled in, which is the vertex type of the [`LaneGraph`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct DirectedLane {
+    lane: LaneId,
+    direction: LaneDirection,
+}
+
+impl DirectedLane {
+    pub fn new(lane: LaneId, direction: LaneDirection) -> Self {
+        DirectedLane {
+            lane,
+            direction,
+        }
+    }
+
+    pub fn get_lane(self) -> LaneId {
+        self.lane
+    }
+
+    pub fn get_edge(self) -> EdgeId {
+        self.lane.get_edge()
+    }
+
+    pub fn get_direction(self) -> LaneDirection {
+        self.direction
+    }
+
+    pub fn get_from(self, node_manager: &NodeManager) -> NodeId {
+        node_manager.get_edge(self.lane.edge).unwrap().get_endpoints(self.direction).0
+    }
+
+    pub fn get_to(self, node_manager: &NodeManager) -> NodeId {
+        node_manager.get_edge(self.lane.edge).unwrap().get_endpoints(self.direction).1
+    }
+
+    pub fn get_type(self, node_manager: &NodeManager) -> LaneType {
+        node_manager.get_edge(self.lane.edge).unwrap().get_lane_type(self.lane.index)
+    }
+
+    /// The start and end of the centre of the lane in the direction of travel.
+    pub fn get_centre_line(self, node_manager: &NodeManager) -> (Vec2, Vec2) {
+        let edge = node_manager.get_edge(self.lane.edge).unwrap();
+        let (a, b) = edge.get_nodes().map(|id| node_manager.get_node_pos(id).unwrap());
+        let offset = edge.get_lane_def().layout(node_manager.get_traffic_side()).get(self.lane.index).map_or(0.0, LaneSlot::get_offset) * (b - a).normalize().perp();
+        let (from, to) = edge.get_endpoints(self.direction).map(|id| node_manager.get_node_pos(id).unwrap());
+        (from + offset, to + offset)
+    }
+}
+
+pub struct LaneGraph {
+    successors: FxHashMap<DirectedLane, Vec<DirectedLane>>,
+    departures: FxHashMap<NodeId, Vec<DirectedLane>>,
+    arrivals: FxHashMap<NodeId, Vec<DirectedLane>>,
+}
+
+impl LaneGraph {
+    pub fn new(node_manager: &NodeManager) -> Self {
+        let mut departures = FxHashMap::<NodeId, Vec<DirectedLane>>::default();
+        let mut arrivals = FxHashMap::<NodeId, Vec<DirectedLane>>::default();
+        for edge in node_manager.get_edges() {
+            for lane in edge.get_routable_lanes() {
+                let (from, to) = edge.get_endpoints(lane.direction);
+                departures.entry(from).or_default().push(lane);
+                arrivals.entry(to).or_default().push(lane);
+            }
+        }
+        let mut successors = FxHashMap::default();
+        for (node, incoming) in &arrivals {
+            let outgoing = departures.get(node).map(Vec::as_slice).unwrap_or(&[]);
+            let junction = node_manager.get_node(*node).unwrap();
+            for lane in incoming {
+                let next = match junction.get_connection_override(*lane) {
+                    Some(connections) => connections.iter().copied().filter(|next| outgoing.contains(next)).collect(),
+                    None => default_connections(node_manager, *lane, incoming, outgoing),
+                };
+                successors.insert(*lane, next);
+            }
+        }
+        LaneGraph {
+            successors,
+            departures,
+            arrivals,
+        }
+    }
+
+    pub fn get_successors(&self, lane: DirectedLane) -> &[DirectedLane] {
+        self.successors.get(&lane).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn get_departures(&self, node: NodeId) -> &[DirectedLane] {
+        self.departures.get(&node).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn get_arrivals(&self, node: NodeId) -> &[DirectedLane] {
+        self.arrivals.get(&node).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
@@ -1,11 +1,16 @@
 use crate::math::vec::Vec2CompWise;
 use crate::math::{if_else, vec::Vec2Axis, Sqr};
 use crate::node::a_star::AStarHeap;
-use crate::traffic::LaneDefinition;
+use crate::node::junction::{major_approaches, ConnectionError, JunctionControl};
+use crate::node::lane_graph::{DirectedLane, LaneGraph, LaneId};
+use crate::node::sidewalk::SidewalkGraph;
+use crate::traffic::LaneType::{BusForward, BusReverse, Grass, NormalForward, NormalReverse, ParkingForward, ParkingReverse, Sidewalk};
+use crate::traffic::side::{TrafficSide, Turn};
+use crate::traffic::{Directionality, LaneDefinition, LaneDirection, LaneError, LaneType, RoutingProfile};
 use crate::CITY_WIDTH;
 use ggez::glam::{IVec2, Vec2};
 use rustc_hash::FxHashMap;
-use std::cell::RefCell;
+use std::cell::{OnceCell, RefCell};
 use std::hash::Hash;
 use std::mem;
 use std::mem::MaybeUninit;
@@ -13,12 +18,21 @@ use std::num::NonZeroU64;
 
 mod a_star;
 mod fibonacci_heap;
+pub mod junction;
+pub mod lane_graph;
+pub mod sidewalk;
 
 const CHUNK_SIZE: f32 = 100.0;
 const MAX_POS_COMP: i32 = ((CITY_WIDTH / 2.0) / CHUNK_SIZE) as i32 - 1;
 const MIN_POS_COMP: i32 = ((-CITY_WIDTH / 2.0) / CHUNK_SIZE) as i32;
 const MIN_POS: IVec2 = IVec2::splat(MIN_POS_COMP);
 const MAX_POS: IVec2 = IVec2::splat(MAX_POS_COMP);
+/// Extra cost of a turn that crosses the opposing traffic, so routes prefer turns on the near side.
+const CROSSING_TURN_PENALTY: f32 = 10.0;
+
+/// Travel times measured on edges in either direction, in ticks. The router uses them instead of the static travel
+/// time of the edges they are known for.
+pub type TravelTimes = FxHashMap<(EdgeId, LaneDirection), f32>;
 
 #[derive(Copy, Clone, Eq, PartialEq, Hash)]
 struct ChunkPos(IVec2);
@@ -173,6 +187,8 @@ pub struct Node {
     id: NodeId,
     pos: Vec2,
     edges: Vec<EdgeId>,
+    connection_overrides: FxHashMap<DirectedLane, Vec<DirectedLane>>,
+    control: Option<JunctionControl>,
 }
 
 impl Eq for Node {}
@@ -199,6 +215,24 @@ impl Node {
         }
     }
 
+    /// The lanes `incoming` was manually connected to, replacing its default connections.
+    pub fn get_connection_override(&self, incoming: DirectedLane) -> Option<&[DirectedLane]> {
+        self.connection_overrides.get(&incoming).map(Vec::as_slice)
+    }
+
+    pub fn get_edges(&self) -> &[EdgeId] {
+        &self.edges
+    }
+
+    /// The control chosen for the junction, or [`None`] if it follows the road hierarchy.
+    pub fn get_control_override(&self) -> Option<JunctionControl> {
+        self.control
+    }
+
+    pub fn has_connection_overrides(&self) -> bool {
+        !self.connection_overrides.is_empty()
+    }
+
     pub fn find_edge(&self, other_node: NodeId, node_manager: &NodeManager) -> Option<EdgeId> {
         for edge_id in &self.edges {
             if node_manager.get_edge(*edge_id).unwrap().get_other_node(self.id) == other_node {
@@ -232,7 +266,15 @@ pub struct NodeManager {
     pub end_node: Option<NodeId>,
     pub selected_node: Option<NodeId>,
     pub selected_edge: Option<EdgeId>,
+    pub selected_incoming: Option<DirectedLane>,
+    pub selected_outgoing: Option<DirectedLane>,
     pub tested_nodes: RefCell<Vec<NodeId>>,
+    pub routing_profile: RoutingProfile,
+    traffic_side: TrafficSide,
+    /// Speed of the fastest edge, which bounds the travel time between any two points.
+    max_speed: f32,
+    lane_graph: OnceCell<LaneGraph>,
+    sidewalk_graph: OnceCell<SidewalkGraph>,
 }
 
 trait FromRawId {
@@ -268,7 +310,14 @@ impl NodeManager {
             end_node: None,
             selected_node: None,
             selected_edge: None,
+            selected_incoming: None,
+            selected_outgoing: None,
             tested_nodes: RefCell::new(vec![]),
+            routing_profile: RoutingProfile::Car,
+            traffic_side: TrafficSide::Right,
+            max_speed: 0.0,
+            lane_graph: OnceCell::new(),
+            sidewalk_graph: OnceCell::new(),
         };
         const RADIUS: i32 = 5;
         const LEN: usize = 2 * RADIUS as usize + 1;
@@ -282,15 +331,17 @@ impl NodeManager {
         let ids = unsafe {
             mem::transmute::<_, [[NodeId; LEN]; LEN]>(ids)
         };
+        let street = LaneDefinition::from_lanes(&[Grass, Sidewalk, ParkingReverse, NormalReverse, NormalForward, ParkingForward, Sidewalk, Grass]).unwrap();
+        let avenue = LaneDefinition::from_lanes(&[Grass, Sidewalk, ParkingReverse, BusReverse, NormalReverse, NormalForward, BusForward, ParkingForward, Sidewalk, Grass]).unwrap();
         for x in -RADIUS..=RADIUS {
             let ids = ids[(x + RADIUS) as usize];
             let mut last_node = None;
             for node in ids {
                 if let Some(last) = last_node {
                     if x == 0 {
-                        manager.make_edge(last, node, 2.0, 16);
+                        manager.make_edge(last, node, 2.0, avenue.clone());
                     } else {
-                        manager.make_edge(last, node, 1.0, 12);
+                        manager.make_edge(last, node, 1.0, street.clone());
                     }
                 }
                 last_node = Some(node);
@@ -301,7 +352,7 @@ impl NodeManager {
             for x in -RADIUS..=RADIUS {
                 let node = ids[(x + RADIUS) as usize][(y + RADIUS) as usize];
                 if let Some(last) = last_node {
-                    manager.make_edge(last, node, 1.0, 12);
+                    manager.make_edge(last, node, 1.0, street.clone());
                 }
                 last_node = Some(node);
             }
@@ -325,6 +376,16 @@ impl NodeManager {
         self.edges.map.get(&id)
     }
 
+    /// Starts editing the lanes of an edge. Nothing changes until [`LaneDefMut::commit`] succeeds.
+    pub fn lane_def_mut(&mut self, id: EdgeId) -> Option<LaneDefMut<'_>> {
+        let lanes = self.get_edge(id)?.lane_def.lanes().collect();
+        Some(LaneDefMut {
+            node_manager: self,
+            edge: id,
+            lanes,
+        })
+    }
+
     pub fn get_nodes(&self) -> impl Iterator<Item=&Node> {
         self.nodes.map.values().into_iter()
     }
@@ -339,19 +400,126 @@ impl NodeManager {
             id,
             pos,
             edges: vec![],
+            connection_overrides: FxHashMap::default(),
+            control: None,
         });
         self.node_lookup.entry(ChunkPos::from_world_pos(pos)).or_insert_with(|| Vec::new()).push(id);
         id
     }
 
-    pub fn make_edge(&mut self, node_a: NodeId, node_b: NodeId, speed: f32, size: u8) -> EdgeId {
+    pub fn get_lane_graph(&self) -> &LaneGraph {
+        self.lane_graph.get_or_init(|| LaneGraph::new(self))
+    }
+
+    pub fn get_sidewalk_graph(&self) -> &SidewalkGraph {
+        self.sidewalk_graph.get_or_init(|| SidewalkGraph::new(self))
+    }
+
+    pub fn get_traffic_side(&self) -> TrafficSide {
+        self.traffic_side
+    }
+
+    /// Changes the side of the road vehicles drive on, refreshing the data derived from it.
+    pub fn set_traffic_side(&mut self, side: TrafficSide) {
+        self.traffic_side = side;
+        self.lane_graph.take();
+        self.sidewalk_graph.take();
+    }
+
+    /// Replaces the lanes `incoming` connects to at the junction it arrives at.
+    pub fn set_connections(&mut self, incoming: DirectedLane, outgoing: Vec<DirectedLane>) -> Result<(), ConnectionError> {
+        let node = incoming.get_to(self);
+        let lane_graph = self.get_lane_graph();
+        if !lane_graph.get_arrivals(node).contains(&incoming) {
+            return Err(ConnectionError::NotArriving(incoming));
+        }
+        for lane in &outgoing {
+            if lane.get_edge() == incoming.get_edge() {
+                return Err(ConnectionError::SameEdge(*lane));
+            }
+            if !lane_graph.get_departures(node).contains(lane) {
+                return Err(ConnectionError::NotDeparting(*lane));
+            }
+        }
+        self.get_node_mut(node).unwrap().connection_overrides.insert(incoming, outgoing);
+        self.lane_graph.take();
+        Ok(())
+    }
+
+    /// Connects `incoming` to `outgoing` if they are not connected, or disconnects them otherwise. Returns whether they
+    /// are connected afterwards.
+    pub fn toggle_connection(&mut self, incoming: DirectedLane, outgoing: DirectedLane) -> Result<bool, ConnectionError> {
+        let mut connections = self.get_lane_graph().get_successors(incoming).to_vec();
+        let connected = match connections.iter().position(|lane| *lane == outgoing) {
+            Some(index) => {
+                connections.remove(index);
+                false
+            }
+            None => {
+                connections.push(outgoing);
+                true
+            }
+        };
+        self.set_connections(incoming, connections)?;
+        Ok(connected)
+    }
+
+    /// Drops every connection override of the node, going back to the connections derived from the lane layouts.
+    pub fn reset_connections(&mut self, node: NodeId) {
+        if let Some(node) = self.get_node_mut(node) && node.has_connection_overrides() {
+            node.connection_overrides.clear();
+            self.lane_graph.take();
+        }
+    }
+
+    /// How right of way is decided at the node. Unless chosen explicitly, junctions where some edges are faster than
+    /// others give priority to the faster road, and all other junctions are uncontrolled.
+    pub fn get_junction_control(&self, node: NodeId) -> JunctionControl {
+        let node = self.get_node(node).unwrap();
+        if let Some(control) = node.control {
+            return control;
+        }
+        let mut speeds = node.edges.iter().map(|edge| self.get_edge(*edge).unwrap().get_speed());
+        let first = speeds.next();
+        if speeds.any(|speed| Some(speed) != first) {
+            JunctionControl::PriorityRoad
+        } //
+        else {
+            JunctionControl::Uncontrolled
+        }
+    }
+
+    pub fn set_junction_control(&mut self, node: NodeId, control: Option<JunctionControl>) {
+        if let Some(node) = self.get_node_mut(node) {
+            node.control = control;
+        }
+    }
+
+    /// The edges of the major road through the node, see [`major_approaches`].
+    pub fn get_major_approaches(&self, node: NodeId) -> Vec<EdgeId> {
+        major_approaches(self, node)
+    }
+
+    /// Classifies the turn made at the junction between two consecutive lanes.
+    pub fn classify_turn(&self, incoming: DirectedLane, outgoing: DirectedLane) -> Turn {
+        let from = self.get_node_pos(incoming.get_from(self)).unwrap();
+        let junction = self.get_node_pos(incoming.get_to(self)).unwrap();
+        let to = self.get_node_pos(outgoing.get_to(self)).unwrap();
+        Turn::classify(junction - from, to - junction)
+    }
+
+    pub fn make_edge(&mut self, node_a: NodeId, node_b: NodeId, speed: f32, lane_def: LaneDefinition) -> EdgeId {
         let id = self.edges.get_id();
         self.edges.map.insert(id, Edge {
             nodes: (node_a, node_b),
             id,
             speed,
-            lane_def: LaneDefinition::new(size),
+            directionality: lane_def.directionality(),
+            lane_def,
         });
+        self.max_speed = self.max_speed.max(speed);
+        self.lane_graph.take();
+        self.sidewalk_graph.take();
         let node_a = self.get_node_mut(node_a).unwrap();
         node_a.edges.push(id);
         let a = node_a.pos;
@@ -417,7 +585,18 @@ impl NodeManager {
         id
     }
 
-    pub fn a_star(&self, start: NodeId, goal: NodeId, h: fn(Vec2, Vec2) -> f32) -> (Option<Vec<EdgeId>>, Vec<EdgeId>) {
+    /// The least time any route between the two points can take, which never overestimates and so suits A*.
+    pub fn min_travel_time(&self, a: Vec2, b: Vec2) -> f32 {
+        a.distance(b) / self.max_speed.max(f32::EPSILON)
+    }
+
+    /// The least cost any lane route of the profile between the two points can have, discounting the lanes the profile
+    /// prefers, so it never overestimates in the lane searches either.
+    pub fn min_lane_cost(&self, a: Vec2, b: Vec2, profile: RoutingProfile) -> f32 {
+        self.min_travel_time(a, b) * profile.min_lane_cost_factor()
+    }
+
+    pub fn a_star(&self, start: NodeId, goal: NodeId, profile: RoutingProfile, h: impl Fn(Vec2, Vec2) -> f32) -> (Option<Vec<EdgeId>>, Vec<EdgeId>) {
         let mut open_set = AStarHeap::new();
         let mut explored_paths = vec![];
         let goal_pos = self.get_node_pos(goal).unwrap();
@@ -432,6 +611,9 @@ impl NodeManager {
             }
             self.get_node(current).map(|node| node.get_neighbours(&self, &mut neighbours));
             for (neighbour, path) in &neighbours {
+                if !self.get_edge(*path).unwrap().is_traversable(profile, current) {
+                    continue;
+                }
                 explored_paths.push(*path);
                 let tentative_g_score = g_score[&current] + self.get_node_pos(current).unwrap().distance(self.get_node_pos(*neighbour).unwrap()) / self.get_edge(*path).unwrap().speed;
                 if tentative_g_score < *g_score.get(&neighbour).unwrap_or(&f32::INFINITY) {
@@ -455,6 +637,90 @@ impl NodeManager {
         vec
     }
 
+    /// Finds the sequence of lanes to travel from `start` to `goal` using only lanes the profile permits, in travel order.
+    /// Edges cost their measured travel time where `travel_times` has one. Routes never start on a turn-only lane.
+    pub fn lane_route(&self, start: NodeId, goal: NodeId, profile: RoutingProfile, travel_times: Option<&TravelTimes>, h: impl Fn(Vec2, Vec2) -> f32) -> Option<Vec<DirectedLane>> {
+        if start == goal {
+            return Some(vec![]);
+        }
+        let starts = self.get_lane_graph().get_departures(start).iter()
+            .filter(|lane| profile.permits(lane.get_type(self)) && !lane.get_type(self).is_turn_only())
+            .map(|lane| (*lane, self.lane_cost(*lane, profile, travel_times)))
+            .collect();
+        self.search_lanes(starts, self.get_node_pos(goal)?, |lane| lane.get_to(self) == goal, profile, travel_times, h)
+    }
+
+    /// Like [`NodeManager::lane_route`], but the route continues from a lane already being travelled and starts with it.
+    pub fn lane_route_from(&self, start: DirectedLane, goal: NodeId, profile: RoutingProfile, travel_times: Option<&TravelTimes>, h: impl Fn(Vec2, Vec2) -> f32) -> Option<Vec<DirectedLane>> {
+        self.search_lanes(vec![(start, 0.0)], self.get_node_pos(goal)?, |lane| lane.get_to(self) == goal, profile, travel_times, h)
+    }
+
+    /// Like [`NodeManager::lane_route_from`], but the route ends on a lane of `edge` travelled in `direction` instead
+    /// of at a node.
+    pub fn lane_route_onto(&self, start: DirectedLane, edge: EdgeId, direction: LaneDirection, profile: RoutingProfile, h: impl Fn(Vec2, Vec2) -> f32) -> Option<Vec<DirectedLane>> {
+        let entry = self.get_edge(edge)?.get_endpoints(direction).0;
+        let is_goal = |lane: DirectedLane| lane != start && lane.get_edge() == edge && lane.get_direction() == direction;
+        self.search_lanes(vec![(start, 0.0)], self.get_node_pos(entry)?, is_goal, profile, None, h)
+    }
+
+    /// What the router counts for travelling a route after its first lane, the way it would find it.
+    pub fn route_cost(&self, route: &[DirectedLane], profile: RoutingProfile, travel_times: Option<&TravelTimes>) -> f32 {
+        let side = self.traffic_side;
+        route.windows(2).map(|pair| {
+            let turn = if_else!(self.classify_turn(pair[0], pair[1]).crosses_traffic(side) => CROSSING_TURN_PENALTY ; 0.0);
+            self.lane_cost(pair[1], profile, travel_times) + turn
+        }).sum()
+    }
+
+    /// Travel time of the edge of a lane, measured or static, weighted by how much the profile prefers that kind of
+    /// lane.
+    fn lane_cost(&self, lane: DirectedLane, profile: RoutingProfile, travel_times: Option<&TravelTimes>) -> f32 {
+        let measured = travel_times.and_then(|travel_times| travel_times.get(&(lane.get_edge(), lane.get_direction())));
+        let travel_time = measured.copied().unwrap_or_else(|| self.get_edge(lane.get_edge()).unwrap().get_travel_time(self));
+        travel_time * profile.lane_cost_factor(lane.get_type(self))
+    }
+
+    fn search_lanes(&self, starts: Vec<(DirectedLane, f32)>, goal_pos: Vec2, is_goal: impl Fn(DirectedLane) -> bool, profile: RoutingProfile, travel_times: Option<&TravelTimes>, h: impl Fn(Vec2, Vec2) -> f32) -> Option<Vec<DirectedLane>> {
+        let lane_graph = self.get_lane_graph();
+        let side = self.traffic_side;
+        let mut open_set = AStarHeap::new();
+        let mut came_from = FxHashMap::<DirectedLane, DirectedLane>::default();
+        let mut g_score = FxHashMap::default();
+        for (lane, score) in starts {
+            g_score.insert(lane, score);
+            open_set.push(lane, score + h(self.get_node_pos(lane.get_to(self)).unwrap(), goal_pos));
+        }
+        while let Some(current) = open_set.pop() {
+            if is_goal(current) {
+                return Some(Self::reconstruct_lane_route(came_from, current));
+            }
+            for next in lane_graph.get_successors(current).iter().filter(|next| profile.permits(next.get_type(self))) {
+                let mut tentative_g_score = g_score[&current] + self.lane_cost(*next, profile, travel_times);
+                if self.classify_turn(current, *next).crosses_traffic(side) {
+                    tentative_g_score += CROSSING_TURN_PENALTY;
+                }
+                if tentative_g_score < *g_score.get(next).unwrap_or(&f32::INFINITY) {
+                    came_from.insert(*next, current);
+                    g_score.insert(*next, tentative_g_score);
+                    let f_score = tentative_g_score + h(self.get_node_pos(next.get_to(self)).unwrap(), goal_pos);
+                    open_set.push(*next, f_score);
+                }
+            }
+        }
+        None
+    }
+
+    fn reconstruct_lane_route(came_from: FxHashMap<DirectedLane, DirectedLane>, last: DirectedLane) -> Vec<DirectedLane> {
+        let mut vec = vec![last];
+        let mut last_lane = last;
+        while let Some(previous) = came_from.get(&last_lane) {
+            vec.push(*previous);
+            last_lane = *previous;
+        }
+        vec.reverse();
+        vec
+    }
+
     pub fn try_node_collision(&self, pos: Vec2) -> Option<NodeId> {
         self.tested_nodes.borrow_mut().clear();
         for chunk_pos in ChunkPos::get_area(pos).into_iter() {
@@ -484,7 +750,7 @@ impl NodeManager {
     }
 }
 
-#[derive(Eq, PartialEq, Copy, Clone, Hash)]
+#[derive(Eq, PartialEq, Copy, Clone, Hash, Debug)]
 pub struct EdgeId(NonZeroU64);
 
 impl FromRawId for EdgeId {
@@ -498,6 +764,7 @@ pub struct Edge {
     nodes: (NodeId, NodeId),
     speed: f32,
     lane_def: LaneDefinition,
+    directionality: Directionality,
 }
 
 impl Edge {
@@ -538,4 +805,262 @@ impl Edge {
     pub fn get_size(&self) -> u8 {
         self.lane_def.get_size()
     }
-}
\ No newline at end of file
+
+    pub fn get_speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn get_lane_def(&self) -> &LaneDefinition {
+        &self.lane_def
+    }
+
+    pub fn get_directionality(&self) -> Directionality {
+        self.directionality
+    }
+
+    pub fn get_length(&self, node_manager: &NodeManager) -> f32 {
+        let (a, b) = self.nodes;
+        node_manager.get_node_pos(a).unwrap().distance(node_manager.get_node_pos(b).unwrap())
+    }
+
+    pub fn get_travel_time(&self, node_manager: &NodeManager) -> f32 {
+        self.get_length(node_manager) / self.speed
+    }
+
+    /// Returns the node a lane of the given direction starts at and the node it leads to.
+    pub fn get_endpoints(&self, direction: LaneDirection) -> (NodeId, NodeId) {
+        match direction {
+            LaneDirection::Forward => self.nodes,
+            LaneDirection::Reverse => (self.nodes.1, self.nodes.0),
+        }
+    }
+
+    pub fn get_lane_type(&self, index: u8) -> LaneType {
+        self.lane_def.get(index).unwrap()
+    }
+
+    /// Returns every lane some profile may route over, once per direction it can be travelled in.
+    pub fn get_routable_lanes(&self) -> impl Iterator<Item=DirectedLane> {
+        self.lane_def.lanes().enumerate().filter(|(_, lane)| RoutingProfile::any_permits(*lane)).flat_map(|(index, lane)| {
+            let lane_id = LaneId::new(self.id, index as u8);
+            let directions: &[LaneDirection] = match lane.direction() {
+                Some(LaneDirection::Forward) => &[LaneDirection::Forward],
+                Some(LaneDirection::Reverse) => &[LaneDirection::Reverse],
+                None => &[LaneDirection::Forward, LaneDirection::Reverse],
+            };
+            directions.iter().map(move |direction| DirectedLane::new(lane_id, *direction))
+        })
+    }
+
+    /// Whether the profile has a permitted lane leading away from `from` along this edge.
+    pub fn is_traversable(&self, profile: RoutingProfile, from: NodeId) -> bool {
+        let direction = if_else!(self.nodes.0 == from => LaneDirection::Forward ; LaneDirection::Reverse);
+        if !self.directionality.allows(direction) {
+            return false;
+        }
+        self.lane_def.lanes().any(|lane| profile.permits(lane) && match lane.direction() {
+            Some(direction) => self.get_endpoints(direction).0 == from,
+            None => !lane.is_turn_only(),
+        })
+    }
+}
+/// Pending lane edits of an edge. The edits are only validated and applied by [`LaneDefMut::commit`], so
+/// intermediate states may have lanes that do not fill the edge.
+pub struct LaneDefMut<'a> {
+    node_manager: &'a mut NodeManager,
+    edge: EdgeId,
+    lanes: Vec<LaneType>,
+}
+
+impl LaneDefMut<'_> {
+    pub fn lanes(&self) -> &[LaneType] {
+        &self.lanes
+    }
+
+    pub fn set(&mut self, index: usize, lane: LaneType) -> Result<LaneType, LaneError> {
+        LaneError::check_index(index, self.lanes.len())?;
+        Ok(mem::replace(&mut self.lanes[index], lane))
+    }
+
+    pub fn insert(&mut self, index: usize, lane: LaneType) -> Result<(), LaneError> {
+        LaneError::check_index(index, self.lanes.len() + 1)?;
+        self.lanes.insert(index, lane);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<LaneType, LaneError> {
+        LaneError::check_index(index, self.lanes.len())?;
+        Ok(self.lanes.remove(index))
+    }
+
+    /// Checks that the edited lanes fill the edge exactly and applies them, updating the derived data of the edge. The
+    /// connection overrides involving the edge are reset as its lanes may have been renumbered.
+    pub fn commit(self) -> Result<(), LaneError> {
+        let id = self.edge;
+        let edge = self.node_manager.edges.map.get_mut(&id).unwrap();
+        let lane_def = LaneDefinition::with_size(edge.get_size(), &self.lanes)?;
+        edge.directionality = lane_def.directionality();
+        edge.lane_def = lane_def;
+        let (a, b) = edge.nodes;
+        for node in [a, b] {
+            self.node_manager.get_node_mut(node).unwrap().connection_overrides.retain(|incoming, outgoing| incoming.get_edge() != id && outgoing.iter().all(|lane| lane.get_edge() != id));
+        }
+        self.node_manager.lane_graph.take();
+        self.node_manager.sidewalk_graph.take();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traffic::preset::RoadPresetLibrary;
+    use crate::traffic::LaneTypeManager;
+    use strum::IntoEnumIterator;
+    use tuple_map::TupleMap2;
+
+    /// A manager with the default grid and a separate edge with the given lanes far away from it.
+    fn manager_with_edge(lanes: &[LaneType]) -> (NodeManager, EdgeId) {
+        let mut node_manager = NodeManager::new();
+        let a = node_manager.add_node(Vec2::new(0.0, 5000.0));
+        let b = node_manager.add_node(Vec2::new(100.0, 5000.0));
+        let edge = node_manager.make_edge(a, b, 1.0, LaneDefinition::from_lanes(lanes).unwrap());
+        (node_manager, edge)
+    }
+
+    fn out_of_bounds(index: usize, len: usize) -> LaneError {
+        LaneError::IndexOutOfBounds {
+            index,
+            len,
+        }
+    }
+
+    #[test]
+    fn edits_reject_indices_out_of_bounds() {
+        let (mut node_manager, edge) = manager_with_edge(&[NormalReverse, NormalForward]);
+        let mut lane_def = node_manager.lane_def_mut(edge).unwrap();
+        assert_eq!(lane_def.set(2, Grass), Err(out_of_bounds(2, 2)));
+        assert_eq!(lane_def.insert(3, Grass), Err(out_of_bounds(3, 3)));
+        assert_eq!(lane_def.remove(2), Err(out_of_bounds(2, 2)));
+        assert_eq!(lane_def.lanes(), &[NormalReverse, NormalForward]);
+    }
+
+    #[test]
+    fn edits_reorder_lanes() {
+        let (mut node_manager, edge) = manager_with_edge(&[Sidewalk, NormalReverse, NormalForward, Sidewalk]);
+        let mut lane_def = node_manager.lane_def_mut(edge).unwrap();
+        assert_eq!(lane_def.set(0, Grass), Ok(Sidewalk));
+        assert_eq!(lane_def.remove(2), Ok(NormalForward));
+        lane_def.insert(1, NormalForward).unwrap();
+        assert_eq!(lane_def.lanes(), &[Grass, NormalForward, NormalReverse, Sidewalk]);
+        lane_def.commit().unwrap();
+        assert_eq!(node_manager.get_edge(edge).unwrap().get_lane_def().lanes().collect::<Vec<_>>(), [Grass, NormalForward, NormalReverse, Sidewalk]);
+    }
+
+    #[test]
+    fn commit_rejects_lanes_not_filling_the_edge() {
+        let (mut node_manager, edge) = manager_with_edge(&[NormalReverse, NormalForward]);
+        let mut lane_def = node_manager.lane_def_mut(edge).unwrap();
+        lane_def.set(0, Sidewalk).unwrap();
+        assert_eq!(lane_def.commit(), Err(LaneError::WidthMismatch {
+            expected: 4,
+            actual: 3,
+        }));
+        let mut lane_def = node_manager.lane_def_mut(edge).unwrap();
+        lane_def.insert(0, Grass).unwrap();
+        assert_eq!(lane_def.commit(), Err(LaneError::WidthMismatch {
+            expected: 4,
+            actual: 5,
+        }));
+        assert_eq!(node_manager.get_edge(edge).unwrap().get_lane_def().lanes().collect::<Vec<_>>(), [NormalReverse, NormalForward]);
+    }
+
+    #[test]
+    fn commit_updates_directionality() {
+        let (mut node_manager, edge) = manager_with_edge(&[NormalReverse, NormalForward]);
+        let mut lane_def = node_manager.lane_def_mut(edge).unwrap();
+        lane_def.set(0, NormalForward).unwrap();
+        lane_def.commit().unwrap();
+        let directionality = node_manager.get_edge(edge).unwrap().get_directionality();
+        assert!(directionality.is_one_way());
+        assert!(directionality.allows(LaneDirection::Forward));
+        assert!(!directionality.allows(LaneDirection::Reverse));
+    }
+
+    /// Checks that the heuristics never exceed the cost of travelling along any edge of the network.
+    fn assert_admissible(node_manager: &NodeManager) {
+        for edge in node_manager.get_edges() {
+            let (a, b) = edge.get_nodes().map(|node| node_manager.get_node_pos(node).unwrap());
+            assert!(node_manager.min_travel_time(a, b) <= edge.get_travel_time(node_manager));
+            for profile in RoutingProfile::iter() {
+                for (index, lane) in edge.get_lane_def().lanes().enumerate().filter(|(_, lane)| profile.permits(*lane)) {
+                    let Some(direction) = lane.direction() else {
+                        continue;
+                    };
+                    let lane = DirectedLane::new(LaneId::new(edge.get_id(), index as u8), direction);
+                    assert!(node_manager.min_lane_cost(a, b, profile) <= node_manager.lane_cost(lane, profile, None), "{profile:?} on {lane:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn heuristics_never_overestimate() {
+        let mut node_manager = NodeManager::new();
+        //The bus lanes of the avenues are the fastest way through the grid
+        assert_admissible(&node_manager);
+        let lane_types = LaneTypeManager::new();
+        let presets = RoadPresetLibrary::new(&lane_types);
+        let mut last = node_manager.add_node(Vec2::new(0.0, 5000.0));
+        for index in 0..presets.len() {
+            let preset = presets.get(index).unwrap();
+            let next = node_manager.add_node(Vec2::new((index + 1) as f32 * 100.0, 5000.0));
+            node_manager.make_edge(last, next, preset.get_speed(), preset.get_lane_def().clone());
+            last = next;
+        }
+        assert_admissible(&node_manager);
+    }
+
+    #[test]
+    fn traffic_side_belongs_to_the_manager() {
+        let mut left = NodeManager::new();
+        left.set_traffic_side(TrafficSide::Left);
+        let right = NodeManager::new();
+        assert_eq!(right.get_traffic_side(), TrafficSide::Right);
+        let node = right.get_nodes().find(|node| node.get_pos() == Vec2::ZERO).unwrap().get_id();
+        for lane in right.get_lane_graph().get_arrivals(node) {
+            let (right_from, right_to) = lane.get_centre_line(&right);
+            let (left_from, left_to) = lane.get_centre_line(&left);
+            let (from, to) = right.get_edge(lane.get_edge()).unwrap().get_endpoints(lane.get_direction()).map(|node| right.get_node_pos(node).unwrap());
+            assert!(((right_from + left_from) / 2.0).distance(from) < 1e-4);
+            assert!(((right_to + left_to) / 2.0).distance(to) < 1e-4);
+        }
+    }
+
+    #[test]
+    fn only_successful_commits_reset_connection_overrides() {
+        let mut node_manager = NodeManager::new();
+        let node = node_manager.get_nodes().find(|node| node.get_pos() == Vec2::ZERO).unwrap().get_id();
+        let incoming = node_manager.get_lane_graph().get_arrivals(node)[0];
+        let outgoing = *node_manager.get_lane_graph().get_departures(node).iter().find(|lane| lane.get_edge() != incoming.get_edge()).unwrap();
+        node_manager.set_connections(incoming, vec![outgoing]).unwrap();
+        let edge = incoming.get_edge();
+        let lanes = node_manager.get_edge(edge).unwrap().get_lane_def().lanes().collect::<Vec<_>>();
+
+        let mut lane_def = node_manager.lane_def_mut(edge).unwrap();
+        lane_def.remove(0).unwrap();
+        drop(lane_def);
+        assert!(node_manager.get_node(node).unwrap().has_connection_overrides());
+
+        let mut lane_def = node_manager.lane_def_mut(edge).unwrap();
+        lane_def.remove(0).unwrap();
+        assert!(lane_def.commit().is_err());
+        assert!(node_manager.get_node(node).unwrap().has_connection_overrides());
+        assert_eq!(node_manager.get_lane_graph().get_successors(incoming), &[outgoing]);
+
+        let mut lane_def = node_manager.lane_def_mut(edge).unwrap();
+        lane_def.set(0, lanes[0]).unwrap();
+        lane_def.commit().unwrap();
+        assert!(!node_manager.get_node(node).unwrap().has_connection_overrides());
+    }
+}
@@ -0,0 +1,313 @@
+use crate::math::if_else;
+use crate::node::a_star::AStarHeap;
+use crate::node::{EdgeId, NodeId, NodeManager};
+use crate::traffic::LaneType;
+use ggez::glam::Vec2;
+use rustc_hash::FxHashMap;
+
+/// Distance between a crosswalk and the carriageway of the widest road at its junction.
+const CROSSWALK_MARGIN: f32 = 2.0;
+/// Extra distance a route counts for every road it crosses, so pedestrians do not zigzag.
+const CROSSING_PENALTY: f32 = 10.0;
+
+/// The crossing of an edge next to one of its nodes.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Crosswalk {
+    node: NodeId,
+    edge: EdgeId,
+}
+
+impl Crosswalk {
+    pub fn new(node: NodeId, edge: EdgeId) -> Self {
+        Crosswalk {
+            node,
+            edge,
+        }
+    }
+
+    pub fn get_node(self) -> NodeId {
+        self.node
+    }
+
+    pub fn get_edge(self) -> EdgeId {
+        self.edge
+    }
+}
+
+/// The corner of a junction between an edge and the next edge counterclockwise around the node.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Corner {
+    node: NodeId,
+    edge: EdgeId,
+}
+
+/// A link of the [`SidewalkGraph`] by the corner it leaves and its index among the links of that corner.
+type LinkId = (Corner, usize);
+
+/// A way from one corner to another, along a sidewalk or over a crosswalk.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SidewalkLink {
+    pub to: Corner,
+    pub from_pos: Vec2,
+    pub to_pos: Vec2,
+    /// The crosswalk the link crosses, [`None`] along a sidewalk.
+    pub crosswalk: Option<Crosswalk>,
+}
+
+/// The network pedestrians walk on, derived from the sidewalks in the lane layouts of the edges. Sidewalks end at
+/// the corners of junctions, which are linked by a crosswalk over every edge of the junction.
+pub struct SidewalkGraph {
+    links: FxHashMap<Corner, Vec<SidewalkLink>>,
+    corners: FxHashMap<NodeId, Vec<Corner>>,
+    /// Every crosswalk with its ends.
+    crosswalks: Vec<(Crosswalk, Vec2, Vec2)>,
+    /// How far from every node with crosswalks they lie.
+    setbacks: FxHashMap<NodeId, f32>,
+}
+
+impl SidewalkGraph {
+    pub fn new(node_manager: &NodeManager) -> Self {
+        let mut junctions = FxHashMap::default();
+        for node in node_manager.get_nodes() {
+            junctions.insert(node.get_id(), Junction::new(node_manager, node.get_id()));
+        }
+        let mut graph = SidewalkGraph {
+            links: FxHashMap::default(),
+            corners: FxHashMap::default(),
+            crosswalks: vec![],
+            setbacks: junctions.iter().filter(|(_, junction)| junction.edges.len() >= 2).map(|(node, junction)| (*node, junction.setback)).collect(),
+        };
+        for (node, junction) in &junctions {
+            graph.corners.insert(*node, junction.edges.iter().map(|edge| Corner {
+                node: *node,
+                edge: *edge,
+            }).collect());
+            if junction.edges.len() < 2 {
+                continue;
+            }
+            for (index, edge) in junction.edges.iter().enumerate() {
+                let crosswalk = Crosswalk {
+                    node: *node,
+                    edge: *edge,
+                };
+                let clockwise = junction.corner_before(index);
+                let counterclockwise = Corner {
+                    node: *node,
+                    edge: *edge,
+                };
+                let (a, b) = (junction.kerb_point(node_manager, *edge, false), junction.kerb_point(node_manager, *edge, true));
+                graph.link(clockwise, counterclockwise, a, b, Some(crosswalk));
+                graph.crosswalks.push((crosswalk, a, b));
+            }
+        }
+        for edge in node_manager.get_edges() {
+            let (a, b) = edge.get_nodes();
+            let (a_pos, b_pos) = (node_manager.get_node_pos(a).unwrap(), node_manager.get_node_pos(b).unwrap());
+            let along = (b_pos - a_pos).normalize_or_zero();
+            for slot in edge.get_lane_def().layout(node_manager.get_traffic_side()).lanes().iter().filter(|slot| slot.get_type() == LaneType::Sidewalk) {
+                let left = slot.get_offset() > 0.0;
+                let offset = along.perp() * slot.get_offset();
+                //Left of the edge seen from `a` is right of it seen from `b`
+                let corner_at = |node: NodeId, counterclockwise: bool| {
+                    let junction = &junctions[&node];
+                    let index = junction.edges.iter().position(|other| *other == edge.get_id()).unwrap();
+                    if_else!(counterclockwise => junction.edges[index] ; junction.corner_before(index).edge)
+                };
+                let from = a_pos + offset + along * junctions[&a].setback;
+                let to = b_pos + offset - along * junctions[&b].setback;
+                graph.link(Corner {
+                    node: a,
+                    edge: corner_at(a, left),
+                }, Corner {
+                    node: b,
+                    edge: corner_at(b, !left),
+                }, from, to, None);
+            }
+        }
+        graph
+    }
+
+    /// Links both corners in both directions.
+    fn link(&mut self, a: Corner, b: Corner, a_pos: Vec2, b_pos: Vec2, crosswalk: Option<Crosswalk>) {
+        self.links.entry(a).or_default().push(SidewalkLink {
+            to: b,
+            from_pos: a_pos,
+            to_pos: b_pos,
+            crosswalk,
+        });
+        self.links.entry(b).or_default().push(SidewalkLink {
+            to: a,
+            from_pos: b_pos,
+            to_pos: a_pos,
+            crosswalk,
+        });
+    }
+
+    /// Every crosswalk with the positions of its ends.
+    pub fn get_crosswalks(&self) -> &[(Crosswalk, Vec2, Vec2)] {
+        &self.crosswalks
+    }
+
+    /// How far from the node its crosswalks lie, if it has any.
+    pub fn get_setback(&self, node: NodeId) -> Option<f32> {
+        self.setbacks.get(&node).copied()
+    }
+
+    /// Finds the shortest walk from any corner of `start` to any corner of `goal`, as the links to follow in order. The
+    /// walks across corners between links count like the links, as pedestrians walk them too.
+    pub fn route(&self, node_manager: &NodeManager, start: NodeId, goal: NodeId) -> Option<Vec<SidewalkLink>> {
+        let goal_pos = node_manager.get_node_pos(goal)?;
+        //Walks end where a link reaches a corner of the goal, at most this far from the goal
+        let goal_radius = self.corners.get(&goal)?.iter().flat_map(|corner| self.get_links(*corner)).map(|link| link.from_pos.distance(goal_pos)).fold(0.0, f32::max);
+        self.search(start, goal, |pos| (pos.distance(goal_pos) - goal_radius).max(0.0))
+    }
+
+    /// A* over the links, with the heuristic `h` estimating the rest of the walk from a position.
+    fn search(&self, start: NodeId, goal: NodeId, h: impl Fn(Vec2) -> f32) -> Option<Vec<SidewalkLink>> {
+        if start == goal {
+            return Some(vec![]);
+        }
+        let mut open_set = AStarHeap::new();
+        let mut came_from = FxHashMap::<LinkId, LinkId>::default();
+        let mut g_score = FxHashMap::<LinkId, f32>::default();
+        for corner in self.corners.get(&start)? {
+            for (index, link) in self.get_links(*corner).iter().enumerate() {
+                g_score.insert((*corner, index), Self::link_cost(link));
+                open_set.push((*corner, index), Self::link_cost(link) + h(link.to_pos));
+            }
+        }
+        while let Some(current) = open_set.pop() {
+            let link = self.get_link(current);
+            if link.to.node == goal {
+                let mut route = vec![*link];
+                let mut id = current;
+                while let Some(previous) = came_from.get(&id) {
+                    route.push(*self.get_link(*previous));
+                    id = *previous;
+                }
+                route.reverse();
+                return Some(route);
+            }
+            for (index, next) in self.get_links(link.to).iter().enumerate() {
+                let id = (link.to, index);
+                let tentative_g_score = g_score[&current] + link.to_pos.distance(next.from_pos) + Self::link_cost(next);
+                if tentative_g_score < *g_score.get(&id).unwrap_or(&f32::INFINITY) {
+                    came_from.insert(id, current);
+                    g_score.insert(id, tentative_g_score);
+                    open_set.push(id, tentative_g_score + h(next.to_pos));
+                }
+            }
+        }
+        None
+    }
+
+    fn get_links(&self, corner: Corner) -> &[SidewalkLink] {
+        self.links.get(&corner).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    fn get_link(&self, (corner, index): LinkId) -> &SidewalkLink {
+        &self.links[&corner][index]
+    }
+
+    /// What walking the link counts for when routing, without reaching its start.
+    fn link_cost(link: &SidewalkLink) -> f32 {
+        link.from_pos.distance(link.to_pos) + if_else!(link.crosswalk.is_some() => CROSSING_PENALTY ; 0.0)
+    }
+}
+
+/// The edges around a node in counterclockwise order, and how far from the node its crosswalks lie.
+struct Junction {
+    node: NodeId,
+    edges: Vec<EdgeId>,
+    setback: f32,
+}
+
+impl Junction {
+    fn new(node_manager: &NodeManager, node: NodeId) -> Self {
+        let pos = node_manager.get_node_pos(node).unwrap();
+        let mut edges = node_manager.get_node(node).unwrap().get_edges().to_vec();
+        let angle = |edge: &EdgeId| (node_manager.get_node_pos(node_manager.get_edge(*edge).unwrap().get_other_node(node)).unwrap() - pos).to_angle();
+        edges.sort_by(|a, b| angle(a).total_cmp(&angle(b)));
+        let setback = edges.iter().map(|edge| node_manager.get_edge(*edge).unwrap().get_lane_def().layout(node_manager.get_traffic_side()).get_width() / 2.0).fold(0.0, f32::max) + CROSSWALK_MARGIN;
+        Junction {
+            node,
+            edges,
+            setback,
+        }
+    }
+
+    /// The corner clockwise of the edge with the given index.
+    fn corner_before(&self, index: usize) -> Corner {
+        Corner {
+            node: self.node,
+            edge: self.edges[(index + self.edges.len() - 1) % self.edges.len()],
+        }
+    }
+
+    /// Where the crosswalk over the edge meets the kerb on its counterclockwise or clockwise side: at the outermost
+    /// sidewalk on that side, or at the border of the edge if there is none.
+    fn kerb_point(&self, node_manager: &NodeManager, edge: EdgeId, counterclockwise: bool) -> Vec2 {
+        let edge_ref = node_manager.get_edge(edge).unwrap();
+        let pos = node_manager.get_node_pos(self.node).unwrap();
+        let outward = (node_manager.get_node_pos(edge_ref.get_other_node(self.node)).unwrap() - pos).normalize_or_zero();
+        //Offsets in the layout are to the left of the edge seen from its first node
+        let sign = if_else!((edge_ref.get_nodes().0 == self.node) == counterclockwise => 1.0 ; -1.0);
+        let layout = edge_ref.get_lane_def().layout(node_manager.get_traffic_side());
+        let offset = layout.lanes().iter()
+            .filter(|slot| slot.get_type() == LaneType::Sidewalk)
+            .map(|slot| slot.get_offset() * sign)
+            .filter(|offset| *offset > 0.0)
+            .fold(None, |outermost: Option<f32>, offset| Some(outermost.map_or(offset, |outermost| outermost.max(offset))))
+            .unwrap_or(layout.get_width() / 2.0);
+        pos + outward * self.setback + outward.perp() * if_else!(counterclockwise => offset ; -offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traffic::LaneDefinition;
+    use crate::traffic::LaneType::{NormalForward, NormalReverse, Sidewalk};
+
+    /// What the router counts for walking the links one after another, crossing the corners between them.
+    fn walk_cost(links: &[SidewalkLink]) -> f32 {
+        links.iter().map(SidewalkGraph::link_cost).sum::<f32>() + links.windows(2).map(|pair| pair[0].to_pos.distance(pair[1].from_pos)).sum::<f32>()
+    }
+
+    /// Checks that the routes between the nodes are as short as the ones found without a heuristic.
+    fn assert_shortest(node_manager: &NodeManager, nodes: &[NodeId]) {
+        let graph = node_manager.get_sidewalk_graph();
+        for start in nodes {
+            for goal in nodes {
+                let route = graph.route(node_manager, *start, *goal).unwrap();
+                let shortest = graph.search(*start, *goal, |_| 0.0).unwrap();
+                assert!(walk_cost(&route) <= walk_cost(&shortest) + 1e-3, "{start:?} to {goal:?}: {} instead of {}", walk_cost(&route), walk_cost(&shortest));
+            }
+        }
+    }
+
+    #[test]
+    fn routes_are_the_shortest_walks() {
+        let node_manager = NodeManager::new();
+        let nodes = node_manager.get_nodes().map(|node| node.get_id()).step_by(9).collect::<Vec<_>>();
+        assert_shortest(&node_manager, &nodes);
+    }
+
+    #[test]
+    fn routes_to_corners_far_from_the_goal_are_the_shortest_walks() {
+        //The corners between the wide road and the street are much further from the goal than the others
+        let wide = LaneDefinition::from_lanes(&[Sidewalk, NormalReverse, NormalReverse, NormalReverse, NormalForward, NormalForward, NormalForward, Sidewalk]).unwrap();
+        let narrow = LaneDefinition::from_lanes(&[Sidewalk, NormalReverse, NormalForward, Sidewalk]).unwrap();
+        for offset in 0..40 {
+            let mut node_manager = NodeManager::new();
+            let start = node_manager.add_node(Vec2::new(0.0, 5000.0));
+            let goal = node_manager.add_node(Vec2::new(400.0, 5000.0));
+            let (a, b) = (node_manager.add_node(Vec2::new(200.0, 4960.0)), node_manager.add_node(Vec2::new(200.0, 5000.0 + offset as f32)));
+            node_manager.make_edge(start, a, 1.0, narrow.clone());
+            node_manager.make_edge(a, goal, 1.0, wide.clone());
+            node_manager.make_edge(start, b, 1.0, narrow.clone());
+            node_manager.make_edge(b, goal, 1.0, narrow.clone());
+            assert_shortest(&node_manager, &[start, goal, a, b]);
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use crate::float::F32;
+use crate::math::if_else;
+use crate::node::{EdgeId, NodeId, NodeManager};
+use crate::sim::demand::DemandMatrix;
+use crate::traffic::{LaneDirection, RoutingProfile};
+use rustc_hash::FxHashMap;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// Parameters of the BPR volume-delay function `t = t0 * (1 + ALPHA * (v / c)^BETA)`.
+const BPR_ALPHA: f32 = 0.15;
+const BPR_BETA: i32 = 4;
+/// Frank-Wolfe stops once the relative gap between the assigned flows and the shortest paths falls below this.
+const GAP_TOLERANCE: f32 = 1e-4;
+const MAX_ITERATIONS: u32 = 200;
+/// Bisection steps of the line search for the step size.
+const LINE_SEARCH_STEPS: u32 = 24;
+
+/// A direction of an edge in the assignment network.
+struct Link {
+    edge: EdgeId,
+    direction: LaneDirection,
+    from: usize,
+    to: usize,
+    /// Travel time without traffic, in ticks.
+    free_flow_time: f32,
+    /// Vehicles per hour.
+    capacity: f32,
+}
+
+impl Link {
+    /// Travel time at the given volume according to the BPR function.
+    fn travel_time(&self, volume: f32) -> f32 {
+        self.free_flow_time * (1.0 + BPR_ALPHA * (volume / self.capacity).powi(BPR_BETA))
+    }
+}
+
+/// The edges a profile can use as links between the nodes. Junctions are not modelled, so every link leaving a node
+/// may follow every link arriving at it.
+struct Network {
+    nodes: FxHashMap<NodeId, usize>,
+    links: Vec<Link>,
+    /// The links leaving every node.
+    outgoing: Vec<Vec<usize>>,
+}
+
+impl Network {
+    fn new(node_manager: &NodeManager, profile: RoutingProfile) -> Self {
+        let nodes = node_manager.get_nodes().enumerate().map(|(index, node)| (node.get_id(), index)).collect::<FxHashMap<_, _>>();
+        let mut network = Network {
+            outgoing: vec![vec![]; nodes.len()],
+            nodes,
+            links: vec![],
+        };
+        for edge in node_manager.get_edges() {
+            for direction in [LaneDirection::Forward, LaneDirection::Reverse] {
+                let capacity = edge.get_lane_def().capacity(direction, profile);
+                if capacity <= 0.0 {
+                    continue;
+                }
+                let (from, to) = edge.get_endpoints(direction);
+                network.outgoing[network.nodes[&from]].push(network.links.len());
+                network.links.push(Link {
+                    edge: edge.get_id(),
+                    direction,
+                    from: network.nodes[&from],
+                    to: network.nodes[&to],
+                    free_flow_time: edge.get_travel_time(node_manager),
+                    capacity,
+                });
+            }
+        }
+        network
+    }
+
+    /// Finds the fastest path from the origin to every node for the given link travel times, as the link each node is
+    /// reached by.
+    fn shortest_paths(&self, origin: usize, travel_times: &[f32]) -> Vec<Option<usize>> {
+        let mut distance = vec![f32::INFINITY; self.outgoing.len()];
+        let mut reached_by = vec![None; self.outgoing.len()];
+        let mut open_set = BinaryHeap::new();
+        distance[origin] = 0.0;
+        open_set.push(Reverse((F32::new(0.0), origin)));
+        while let Some(Reverse((node_distance, node))) = open_set.pop() {
+            if *node_distance > distance[node] {
+                continue;
+            }
+            for link in &self.outgoing[node] {
+                let next = self.links[*link].to;
+                let next_distance = *node_distance + travel_times[*link];
+                if next_distance < distance[next] {
+                    distance[next] = next_distance;
+                    reached_by[next] = Some(*link);
+                    open_set.push(Reverse((F32::new(next_distance), next)));
+                }
+            }
+        }
+        reached_by
+    }
+
+    /// Loads every trip onto the fastest path for the given travel times. Returns the volume of every link and the
+    /// trips per hour that cannot reach their destination.
+    fn all_or_nothing(&self, demand: &FxHashMap<usize, Vec<(usize, f32)>>, travel_times: &[f32]) -> (Vec<f32>, f32) {
+        let mut volumes = vec![0.0; self.links.len()];
+        let mut unroutable = 0.0;
+        for (origin, destinations) in demand {
+            let reached_by = self.shortest_paths(*origin, travel_times);
+            for (destination, trips) in destinations {
+                let mut node = *destination;
+                let mut path = vec![];
+                while node != *origin {
+                    let Some(link) = reached_by[node] else {
+                        break;
+                    };
+                    path.push(link);
+                    node = self.links[link].from;
+                }
+                if node != *origin {
+                    unroutable += trips;
+                    continue;
+                }
+                for link in path {
+                    volumes[link] += trips;
+                }
+            }
+        }
+        (volumes, unroutable)
+    }
+
+    fn travel_times(&self, volumes: &[f32]) -> Vec<f32> {
+        self.links.iter().zip(volumes).map(|(link, volume)| link.travel_time(*volume)).collect()
+    }
+}
+
+/// The assigned traffic on a direction of an edge.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LinkVolume {
+    pub edge: EdgeId,
+    pub direction: LaneDirection,
+    /// Vehicles per hour.
+    pub volume: f32,
+    /// Vehicles per hour, derived from the lanes of the edge.
+    pub capacity: f32,
+    /// Travel time at the assigned volume, in ticks.
+    pub travel_time: f32,
+}
+
+impl LinkVolume {
+    pub fn volume_capacity_ratio(&self) -> f32 {
+        self.volume / self.capacity
+    }
+}
+
+/// A user equilibrium of the trips of a demand matrix: no trip could reach its destination faster on another path.
+pub struct Assignment {
+    links: Vec<LinkVolume>,
+    iterations: u32,
+    relative_gap: f32,
+    unroutable: f32,
+}
+
+impl Assignment {
+    /// Every direction of an edge the profile can use, with its traffic.
+    pub fn get_links(&self) -> &[LinkVolume] {
+        &self.links
+    }
+
+    pub fn get_iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    /// How much longer the assigned paths take than the fastest paths at the assigned volumes, relative to the former.
+    pub fn get_relative_gap(&self) -> f32 {
+        self.relative_gap
+    }
+
+    /// Trips per hour that cannot reach their destination.
+    pub fn get_unroutable(&self) -> f32 {
+        self.unroutable
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum AssignmentError {
+    /// The matrix has no trips of the profile between nodes of the network.
+    NoDemand(RoutingProfile),
+}
+
+impl Display for AssignmentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssignmentError::NoDemand(profile) => write!(f, "No {profile:?} trips to assign"),
+        }
+    }
+}
+
+impl Error for AssignmentError {}
+
+/// Assigns the hourly trips of the profile in the matrix to the network by iterating Frank-Wolfe to the user
+/// equilibrium, without simulating any vehicle. Link travel times follow the BPR function with capacities derived
+/// from the lane definitions of the edges.
+pub fn assign(node_manager: &NodeManager, matrix: &DemandMatrix, profile: RoutingProfile) -> Result<Assignment, AssignmentError> {
+    let network = Network::new(node_manager, profile);
+    let mut demand = FxHashMap::<usize, Vec<(usize, f32)>>::default();
+    for pair in matrix.get_pairs().iter().filter(|pair| pair.profile == profile && pair.trips_per_hour > 0.0 && pair.origin != pair.destination) {
+        if let (Some(origin), Some(destination)) = (network.nodes.get(&pair.origin), network.nodes.get(&pair.destination)) {
+            demand.entry(*origin).or_default().push((*destination, pair.trips_per_hour));
+        }
+    }
+    if demand.is_empty() {
+        return Err(AssignmentError::NoDemand(profile));
+    }
+    let free_flow_times = network.links.iter().map(|link| link.free_flow_time).collect::<Vec<_>>();
+    let (mut volumes, unroutable) = network.all_or_nothing(&demand, &free_flow_times);
+    let mut iterations = 0;
+    let mut relative_gap = f32::INFINITY;
+    while iterations < MAX_ITERATIONS {
+        iterations += 1;
+        let travel_times = network.travel_times(&volumes);
+        let (targets, _) = network.all_or_nothing(&demand, &travel_times);
+        let assigned = volumes.iter().zip(&travel_times).map(|(volume, time)| volume * time).sum::<f32>();
+        let fastest = targets.iter().zip(&travel_times).map(|(volume, time)| volume * time).sum::<f32>();
+        relative_gap = if_else!(assigned > 0.0 => (assigned - fastest) / assigned ; 0.0);
+        if relative_gap < GAP_TOLERANCE {
+            break;
+        }
+        let step = line_search(&network, &volumes, &targets);
+        for (volume, target) in volumes.iter_mut().zip(&targets) {
+            *volume += step * (target - *volume);
+        }
+    }
+    let travel_times = network.travel_times(&volumes);
+    Ok(Assignment {
+        links: network.links.iter().zip(volumes.iter().zip(travel_times)).map(|(link, (volume, travel_time))| LinkVolume {
+            edge: link.edge,
+            direction: link.direction,
+            volume: *volume,
+            capacity: link.capacity,
+            travel_time,
+        }).collect(),
+        iterations,
+        relative_gap,
+        unroutable,
+    })
+}
+
+/// Finds the step from the current volumes towards the all-or-nothing target volumes minimising the Beckmann
+/// objective, by bisecting on its derivative.
+fn line_search(network: &Network, volumes: &[f32], targets: &[f32]) -> f32 {
+    let derivative = |step: f32| network.links.iter().zip(volumes.iter().zip(targets))
+        .map(|(link, (volume, target))| (target - volume) * link.travel_time(volume + step * (target - volume)))
+        .sum::<f32>();
+    if derivative(1.0) <= 0.0 {
+        return 1.0;
+    }
+    let (mut low, mut high) = (0.0, 1.0);
+    for _ in 0..LINE_SEARCH_STEPS {
+        let middle = (low + high) / 2.0;
+        if derivative(middle) > 0.0 {
+            high = middle;
+        } //
+        else {
+            low = middle;
+        }
+    }
+    (low + high) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::demand::OdPair;
+    use crate::traffic::LaneDefinition;
+    use crate::traffic::LaneType::{Grass, NormalForward};
+    use ggez::glam::Vec2;
+
+    /// One-way roads away from the default grid from an origin to a destination, directly and by a detour. Returns the
+    /// manager, the origin, the destination, the direct edge and the edges of the detour.
+    fn two_routes() -> (NodeManager, NodeId, NodeId, EdgeId, [EdgeId; 2]) {
+        let mut node_manager = NodeManager::new();
+        let [origin, detour, destination] = [Vec2::new(0.0, 5000.0), Vec2::new(500.0, 5300.0), Vec2::new(1000.0, 5000.0)].map(|pos| node_manager.add_node(pos));
+        let mut road = |from, to| node_manager.make_edge(from, to, 1.0, LaneDefinition::from_lanes(&[Grass, NormalForward, Grass]).unwrap());
+        let direct = road(origin, destination);
+        let detour = [road(origin, detour), road(detour, destination)];
+        (node_manager, origin, destination, direct, detour)
+    }
+
+    fn trips(origin: NodeId, destination: NodeId, trips_per_hour: f32, profile: RoutingProfile) -> OdPair {
+        OdPair {
+            origin,
+            destination,
+            trips_per_hour,
+            profile,
+            parking_minutes: None,
+        }
+    }
+
+    fn link(assignment: &Assignment, edge: EdgeId) -> LinkVolume {
+        *assignment.get_links().iter().find(|link| link.edge == edge && link.direction == LaneDirection::Forward).unwrap()
+    }
+
+    #[test]
+    fn light_traffic_takes_the_fastest_route() {
+        let (node_manager, origin, destination, direct, detour) = two_routes();
+        let assignment = assign(&node_manager, &DemandMatrix::new(vec![trips(origin, destination, 10.0, RoutingProfile::Car)]), RoutingProfile::Car).unwrap();
+        assert_eq!(link(&assignment, direct).volume, 10.0);
+        assert_eq!(link(&assignment, detour[0]).volume, 0.0);
+        assert!(assignment.get_relative_gap() < GAP_TOLERANCE);
+    }
+
+    #[test]
+    fn heavy_traffic_reaches_equilibrium_between_the_routes() {
+        let (node_manager, origin, destination, direct, detour) = two_routes();
+        let capacity = node_manager.get_edge(direct).unwrap().get_lane_def().capacity(LaneDirection::Forward, RoutingProfile::Car);
+        let demand = 3.0 * capacity;
+        let assignment = assign(&node_manager, &DemandMatrix::new(vec![trips(origin, destination, demand, RoutingProfile::Car)]), RoutingProfile::Car).unwrap();
+        assert!(assignment.get_relative_gap() < GAP_TOLERANCE, "{}", assignment.get_relative_gap());
+        assert!(assignment.get_iterations() < MAX_ITERATIONS);
+        assert_eq!(assignment.get_unroutable(), 0.0);
+        let (direct, detour) = (link(&assignment, direct), detour.map(|edge| link(&assignment, edge)));
+        //Every trip takes one of the routes, and both take as long
+        assert!((direct.volume + detour[0].volume - demand).abs() < demand * 1e-4);
+        assert!((detour[0].volume - detour[1].volume).abs() < demand * 1e-4);
+        assert!(detour[0].volume > 0.1 * demand && direct.volume > detour[0].volume);
+        let detour_time = detour[0].travel_time + detour[1].travel_time;
+        assert!((direct.travel_time - detour_time).abs() < direct.travel_time * 1e-3, "{} {detour_time}", direct.travel_time);
+        assert_eq!(direct.volume_capacity_ratio(), direct.volume / capacity);
+    }
+
+    #[test]
+    fn line_search_minimises_the_objective() {
+        let (node_manager, _, _, direct, detour) = two_routes();
+        let network = Network::new(&node_manager, RoutingProfile::Car);
+        let index = |edge: EdgeId| network.links.iter().position(|link| link.edge == edge).unwrap();
+        let demand = 3.0 * network.links[index(direct)].capacity;
+        //Shifting from the direct road onto the detour stops once both take as long
+        let mut volumes = vec![0.0; network.links.len()];
+        volumes[index(direct)] = demand;
+        let mut targets = vec![0.0; network.links.len()];
+        for edge in detour {
+            targets[index(edge)] = demand;
+        }
+        let step = line_search(&network, &volumes, &targets);
+        assert!(step > 0.0 && step < 1.0);
+        let shifted = volumes.iter().zip(&targets).map(|(volume, target)| volume + step * (target - volume)).collect::<Vec<_>>();
+        let times = network.travel_times(&shifted);
+        let detour_time = times[index(detour[0])] + times[index(detour[1])];
+        assert!((times[index(direct)] - detour_time).abs() < detour_time * 1e-4, "{} {detour_time}", times[index(direct)]);
+        //Coming from the detour stops at the same split, and light traffic moves onto the faster road completely
+        assert!((line_search(&network, &targets, &volumes) - (1.0 - step)).abs() < 1e-4);
+        let light = volumes.iter().map(|volume| volume / 100.0).collect::<Vec<_>>();
+        assert_eq!(line_search(&network, &targets.iter().map(|target| target / 100.0).collect::<Vec<_>>(), &light), 1.0);
+    }
+
+    #[test]
+    fn trips_without_a_route_are_unroutable() {
+        let (node_manager, origin, destination, ..) = two_routes();
+        let matrix = DemandMatrix::new(vec![trips(origin, destination, 10.0, RoutingProfile::Car), trips(destination, origin, 5.0, RoutingProfile::Car)]);
+        assert_eq!(assign(&node_manager, &matrix, RoutingProfile::Car).unwrap().get_unroutable(), 5.0);
+    }
+
+    #[test]
+    fn assignments_need_trips_of_the_profile() {
+        let (node_manager, origin, destination, ..) = two_routes();
+        for pairs in [vec![], vec![trips(origin, destination, 10.0, RoutingProfile::Bus)], vec![trips(origin, destination, 0.0, RoutingProfile::Car)], vec![trips(origin, origin, 10.0, RoutingProfile::Car)]] {
+            assert_eq!(assign(&node_manager, &DemandMatrix::new(pairs.clone()), RoutingProfile::Car).err(), Some(AssignmentError::NoDemand(RoutingProfile::Car)), "{pairs:?}");
+        }
+    }
+}
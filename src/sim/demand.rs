@@ -0,0 +1,290 @@
+use crate::node::{NodeId, NodeManager};
+use crate::sim::Simulation;
+use crate::traffic::RoutingProfile;
+use ggez::glam::Vec2;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use strum::IntoEnumIterator;
+
+/// Share of the peak hour demand travelling in every hour of the day, with morning and evening commuter peaks.
+pub const COMMUTER_PROFILE: [f32; 24] = [
+    0.05, 0.03, 0.02, 0.02, 0.04, 0.15, 0.45, 0.85, 1.0, 0.7, 0.5, 0.5,
+    0.55, 0.5, 0.5, 0.6, 0.8, 1.0, 0.85, 0.55, 0.35, 0.25, 0.15, 0.1,
+];
+
+/// Trips per hour between two nodes, at a time-of-day factor of 1.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct OdPair {
+    pub origin: NodeId,
+    pub destination: NodeId,
+    pub trips_per_hour: f32,
+    pub profile: RoutingProfile,
+    /// Simulated minutes the trips park near the destination for, [`None`] if they leave the network there.
+    pub parking_minutes: Option<f32>,
+}
+
+/// An origin-destination matrix between nodes, stored sparsely.
+#[derive(Clone, Default, Debug)]
+pub struct DemandMatrix {
+    pairs: Vec<OdPair>,
+}
+
+impl DemandMatrix {
+    pub fn new(pairs: Vec<OdPair>) -> Self {
+        DemandMatrix {
+            pairs,
+        }
+    }
+
+    /// Reads a matrix from CSV, one origin-destination pair per line:
+    /// ```text
+    /// origin_x,origin_y,destination_x,destination_y,trips_per_hour,profile,parking_minutes
+    /// -500,0,500,0,120,Car,30
+    /// ```
+    /// Origins and destinations are the nodes at the given world positions. The profile column is optional and
+    /// defaults to `Car`. Trips with a parking duration search for a parking spot near the destination, trips without
+    /// one leave the network there. A header line starting with `origin`, empty lines and lines starting with `#` are
+    /// ignored.
+    pub fn from_csv(source: &str, node_manager: &NodeManager) -> Result<Self, DemandLoadError> {
+        let mut pairs = vec![];
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("origin") {
+                continue;
+            }
+            let error = |kind| DemandLoadError {
+                line: index + 1,
+                kind,
+            };
+            let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+            if !(5..=7).contains(&fields.len()) {
+                return Err(error(DemandErrorKind::ColumnCount(fields.len())));
+            }
+            let number = |field: &str| field.parse::<f32>().ok().filter(|number| number.is_finite()).ok_or_else(|| error(DemandErrorKind::InvalidNumber(field.to_string())));
+            let node = |x: &str, y: &str| {
+                let pos = Vec2::new(number(x)?, number(y)?);
+                node_manager.try_node_collision(pos).ok_or_else(|| error(DemandErrorKind::NoNode(pos)))
+            };
+            let trips_per_hour = number(fields[4])?;
+            if trips_per_hour < 0.0 {
+                return Err(error(DemandErrorKind::InvalidNumber(fields[4].to_string())));
+            }
+            let profile = match fields.get(5).filter(|name| !name.is_empty()) {
+                Some(name) => RoutingProfile::iter().find(|profile| format!("{profile:?}").eq_ignore_ascii_case(name)).ok_or_else(|| error(DemandErrorKind::UnknownProfile(name.to_string())))?,
+                None => RoutingProfile::Car,
+            };
+            let parking_minutes = match fields.get(6).filter(|minutes| !minutes.is_empty()) {
+                Some(minutes) => Some(number(minutes)?.max(0.0)),
+                None => None,
+            };
+            pairs.push(OdPair {
+                origin: node(fields[0], fields[1])?,
+                destination: node(fields[2], fields[3])?,
+                trips_per_hour,
+                profile,
+                parking_minutes,
+            });
+        }
+        Ok(DemandMatrix::new(pairs))
+    }
+
+    pub fn get_pairs(&self) -> &[OdPair] {
+        &self.pairs
+    }
+
+    /// Trips per hour of the whole matrix at a time-of-day factor of 1.
+    pub fn total_trips_per_hour(&self) -> f32 {
+        self.pairs.iter().map(|pair| pair.trips_per_hour).sum()
+    }
+}
+
+/// Spawns the trips of a [`DemandMatrix`] as simulated time passes, scaled by an hourly time-of-day profile. Trips of
+/// every pair are spread evenly over time, so runs are reproducible.
+pub struct DemandModel {
+    matrix: DemandMatrix,
+    hourly_factors: [f32; 24],
+    ticks_per_hour: u64,
+    /// Hour of the day at the first tick of the simulation.
+    start_hour: f32,
+    enabled: bool,
+    /// Fraction of a trip accumulated for every pair.
+    pending: Vec<f32>,
+    spawned: u32,
+    /// Trips that could not be routed.
+    failed: u32,
+}
+
+impl DemandModel {
+    /// A disabled model scaling the matrix by `hourly_factors`, one for every hour of the day. A simulated hour lasts
+    /// `ticks_per_hour` ticks, so the day can be compressed.
+    pub fn new(matrix: DemandMatrix, hourly_factors: [f32; 24], start_hour: f32, ticks_per_hour: u64) -> Self {
+        let pending = vec![0.0; matrix.pairs.len()];
+        DemandModel {
+            matrix,
+            hourly_factors,
+            ticks_per_hour: ticks_per_hour.max(1),
+            start_hour: start_hour.rem_euclid(24.0),
+            enabled: false,
+            pending,
+            spawned: 0,
+            failed: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn get_matrix(&self) -> &DemandMatrix {
+        &self.matrix
+    }
+
+    pub fn get_spawned(&self) -> u32 {
+        self.spawned
+    }
+
+    pub fn get_failed(&self) -> u32 {
+        self.failed
+    }
+
+    /// Hour of the day at the given tick, between 0 and 24.
+    pub fn hour_of_day(&self, tick: u64) -> f32 {
+        (self.start_hour + tick as f32 / self.ticks_per_hour as f32).rem_euclid(24.0)
+    }
+
+    /// Demand factor of the hour of the day at the given tick.
+    pub fn factor(&self, tick: u64) -> f32 {
+        self.hourly_factors[(self.hour_of_day(tick) as usize).min(23)]
+    }
+
+    /// Spawns the trips due in the coming tick of the simulation. Vehicles are removed by the simulation once they
+    /// arrive.
+    pub fn tick(&mut self, node_manager: &NodeManager, simulation: &mut Simulation) {
+        if !self.enabled {
+            return;
+        }
+        let factor = self.factor(simulation.get_ticks());
+        for (pair, pending) in self.matrix.pairs.iter().zip(&mut self.pending) {
+            *pending += pair.trips_per_hour * factor / self.ticks_per_hour as f32;
+            while *pending >= 1.0 {
+                *pending -= 1.0;
+                let spawned = match (pair.parking_minutes, pair.profile) {
+                    (_, RoutingProfile::Pedestrian) => simulation.spawn_pedestrian(node_manager, pair.origin, pair.destination).is_some(),
+                    (Some(minutes), _) => simulation.spawn_parking(node_manager, pair.origin, pair.destination, pair.profile, (minutes * self.ticks_per_hour as f32 / 60.0) as u32).is_some(),
+                    (None, _) => simulation.spawn(node_manager, pair.origin, pair.destination, pair.profile).is_some(),
+                };
+                if spawned {
+                    self.spawned += 1;
+                } //
+                else {
+                    self.failed += 1;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct DemandLoadError {
+    line: usize,
+    kind: DemandErrorKind,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum DemandErrorKind {
+    ColumnCount(usize),
+    InvalidNumber(String),
+    NoNode(Vec2),
+    UnknownProfile(String),
+}
+
+impl Display for DemandLoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Line {}: ", self.line)?;
+        match &self.kind {
+            DemandErrorKind::ColumnCount(count) => write!(f, "Expected 5 to 7 columns, got {count}"),
+            DemandErrorKind::InvalidNumber(number) => write!(f, "`{number}` is not a valid number"),
+            DemandErrorKind::NoNode(pos) => write!(f, "There is no node at {}, {}", pos.x, pos.y),
+            DemandErrorKind::UnknownProfile(profile) => write!(f, "Unknown routing profile `{profile}`"),
+        }
+    }
+}
+
+impl Error for DemandLoadError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(line: usize, kind: DemandErrorKind) -> Option<DemandLoadError> {
+        Some(DemandLoadError {
+            line,
+            kind,
+        })
+    }
+
+    #[test]
+    fn reads_pairs() {
+        let node_manager = NodeManager::new();
+        let source = "origin_x,origin_y,destination_x,destination_y,trips_per_hour,profile,parking_minutes\n# Commuters\n\n-500,0,500,0,120\n 0, -500 , 0,500,30,bus,\n-500,-500,500,500,2.5,Cyclist,-5\n100,100,-100,-100,0,,45";
+        let matrix = DemandMatrix::from_csv(source, &node_manager).unwrap();
+        let node = |x, y| node_manager.try_node_collision(Vec2::new(x, y)).unwrap();
+        assert_eq!(matrix.get_pairs(), [
+            OdPair {
+                origin: node(-500.0, 0.0),
+                destination: node(500.0, 0.0),
+                trips_per_hour: 120.0,
+                profile: RoutingProfile::Car,
+                parking_minutes: None,
+            },
+            OdPair {
+                origin: node(0.0, -500.0),
+                destination: node(0.0, 500.0),
+                trips_per_hour: 30.0,
+                profile: RoutingProfile::Bus,
+                parking_minutes: None,
+            },
+            OdPair {
+                origin: node(-500.0, -500.0),
+                destination: node(500.0, 500.0),
+                trips_per_hour: 2.5,
+                profile: RoutingProfile::Cyclist,
+                parking_minutes: Some(0.0),
+            },
+            OdPair {
+                origin: node(100.0, 100.0),
+                destination: node(-100.0, -100.0),
+                trips_per_hour: 0.0,
+                profile: RoutingProfile::Car,
+                parking_minutes: Some(45.0),
+            },
+        ]);
+        assert_eq!(matrix.total_trips_per_hour(), 152.5);
+    }
+
+    #[test]
+    fn rejects_wrong_column_counts() {
+        let node_manager = NodeManager::new();
+        assert_eq!(DemandMatrix::from_csv("# Comment\n-500,0,500,0", &node_manager).err(), error(2, DemandErrorKind::ColumnCount(4)));
+        assert_eq!(DemandMatrix::from_csv("-500,0,500,0,1,Car,5,5", &node_manager).err(), error(1, DemandErrorKind::ColumnCount(8)));
+    }
+
+    #[test]
+    fn rejects_invalid_numbers() {
+        let node_manager = NodeManager::new();
+        for (line, number) in [("west,0,500,0,1", "west"), ("-500,0,500,0,inf", "inf"), ("-500,0,500,0,-1", "-1"), ("-500,0,500,0,1,Car,NaN", "NaN"), ("-500,0,500,,1", "")] {
+            assert_eq!(DemandMatrix::from_csv(line, &node_manager).err(), error(1, DemandErrorKind::InvalidNumber(number.to_string())), "{line}");
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_profiles_and_positions_without_nodes() {
+        let node_manager = NodeManager::new();
+        assert_eq!(DemandMatrix::from_csv("-500,0,500,0,1,Lorry", &node_manager).err(), error(1, DemandErrorKind::UnknownProfile("Lorry".to_string())));
+        assert_eq!(DemandMatrix::from_csv("-500,0,500,0,1\n-500,0,550,0,1", &node_manager).err(), error(2, DemandErrorKind::NoNode(Vec2::new(550.0, 0.0))));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::traffic::RoutingProfile;
+
+/// Exponent of the free-road term, 4 is the usual choice.
+const ACCELERATION_EXPONENT: i32 = 4;
+/// Smallest gap used in the interaction term, so touching vehicles brake hard instead of dividing by zero.
+const MIN_EFFECTIVE_GAP: f32 = 0.01;
+
+/// Parameters of the Intelligent Driver Model, in world units and ticks.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DriverParams {
+    /// Acceleration from standstill, in world units per tick squared.
+    pub max_acceleration: f32,
+    /// Deceleration the driver brakes with in normal traffic, in world units per tick squared.
+    pub comfortable_deceleration: f32,
+    /// Desired time to the leader, in ticks.
+    pub time_headway: f32,
+    /// Gap kept to the leader when standing still.
+    pub min_gap: f32,
+    /// Speed the vehicle never exceeds, even where the edge allows more.
+    pub max_speed: f32,
+}
+
+impl DriverParams {
+    pub fn for_profile(profile: RoutingProfile) -> Self {
+        match profile {
+            RoutingProfile::Car => DriverParams {
+                max_acceleration: 0.02,
+                comfortable_deceleration: 0.03,
+                time_headway: 30.0,
+                min_gap: 2.0,
+                max_speed: 3.0,
+            },
+            RoutingProfile::Bus => DriverParams {
+                max_acceleration: 0.01,
+                comfortable_deceleration: 0.02,
+                time_headway: 36.0,
+                min_gap: 3.0,
+                max_speed: 1.5,
+            },
+            RoutingProfile::Pedestrian => DriverParams {
+                max_acceleration: 0.01,
+                comfortable_deceleration: 0.02,
+                time_headway: 10.0,
+                min_gap: 0.5,
+                max_speed: 0.1,
+            },
+            RoutingProfile::Cyclist => DriverParams {
+                max_acceleration: 0.01,
+                comfortable_deceleration: 0.02,
+                time_headway: 20.0,
+                min_gap: 1.0,
+                max_speed: 0.4,
+            },
+            RoutingProfile::Truck => DriverParams {
+                max_acceleration: 0.008,
+                comfortable_deceleration: 0.02,
+                time_headway: 40.0,
+                min_gap: 3.0,
+                max_speed: 1.5,
+            },
+        }
+    }
+
+    /// Acceleration of a vehicle driving at `speed` towards `desired_speed`, following `leader` if there is one.
+    pub fn acceleration(&self, speed: f32, desired_speed: f32, leader: Option<Leader>) -> f32 {
+        let free_road = if desired_speed > 0.0 {
+            1.0 - (speed / desired_speed).powi(ACCELERATION_EXPONENT)
+        } //
+        else {
+            //Nowhere to go, brake as if the desired speed was already exceeded
+            -1.0
+        };
+        //Well above the desired speed, e.g. after turning onto a slower edge, slow down comfortably instead of braking hard
+        let free_road = free_road.max(-self.comfortable_deceleration / self.max_acceleration);
+        let interaction = match leader {
+            Some(leader) => {
+                let approach = speed * (speed - leader.speed) / (2.0 * (self.max_acceleration * self.comfortable_deceleration).sqrt());
+                let desired_gap = self.min_gap + (speed * self.time_headway + approach).max(0.0);
+                (desired_gap / leader.gap.max(MIN_EFFECTIVE_GAP)).powi(2)
+            }
+            None => 0.0,
+        };
+        self.max_acceleration * (free_road - interaction)
+    }
+}
+
+/// The closest vehicle ahead, seen from the vehicle following it.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Leader {
+    /// Distance from the front of the follower to the rear of the leader.
+    pub gap: f32,
+    pub speed: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::IntoEnumIterator;
+
+    const LENGTH: f32 = 4.0;
+
+    /// Moves a vehicle by one tick like the simulation does, stopping instead of reversing.
+    fn step(position: &mut f32, speed: &mut f32, acceleration: f32) {
+        if *speed + acceleration < 0.0 {
+            *position += -*speed * *speed / (2.0 * acceleration);
+            *speed = 0.0;
+        } //
+        else {
+            *position += *speed + acceleration / 2.0;
+            *speed += acceleration;
+        }
+    }
+
+    /// Front positions and speeds of a column of cars, the first being the leader.
+    fn platoon(count: usize, spacing: f32, speed: f32) -> Vec<(f32, f32)> {
+        (0..count).map(|i| (-(i as f32) * spacing, speed)).collect()
+    }
+
+    /// Advances every follower by one tick, the leader keeping its speed.
+    fn tick(driver: &DriverParams, desired_speed: f32, vehicles: &mut [(f32, f32)]) {
+        let accelerations = vehicles.windows(2).map(|pair| driver.acceleration(pair[1].1, desired_speed, Some(Leader {
+            gap: pair[0].0 - pair[1].0 - LENGTH,
+            speed: pair[0].1,
+        }))).collect::<Vec<_>>();
+        let (position, speed) = &mut vehicles[0];
+        step(position, speed, 0.0);
+        for ((position, speed), acceleration) in vehicles[1..].iter_mut().zip(accelerations) {
+            step(position, speed, acceleration);
+        }
+    }
+
+    fn gaps(vehicles: &[(f32, f32)]) -> impl Iterator<Item=f32> + '_ {
+        vehicles.windows(2).map(|pair| pair[0].0 - pair[1].0 - LENGTH)
+    }
+
+    #[test]
+    fn platoon_settles_at_the_desired_gap() {
+        let driver = DriverParams::for_profile(RoutingProfile::Car);
+        let speed = 1.0;
+        let mut vehicles = platoon(5, 60.0, speed);
+        for _ in 0..20000 {
+            tick(&driver, driver.max_speed, &mut vehicles);
+        }
+        let desired_gap = driver.min_gap + speed * driver.time_headway;
+        //The free road term keeps the equilibrium slightly above the desired gap
+        let equilibrium = desired_gap / (1.0 - (speed / driver.max_speed).powi(ACCELERATION_EXPONENT)).sqrt();
+        for (gap, (_, follower_speed)) in gaps(&vehicles).zip(&vehicles[1..]) {
+            assert!((gap - equilibrium).abs() < 0.1, "gap {gap}, expected {equilibrium}");
+            assert!((gap - desired_gap).abs() < 0.02 * desired_gap, "gap {gap}, expected about {desired_gap}");
+            assert!((follower_speed - speed).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn follower_stops_behind_a_stopped_leader() {
+        for profile in RoutingProfile::iter() {
+            let driver = DriverParams::for_profile(profile);
+            let mut vehicles = vec![(1000.0, 0.0), (0.0, driver.max_speed)];
+            let mut min_gap = f32::INFINITY;
+            for _ in 0..20000 {
+                tick(&driver, driver.max_speed, &mut vehicles);
+                min_gap = min_gap.min(gaps(&vehicles).next().unwrap());
+            }
+            let gap = gaps(&vehicles).next().unwrap();
+            assert!(vehicles[1].1 < 1e-3, "{profile:?} still moving at {}", vehicles[1].1);
+            assert!(min_gap >= driver.min_gap * 0.99, "{profile:?} came within {min_gap} of the leader");
+            assert!(gap < driver.min_gap * 1.1, "{profile:?} stopped {gap} behind the leader");
+        }
+    }
+
+    #[test]
+    fn braking_above_the_desired_speed_is_comfortable() {
+        for profile in RoutingProfile::iter() {
+            let driver = DriverParams::for_profile(profile);
+            assert_eq!(driver.acceleration(driver.max_speed, driver.max_speed, None), 0.0);
+            assert!(driver.acceleration(driver.max_speed * 1.1, driver.max_speed, None) < 0.0);
+            for factor in [1.5, 2.0, 10.0] {
+                assert_eq!(driver.acceleration(driver.max_speed * factor, driver.max_speed, None), -driver.comfortable_deceleration);
+            }
+        }
+    }
+}
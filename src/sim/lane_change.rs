@@ -0,0 +1,263 @@
+use crate::node::lane_graph::{DirectedLane, LaneId};
+use crate::node::NodeManager;
+use crate::sim::vehicle::Vehicle;
+use crate::sim::{Occupancy, Simulation};
+use crate::traffic::LaneType;
+
+/// Share of the acceleration gained or lost by the followers a driver takes into account.
+const POLITENESS: f32 = 0.3;
+/// Acceleration a lane change has to gain to be worth it, so vehicles do not weave for nothing.
+const CHANGE_THRESHOLD: f32 = 0.002;
+/// Hardest braking a lane change may force on the new follower.
+const SAFE_DECELERATION: f32 = 0.06;
+/// Extra incentive to move towards the lanes leading on along the route.
+const STRATEGIC_BIAS: f32 = 0.03;
+/// Close to the end of a lane, vehicles no longer move away from the lanes leading on along their route.
+const STRATEGIC_DISTANCE: f32 = 60.0;
+/// Ticks a vehicle keeps its new lane before considering another change.
+const LANE_CHANGE_COOLDOWN: u32 = 40;
+
+impl Simulation {
+    /// Lets every vehicle change to a neighbouring lane when the MOBIL incentive and safety criteria allow it. Vehicles
+    /// decide one after another, each seeing the changes made before it.
+    pub(super) fn change_lanes(&mut self, node_manager: &NodeManager, occupancy: &mut Occupancy) {
+        let ids = self.vehicles.iter().map(|(id, _)| id).collect::<Vec<_>>();
+        for id in ids {
+            let Some(lane) = self.choose_lane(node_manager, occupancy, id) else {
+                continue;
+            };
+            occupancy.get_mut(&self.vehicles[id].get_lane()).unwrap().retain(|other| *other != id);
+            self.vehicles[id].change_lane(node_manager, lane, LANE_CHANGE_COOLDOWN);
+            let vehicles = occupancy.entry(lane).or_default();
+            let index = vehicles.partition_point(|other| self.compare_distance(*other, id).is_lt());
+            vehicles.insert(index, id);
+        }
+    }
+
+    fn choose_lane(&self, node_manager: &NodeManager, occupancy: &Occupancy, id: usize) -> Option<DirectedLane> {
+        let vehicle = &self.vehicles[id];
+        if !vehicle.can_change_lane() {
+            return None;
+        }
+        let lane = vehicle.get_lane();
+        let edge = node_manager.get_edge(lane.get_edge())?;
+        let lane_def = edge.get_lane_def();
+        let direction = lane.get_direction();
+        let layout = lane_def.layout(node_manager.get_traffic_side());
+        let profile = vehicle.get_profile();
+        let lane_at = |index: u8| DirectedLane::new(LaneId::new(lane.get_edge(), index), direction);
+        let lane_graph = node_manager.get_lane_graph();
+        let leads_on = |index: u8| vehicle.get_upcoming_lanes().first().is_none_or(|next| lane_graph.get_successors(lane_at(index)).contains(next));
+        //Turn-only lanes are shared by both directions and only entered to turn off along the route
+        let usable = |index: u8| lane_def.get(index).is_some_and(|lane_type| profile.permits(lane_type) && match lane_type.direction() {
+            Some(lane_direction) => lane_direction == direction,
+            None => lane_type.is_turn_only() && leads_on(index),
+        });
+        //How many lane changes are left to reach a lane leading on along the route, turning vehicles wait in the
+        //turn-only lane where there is one
+        let mut targets = (0..lane_def.lane_count()).filter(|index| usable(*index) && leads_on(*index)).collect::<Vec<_>>();
+        let is_turn_only = |index: &u8| lane_def.get(*index).is_some_and(LaneType::is_turn_only);
+        if targets.iter().any(is_turn_only) {
+            targets.retain(is_turn_only);
+        }
+        let changes_left = |index: u8| targets.iter().map(|target| target.abs_diff(index)).min();
+        let current_changes_left = changes_left(lane.get_lane().get_index());
+        let remaining = vehicle.get_lane_length() - vehicle.get_distance();
+
+        let (old_follower, old_leader) = self.neighbours(occupancy, lane, id);
+        let current = vehicle.get_acceleration(node_manager, self.find_leader(node_manager, occupancy, id, vehicle));
+        //The old follower would follow our leader instead of us
+        let old_follower_gain = old_follower.map_or(0.0, |follower| Self::following(node_manager, follower, old_leader) - Self::following(node_manager, follower, Some(vehicle)));
+
+        let mut best = None;
+        let mut best_gain = CHANGE_THRESHOLD;
+        let index = lane.get_lane().get_index();
+        for candidate_index in [index.checked_sub(1), index.checked_add(1)].into_iter().flatten() {
+            if !usable(candidate_index) || !layout.get(candidate_index.max(index))?.get_pre_separator().is_crossable() {
+                continue;
+            }
+            let candidate = lane_at(candidate_index);
+            let (new_follower, new_leader) = self.neighbours_at(occupancy, candidate, vehicle);
+            //Never change into a gap the vehicle does not fit in
+            if new_follower.is_some_and(|follower| follower.get_front() > vehicle.get_rear()) || new_leader.is_some_and(|leader| leader.get_rear() < vehicle.get_front()) {
+                continue;
+            }
+            let new_follower_after = new_follower.map(|follower| Self::following(node_manager, follower, Some(vehicle)));
+            if new_follower_after.is_some_and(|acceleration| acceleration < -SAFE_DECELERATION) {
+                continue;
+            }
+            let new_follower_gain = new_follower.zip(new_follower_after).map_or(0.0, |(follower, after)| after - Self::following(node_manager, follower, new_leader));
+            let after = vehicle.get_acceleration(node_manager, self.find_leader_on(node_manager, occupancy, id, vehicle, candidate, new_leader));
+            let mut gain = after - current + POLITENESS * (new_follower_gain + old_follower_gain);
+            match (changes_left(candidate_index), current_changes_left) {
+                (Some(candidate), Some(current)) if candidate < current => gain += STRATEGIC_BIAS,
+                (Some(candidate), Some(current)) if candidate > current => {
+                    if remaining < STRATEGIC_DISTANCE {
+                        continue;
+                    }
+                    gain -= STRATEGIC_BIAS;
+                }
+                _ => {}
+            }
+            if gain > best_gain {
+                best = Some(candidate);
+                best_gain = gain;
+            }
+        }
+        best
+    }
+
+    /// The vehicles directly behind and ahead of vehicle `id` on its own lane.
+    fn neighbours(&self, occupancy: &Occupancy, lane: DirectedLane, id: usize) -> (Option<&Vehicle>, Option<&Vehicle>) {
+        let vehicles = &occupancy[&lane];
+        let position = vehicles.iter().position(|other| *other == id).unwrap();
+        let follower = position.checked_sub(1).map(|index| &self.vehicles[vehicles[index]]);
+        let leader = vehicles.get(position + 1).map(|other| &self.vehicles[*other]);
+        (follower, leader)
+    }
+
+    /// The vehicles that would be directly behind and ahead of `vehicle` if it was on `lane`.
+    fn neighbours_at(&self, occupancy: &Occupancy, lane: DirectedLane, vehicle: &Vehicle) -> (Option<&Vehicle>, Option<&Vehicle>) {
+        let Some(vehicles) = occupancy.get(&lane) else {
+            return (None, None);
+        };
+        let position = vehicles.partition_point(|other| self.vehicles[*other].get_distance() <= vehicle.get_distance());
+        let follower = position.checked_sub(1).map(|index| &self.vehicles[vehicles[index]]);
+        let leader = vehicles.get(position).map(|other| &self.vehicles[*other]);
+        (follower, leader)
+    }
+
+    /// Acceleration of `follower` behind `leader` when both are on the same lane.
+    fn following(node_manager: &NodeManager, follower: &Vehicle, leader: Option<&Vehicle>) -> f32 {
+        follower.get_acceleration(node_manager, leader.map(|leader| Self::leader(follower, leader, 0.0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::if_else;
+    use crate::node::{EdgeId, NodeId};
+    use crate::sim::priority::STANDSTILL_SPEED;
+    use crate::sim::signal::{SignalController, SignalMode, SignalPhase, SignalPlan};
+    use crate::traffic::LaneType::{BikeForward, BikeReverse, BusForward, DirtForward, Grass, NormalForward, NormalReverse, Sidewalk, TurnLane};
+    use crate::traffic::{LaneDefinition, LaneDirection, RoutingProfile};
+    use ggez::glam::Vec2;
+
+    /// A manager with the default grid and a separate one-way road with the given lanes far away from it.
+    fn road(lanes: &[LaneType]) -> (NodeManager, NodeId, EdgeId) {
+        let mut node_manager = NodeManager::new();
+        let a = node_manager.add_node(Vec2::new(0.0, 5000.0));
+        let b = node_manager.add_node(Vec2::new(1000.0, 5000.0));
+        let edge = node_manager.make_edge(a, b, 3.0, LaneDefinition::from_lanes(lanes).unwrap());
+        (node_manager, b, edge)
+    }
+
+    fn lane(edge: EdgeId, index: u8) -> DirectedLane {
+        DirectedLane::new(LaneId::new(edge, index), LaneDirection::Forward)
+    }
+
+    /// Puts a vehicle at the start of the lane, driving to its end.
+    fn place(simulation: &mut Simulation, node_manager: &NodeManager, profile: RoutingProfile, lane: DirectedLane) -> usize {
+        simulation.vehicles.insert(Vehicle::new(node_manager, profile, vec![lane], simulation.ticks))
+    }
+
+    /// Lets a cyclist start on the lane, then a vehicle of the profile behind it, and returns the lanes the vehicle
+    /// used until it passed the cyclist or left the road.
+    fn follow_cyclist(node_manager: &NodeManager, profile: RoutingProfile, lane: DirectedLane) -> Vec<DirectedLane> {
+        let mut simulation = Simulation::new();
+        let cyclist = place(&mut simulation, node_manager, RoutingProfile::Cyclist, lane);
+        for _ in 0..200 {
+            simulation.tick(node_manager);
+        }
+        let id = place(&mut simulation, node_manager, profile, lane);
+        let mut lanes = vec![lane];
+        while let Some(vehicle) = simulation.vehicles.get(id) && simulation.vehicles.get(cyclist).is_some_and(|cyclist| cyclist.get_distance() > vehicle.get_distance()) {
+            if *lanes.last().unwrap() != vehicle.get_lane() {
+                lanes.push(vehicle.get_lane());
+            }
+            simulation.tick(node_manager);
+        }
+        lanes
+    }
+
+    /// A crossroads away from the default grid, entered from the west along a street with a centre turn lane. Returns
+    /// the manager, the west node, the street and the nodes east, north and south of the crossroads.
+    fn turn_lane_crossroads() -> (NodeManager, NodeId, EdgeId, [NodeId; 3]) {
+        let mut node_manager = NodeManager::new();
+        let west = node_manager.add_node(Vec2::new(-500.0, 5000.0));
+        let centre = node_manager.add_node(Vec2::new(0.0, 5000.0));
+        let street = node_manager.make_edge(west, centre, 1.0, LaneDefinition::from_lanes(&[Sidewalk, BikeReverse, NormalReverse, TurnLane, NormalForward, BikeForward, Sidewalk]).unwrap());
+        let arms = [Vec2::new(500.0, 5000.0), Vec2::new(0.0, 5500.0), Vec2::new(0.0, 4500.0)].map(|pos| {
+            let node = node_manager.add_node(pos);
+            node_manager.make_edge(centre, node, 1.0, LaneDefinition::from_lanes(&[Sidewalk, NormalReverse, NormalForward, Sidewalk]).unwrap());
+            node
+        });
+        (node_manager, west, street, arms)
+    }
+
+    /// Drives a car from `start` to `goal` and returns the lanes it used on the edge.
+    fn lanes_used_on(node_manager: &NodeManager, edge: EdgeId, start: NodeId, goal: NodeId) -> Vec<DirectedLane> {
+        let mut simulation = Simulation::new();
+        let id = simulation.spawn(node_manager, start, goal, RoutingProfile::Car).unwrap();
+        let mut lanes = vec![];
+        while let Some(vehicle) = simulation.vehicles.get(id.0) && vehicle.get_lane().get_edge() == edge {
+            if lanes.last() != Some(&vehicle.get_lane()) {
+                lanes.push(vehicle.get_lane());
+            }
+            simulation.tick(node_manager);
+        }
+        lanes
+    }
+
+    /// Whether vehicles may cross the separator before the lane at the index.
+    fn crossable(node_manager: &NodeManager, edge: EdgeId, index: u8) -> bool {
+        let layout = node_manager.get_edge(edge).unwrap().get_lane_def().layout(node_manager.get_traffic_side());
+        layout.get(index).unwrap().get_pre_separator().is_crossable()
+    }
+
+    #[test]
+    fn overtakes_a_slow_leader() {
+        let (node_manager, _, edge) = road(&[Grass, NormalForward, NormalForward, Grass]);
+        assert_eq!(follow_cyclist(&node_manager, RoutingProfile::Car, lane(edge, 1)), [lane(edge, 1), lane(edge, 2)]);
+    }
+
+    #[test]
+    fn never_crosses_a_double_continuous_line() {
+        let (node_manager, _, edge) = road(&[Grass, NormalForward, BusForward, Grass]);
+        assert!(!crossable(&node_manager, edge, 2));
+        assert_eq!(follow_cyclist(&node_manager, RoutingProfile::Bus, lane(edge, 1)), [lane(edge, 1)]);
+    }
+
+    #[test]
+    fn never_enters_a_bus_lane() {
+        let (node_manager, _, edge) = road(&[Grass, DirtForward, BusForward, Grass]);
+        assert!(crossable(&node_manager, edge, 2));
+        assert_eq!(follow_cyclist(&node_manager, RoutingProfile::Car, lane(edge, 1)), [lane(edge, 1)]);
+    }
+
+    #[test]
+    fn waits_at_a_red_light_without_changing_lanes() {
+        let (node_manager, node, edge) = road(&[Grass, NormalForward, NormalForward, Grass]);
+        let mut simulation = Simulation::new();
+        //Red for the whole test
+        let plan = SignalPlan::new(vec![SignalPhase::new(vec![], 100_000), SignalPhase::new(vec![lane(edge, 1), lane(edge, 2)], 100)], 0, 0).unwrap();
+        simulation.attach_signal(node, SignalController::new(plan, SignalMode::FixedTime));
+        let id = place(&mut simulation, &node_manager, RoutingProfile::Car, lane(edge, 1));
+        for _ in 0..3000 {
+            simulation.tick(&node_manager);
+            assert_eq!(simulation.vehicles[id].get_lane(), lane(edge, 1));
+        }
+        assert!(simulation.vehicles[id].get_speed() < STANDSTILL_SPEED);
+    }
+
+    #[test]
+    fn only_vehicles_turning_across_traffic_use_the_turn_lane() {
+        let (node_manager, west, street, arms) = turn_lane_crossroads();
+        let across = node_manager.get_lane_graph().get_successors(lane(street, 3))[0].get_to(&node_manager);
+        for goal in arms {
+            let expected = if_else!(goal == across => vec![lane(street, 4), lane(street, 3)] ; vec![lane(street, 4)]);
+            assert_eq!(lanes_used_on(&node_manager, street, west, goal), expected, "towards {goal:?}");
+        }
+    }
+}
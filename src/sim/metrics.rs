@@ -0,0 +1,292 @@
+use crate::math::if_else;
+use crate::node::lane_graph::DirectedLane;
+use crate::node::{EdgeId, NodeManager};
+use rustc_hash::FxHashMap;
+use std::collections::VecDeque;
+
+/// Ticks gathered into one bucket of the rolling window.
+const BUCKET_TICKS: u32 = 200;
+/// Buckets in the rolling window the metrics are measured over.
+const WINDOW_BUCKETS: usize = 6;
+
+/// What vehicles did on an edge during a bucket.
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+struct EdgeCounts {
+    /// Vehicles that left the edge.
+    exits: u32,
+    /// Sum over the ticks of the vehicles on the edge.
+    vehicle_ticks: u32,
+    /// Distance the vehicles on the edge travelled.
+    distance: f32,
+    /// Ticks the vehicles on the edge lost against driving at their free speed.
+    delay: f32,
+}
+
+impl EdgeCounts {
+    fn add(&mut self, other: &EdgeCounts) {
+        self.exits += other.exits;
+        self.vehicle_ticks += other.vehicle_ticks;
+        self.distance += other.distance;
+        self.delay += other.delay;
+    }
+}
+
+#[derive(Default)]
+struct Bucket {
+    ticks: u32,
+    edges: FxHashMap<EdgeId, EdgeCounts>,
+    /// Vehicles that left each lane.
+    lane_exits: FxHashMap<DirectedLane, u32>,
+}
+
+/// Traffic on an edge over the rolling window, in both directions, in world units and ticks.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct EdgeMetrics {
+    /// Vehicles leaving the edge per tick.
+    pub flow: f32,
+    /// Mean number of vehicles on the edge per world unit of its length.
+    pub density: f32,
+    /// Mean speed of the vehicles on the edge, [`None`] if there were none.
+    pub speed: Option<f32>,
+    /// Mean ticks a vehicle lost on the edge against driving at its free speed, counted per vehicle leaving it or,
+    /// when none left, per vehicle on it.
+    pub delay: f32,
+}
+
+/// A measure of the traffic on an edge, to colour edges by.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum EdgeMetric {
+    Flow,
+    Density,
+    Speed,
+    Delay,
+}
+
+impl EdgeMetric {
+    /// The metric shown after this one, [`None`] after the last.
+    pub fn next(self) -> Option<Self> {
+        match self {
+            EdgeMetric::Flow => Some(EdgeMetric::Density),
+            EdgeMetric::Density => Some(EdgeMetric::Speed),
+            EdgeMetric::Speed => Some(EdgeMetric::Delay),
+            EdgeMetric::Delay => None,
+        }
+    }
+
+    pub fn get_unit(self) -> &'static str {
+        match self {
+            EdgeMetric::Flow => "vehicles/h",
+            EdgeMetric::Density => "vehicles/100 units",
+            EdgeMetric::Speed => "units/s",
+            EdgeMetric::Delay => "s",
+        }
+    }
+
+    /// The value of the metric in its unit, if there is one.
+    pub fn value(self, metrics: &EdgeMetrics, ticks_per_second: f32) -> Option<f32> {
+        match self {
+            EdgeMetric::Flow => Some(metrics.flow * ticks_per_second * 3600.0),
+            EdgeMetric::Density => Some(metrics.density * 100.0),
+            EdgeMetric::Speed => metrics.speed.map(|speed| speed * ticks_per_second),
+            EdgeMetric::Delay => Some(metrics.delay / ticks_per_second),
+        }
+    }
+
+    /// The values mapped to the ends of the colour scale, the first one counting as free-flowing traffic.
+    pub fn get_scale(self) -> (f32, f32) {
+        match self {
+            EdgeMetric::Flow => (0.0, 1800.0),
+            EdgeMetric::Density => (0.0, 10.0),
+            EdgeMetric::Speed => (40.0, 0.0),
+            EdgeMetric::Delay => (0.0, 60.0),
+        }
+    }
+}
+
+/// Flow, density, speed and delay of every edge and the flow of every lane, measured over a rolling window of recent
+/// ticks.
+#[derive(Default)]
+pub struct TrafficMetrics {
+    /// The newest bucket first.
+    buckets: VecDeque<Bucket>,
+}
+
+impl TrafficMetrics {
+    /// Starts the next tick, dropping the oldest bucket once the window is full.
+    pub(super) fn advance(&mut self) {
+        if self.buckets.front().is_none_or(|bucket| bucket.ticks >= BUCKET_TICKS) {
+            self.buckets.push_front(Bucket::default());
+            self.buckets.truncate(WINDOW_BUCKETS);
+        }
+        self.buckets[0].ticks += 1;
+    }
+
+    /// Records a vehicle on the edge during the current tick, driving at `speed` with `free_speed` being how fast it
+    /// would go without other traffic.
+    pub(super) fn record_vehicle(&mut self, edge: EdgeId, speed: f32, free_speed: f32) {
+        let counts = self.current(edge);
+        counts.vehicle_ticks += 1;
+        counts.distance += speed;
+        if free_speed > 0.0 {
+            counts.delay += (1.0 - speed / free_speed).max(0.0);
+        }
+    }
+
+    /// Records a vehicle leaving the edge during the current tick.
+    pub(super) fn record_exit(&mut self, edge: EdgeId) {
+        self.current(edge).exits += 1;
+    }
+
+    /// Records a vehicle leaving the lane during the current tick, onto another lane or out of the network.
+    pub(super) fn record_lane_exit(&mut self, lane: DirectedLane) {
+        *self.current_bucket().lane_exits.entry(lane).or_default() += 1;
+    }
+
+    /// Drops the lane exits recorded on the edge, as its lanes were renumbered.
+    pub(super) fn forget_lanes(&mut self, edge: EdgeId) {
+        for bucket in &mut self.buckets {
+            bucket.lane_exits.retain(|lane, _| lane.get_edge() != edge);
+        }
+    }
+
+    fn current(&mut self, edge: EdgeId) -> &mut EdgeCounts {
+        self.current_bucket().edges.entry(edge).or_default()
+    }
+
+    fn current_bucket(&mut self) -> &mut Bucket {
+        if self.buckets.is_empty() {
+            self.buckets.push_front(Bucket::default());
+        }
+        &mut self.buckets[0]
+    }
+
+    /// Ticks the metrics are currently measured over.
+    pub fn get_window(&self) -> u32 {
+        self.buckets.iter().map(|bucket| bucket.ticks).sum()
+    }
+
+    /// The traffic on the edge over the window, [`None`] if no vehicle used it or the edge no longer exists.
+    pub fn get(&self, node_manager: &NodeManager, edge: EdgeId) -> Option<EdgeMetrics> {
+        let mut counts = None::<EdgeCounts>;
+        for edge_counts in self.buckets.iter().filter_map(|bucket| bucket.edges.get(&edge)) {
+            counts.get_or_insert_default().add(edge_counts);
+        }
+        let counts = counts?;
+        let length = node_manager.get_edge(edge)?.get_length(node_manager);
+        let window = self.get_window().max(1) as f32;
+        Some(EdgeMetrics {
+            flow: counts.exits as f32 / window,
+            density: counts.vehicle_ticks as f32 / window / length.max(f32::EPSILON),
+            speed: (counts.vehicle_ticks > 0).then(|| counts.distance / counts.vehicle_ticks as f32),
+            delay: counts.delay / if_else!(counts.exits > 0 => counts.exits as f32 ; (counts.vehicle_ticks as f32 / window).max(1.0)),
+        })
+    }
+
+    /// Vehicles per tick that left the lane during the window.
+    pub fn get_lane_flow(&self, lane: DirectedLane) -> f32 {
+        let exits = self.buckets.iter().filter_map(|bucket| bucket.lane_exits.get(&lane)).sum::<u32>();
+        exits as f32 / self.get_window().max(1) as f32
+    }
+
+    /// Every edge vehicles used during the window, with its traffic.
+    pub fn get_edges<'a>(&'a self, node_manager: &'a NodeManager) -> impl Iterator<Item=(EdgeId, EdgeMetrics)> + 'a {
+        node_manager.get_edges().filter_map(|edge| self.get(node_manager, edge.get_id()).map(|metrics| (edge.get_id(), metrics)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::lane_graph::LaneId;
+    use crate::node::NodeId;
+    use crate::sim::Simulation;
+    use crate::traffic::LaneType::{NormalForward, NormalReverse, ParkingForward, ParkingReverse, Sidewalk};
+    use crate::traffic::{LaneDefinition, LaneDirection, RoutingProfile};
+    use ggez::glam::Vec2;
+
+    const WINDOW: u32 = BUCKET_TICKS * WINDOW_BUCKETS as u32;
+    const LENGTH: f32 = 100.0;
+
+    /// A manager with the default grid and a separate street with parking from the first node to the second one far
+    /// away from it.
+    fn street() -> (NodeManager, NodeId, NodeId, EdgeId) {
+        let mut node_manager = NodeManager::new();
+        let a = node_manager.add_node(Vec2::new(0.0, 5000.0));
+        let b = node_manager.add_node(Vec2::new(LENGTH, 5000.0));
+        let lanes = LaneDefinition::from_lanes(&[Sidewalk, ParkingReverse, NormalReverse, NormalForward, ParkingForward, Sidewalk]).unwrap();
+        let edge = node_manager.make_edge(a, b, 1.0, lanes);
+        (node_manager, a, b, edge)
+    }
+
+    /// Ticks until every vehicle left, returning the vehicle ticks and the distance they drove meanwhile.
+    fn run(node_manager: &NodeManager, simulation: &mut Simulation, spawn: impl Fn(&mut Simulation, u32)) -> (u32, f32) {
+        let (mut vehicle_ticks, mut distance) = (0, 0.0);
+        for tick in 0..WINDOW {
+            spawn(simulation, tick);
+            simulation.tick(node_manager);
+            for (_, vehicle) in simulation.get_vehicles() {
+                vehicle_ticks += 1;
+                distance += vehicle.get_speed();
+            }
+            if simulation.vehicle_count() == 0 {
+                break;
+            }
+        }
+        assert_eq!(simulation.vehicle_count(), 0);
+        (vehicle_ticks, distance)
+    }
+
+    #[test]
+    fn lane_flow_covers_only_the_window() {
+        let node_manager = NodeManager::new();
+        let edge = node_manager.get_edges().next().unwrap().get_id();
+        let lane = DirectedLane::new(LaneId::new(edge, 3), LaneDirection::Forward);
+        let mut metrics = TrafficMetrics::default();
+        for tick in 0..WINDOW {
+            metrics.advance();
+            if tick % 10 == 0 {
+                metrics.record_lane_exit(lane);
+            }
+        }
+        assert_eq!(metrics.get_lane_flow(lane), 0.1);
+        for _ in 0..WINDOW / 2 {
+            metrics.advance();
+        }
+        assert!((metrics.get_lane_flow(lane) - 0.05).abs() < 1e-6);
+        for _ in 0..WINDOW / 2 {
+            metrics.advance();
+        }
+        assert_eq!(metrics.get_window(), WINDOW);
+        assert_eq!(metrics.get_lane_flow(lane), 0.0);
+    }
+
+    #[test]
+    fn vehicles_crossing_an_edge_are_measured() {
+        let (node_manager, a, b, edge) = street();
+        let mut simulation = Simulation::new();
+        let (vehicle_ticks, distance) = run(&node_manager, &mut simulation, |simulation, tick| {
+            if tick < 500 && tick % 100 == 0 {
+                simulation.spawn(&node_manager, a, b, RoutingProfile::Car).unwrap();
+            }
+        });
+        let window = simulation.get_metrics().get_window() as f32;
+        let metrics = simulation.get_metrics().get(&node_manager, edge).unwrap();
+        assert_eq!(metrics.flow, 5.0 / window);
+        assert_eq!(metrics.density, vehicle_ticks as f32 / window / LENGTH);
+        let speed = metrics.speed.unwrap();
+        assert!((speed - distance / vehicle_ticks as f32).abs() < 1e-4);
+        //Starting from standstill below the speed limit of the edge
+        assert!(speed > 0.5 && speed < 1.0, "mean speed {speed}");
+    }
+
+    #[test]
+    fn parked_vehicles_leave_the_edge() {
+        let (node_manager, a, b, edge) = street();
+        let mut simulation = Simulation::new();
+        simulation.spawn_parking(&node_manager, a, b, RoutingProfile::Car, WINDOW).unwrap();
+        run(&node_manager, &mut simulation, |_, _| {});
+        assert_eq!(simulation.get_parking_stats().parked, 1);
+        let window = simulation.get_metrics().get_window() as f32;
+        assert_eq!(simulation.get_metrics().get(&node_manager, edge).unwrap().flow, 1.0 / window);
+    }
+}
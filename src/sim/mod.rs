@@ -0,0 +1,351 @@
+use crate::math::if_else;
+use crate::node::lane_graph::DirectedLane;
+use crate::node::sidewalk::Crosswalk;
+use crate::node::{EdgeId, NodeId, NodeManager, TravelTimes};
+use crate::sim::idm::Leader;
+use crate::sim::metrics::TrafficMetrics;
+use crate::sim::parking::{ParkingSpot, ParkingStats};
+use crate::sim::pedestrian::{crosswalk_clearance, Pedestrian, PedestrianStats};
+use crate::sim::signal::{SignalAspect, SignalController};
+use crate::sim::transit::BusLine;
+use crate::sim::vehicle::{Trip, Vehicle};
+use crate::traffic::RoutingProfile;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use ggez::glam::Vec2;
+use slab::Slab;
+
+pub mod assignment;
+pub mod demand;
+pub mod idm;
+mod lane_change;
+pub mod metrics;
+pub mod parking;
+pub mod pedestrian;
+mod routing;
+mod priority;
+pub mod signal;
+pub mod timing;
+pub mod transit;
+pub mod vehicle;
+
+/// How far ahead along their route vehicles look for a leader.
+const LOOK_AHEAD: f32 = 150.0;
+/// Vehicles closer than this to the stop line of a lane with green keep an actuated signal green.
+const DETECTOR_DISTANCE: f32 = 30.0;
+
+/// The vehicles on every lane, ordered by the distance they travelled along it.
+type Occupancy = FxHashMap<DirectedLane, Vec<usize>>;
+
+/// Estimate of the cost between two points used when routing vehicles of the profile.
+fn route_heuristic(node_manager: &NodeManager, profile: RoutingProfile) -> impl Fn(Vec2, Vec2) -> f32 + '_ {
+    move |a, b| node_manager.min_lane_cost(a, b, profile)
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct VehicleId(usize);
+
+pub struct Simulation {
+    vehicles: Slab<Vehicle>,
+    signals: FxHashMap<NodeId, SignalController>,
+    /// Number of ticks simulated so far.
+    ticks: u64,
+    /// The spots of every parking lane a driver looked at.
+    parking: FxHashMap<DirectedLane, Vec<ParkingSpot>>,
+    parking_stats: ParkingStats,
+    lines: Vec<BusLine>,
+    pedestrians: Slab<Pedestrian>,
+    pedestrian_stats: PedestrianStats,
+    /// The crosswalks pedestrians are walking on.
+    busy_crosswalks: FxHashSet<Crosswalk>,
+    metrics: TrafficMetrics,
+    /// Whether routes are searched with the measured travel times.
+    dynamic_routing: bool,
+    /// Exponentially smoothed travel times of the edges vehicles left.
+    smoothed_travel_times: TravelTimes,
+    /// The travel times the router uses, updated periodically.
+    travel_times: TravelTimes,
+    reroutes: u32,
+}
+
+impl Simulation {
+    pub fn new() -> Self {
+        Simulation {
+            vehicles: Slab::new(),
+            signals: FxHashMap::default(),
+            ticks: 0,
+            parking: FxHashMap::default(),
+            parking_stats: ParkingStats::default(),
+            lines: Vec::new(),
+            pedestrians: Slab::new(),
+            pedestrian_stats: PedestrianStats::default(),
+            busy_crosswalks: FxHashSet::default(),
+            metrics: TrafficMetrics::default(),
+            dynamic_routing: false,
+            smoothed_travel_times: TravelTimes::default(),
+            travel_times: TravelTimes::default(),
+            reroutes: 0,
+        }
+    }
+
+    /// Adds a vehicle travelling from `start` to `goal`, if the profile can reach the goal.
+    pub fn spawn(&mut self, node_manager: &NodeManager, start: NodeId, goal: NodeId, profile: RoutingProfile) -> Option<VehicleId> {
+        let route = node_manager.lane_route(start, goal, profile, self.get_travel_times(), route_heuristic(node_manager, profile))?;
+        if route.is_empty() {
+            return None;
+        }
+        Some(VehicleId(self.vehicles.insert(Vehicle::new(node_manager, profile, route, self.ticks))))
+    }
+
+    /// Advances every vehicle by one fixed tick, removing the ones that arrived. Accelerations are computed from the
+    /// state at the start of the tick, so the order vehicles are updated in does not matter.
+    pub fn tick(&mut self, node_manager: &NodeManager) {
+        self.update_routing(node_manager);
+        self.update_parking(node_manager);
+        self.dispatch_buses(node_manager);
+        self.update_buses();
+        let mut occupancy = self.get_occupancy();
+        self.update_pedestrians(node_manager, &occupancy);
+        for (node, signal) in self.signals.iter_mut() {
+            //Pedestrians still on a crosswalk keep an actuated signal green as well
+            let detected = self.busy_crosswalks.iter().any(|crosswalk| crosswalk.get_node() == *node)
+                || signal.get_green_lanes().iter().any(|lane| occupancy.get(lane).is_some_and(|vehicles| vehicles.iter().any(|id| {
+                let vehicle = &self.vehicles[*id];
+                vehicle.get_lane_length() - vehicle.get_front() < DETECTOR_DISTANCE
+            })));
+            signal.tick(detected);
+        }
+        self.change_lanes(node_manager, &mut occupancy);
+        let accelerations = self.vehicles.iter()
+            .map(|(id, vehicle)| (id, vehicle.get_acceleration(node_manager, self.find_leader(node_manager, &occupancy, id, vehicle))))
+            .collect::<FxHashMap<_, _>>();
+        let now = self.ticks;
+        let metrics = &mut self.metrics;
+        metrics.advance();
+        //Same as get_travel_times, borrowing only the fields it reads while the vehicles are updated
+        let routing_times = self.dynamic_routing.then_some(&self.travel_times);
+        let mut travel_times = vec![];
+        self.vehicles.retain(|id, vehicle| {
+            let lane = vehicle.get_lane();
+            let entered_at = vehicle.get_edge_entered_at();
+            let alive = vehicle.tick(node_manager, accelerations[&id], routing_times, now);
+            if !alive || vehicle.get_lane() != lane {
+                metrics.record_lane_exit(lane);
+            }
+            if !alive || vehicle.get_lane().get_edge() != lane.get_edge() {
+                metrics.record_exit(lane.get_edge());
+                travel_times.push((lane, (now - entered_at) as f32));
+            }
+            alive
+        });
+        for (lane, ticks) in travel_times {
+            self.record_travel_time(node_manager, lane, ticks);
+        }
+        for (_, vehicle) in &self.vehicles {
+            let edge = vehicle.get_lane().get_edge();
+            let limit = node_manager.get_edge(edge).map_or(0.0, |edge| edge.get_speed());
+            self.metrics.record_vehicle(edge, vehicle.get_speed(), limit.min(vehicle.get_driver().max_speed));
+        }
+        self.ticks += 1;
+    }
+
+    /// Takes a vehicle out of the network before the end of its route, e.g. when it parks, counting it as leaving its
+    /// edge.
+    fn remove_vehicle(&mut self, id: usize) {
+        let vehicle = self.vehicles.remove(id);
+        self.metrics.record_exit(vehicle.get_lane().get_edge());
+    }
+
+    fn get_occupancy(&self) -> Occupancy {
+        let mut occupancy = Occupancy::default();
+        for (id, vehicle) in &self.vehicles {
+            occupancy.entry(vehicle.get_lane()).or_default().push(id);
+        }
+        for vehicles in occupancy.values_mut() {
+            vehicles.sort_by(|a, b| self.compare_distance(*a, *b));
+        }
+        occupancy
+    }
+
+    fn compare_distance(&self, a: usize, b: usize) -> Ordering {
+        self.vehicles[a].get_distance().total_cmp(&self.vehicles[b].get_distance()).then(a.cmp(&b))
+    }
+
+    /// Finds what the vehicle has to follow: the closest vehicle ahead, a crosswalk pedestrians are on, or the parking
+    /// spot or bus stop it is heading for.
+    fn find_leader(&self, node_manager: &NodeManager, occupancy: &Occupancy, id: usize, vehicle: &Vehicle) -> Option<Leader> {
+        let same_lane = &occupancy[&vehicle.get_lane()];
+        let position = same_lane.iter().position(|other| *other == id).unwrap();
+        let ahead = same_lane.get(position + 1).map(|other| &self.vehicles[*other]);
+        self.find_leader_on(node_manager, occupancy, id, vehicle, vehicle.get_lane(), ahead)
+    }
+
+    /// The leader vehicle `id` would have on `lane` of its edge, with `ahead` the closest vehicle in front of it there.
+    /// Lane changes compare lanes with this, so stop lines, yielding and stops weigh the same on every lane.
+    fn find_leader_on(&self, node_manager: &NodeManager, occupancy: &Occupancy, id: usize, vehicle: &Vehicle, lane: DirectedLane, ahead: Option<&Vehicle>) -> Option<Leader> {
+        let leader = self.find_traffic_leader(node_manager, occupancy, id, vehicle, lane, ahead);
+        let stop = match vehicle.get_trip() {
+            Trip::Through => None,
+            Trip::Parking(_) => Self::parking_stop(vehicle),
+            Trip::Bus(run) => self.bus_stop(node_manager, vehicle, run),
+        };
+        [stop, self.crosswalk_stop(node_manager, vehicle, lane)].into_iter().flatten().fold(leader, |leader, stop| {
+            if_else!(leader.is_none_or(|leader| stop.gap < leader.gap) => Some(stop) ; leader)
+        })
+    }
+
+    /// Finds the closest vehicle ahead on the current lane, or on the next lanes of the route. A stop line the vehicle
+    /// has to stop at, or the end of its lane when it has to give way there, counts as a standing leader.
+    fn find_traffic_leader(&self, node_manager: &NodeManager, occupancy: &Occupancy, id: usize, vehicle: &Vehicle, lane: DirectedLane, ahead: Option<&Vehicle>) -> Option<Leader> {
+        if let Some(other) = ahead {
+            return Some(Self::leader(vehicle, other, 0.0));
+        }
+        let mut lane_end = vehicle.get_lane_length();
+        if let Some(stop) = self.stop_line(node_manager, vehicle, lane, lane_end) {
+            return Some(stop);
+        }
+        if self.must_give_way(node_manager, occupancy, id, vehicle, lane) {
+            return Some(Leader {
+                gap: lane_end - vehicle.get_front(),
+                speed: 0.0,
+            });
+        }
+        for lane in vehicle.get_upcoming_lanes() {
+            if lane_end - vehicle.get_distance() > LOOK_AHEAD {
+                break;
+            }
+            if let Some(other) = occupancy.get(lane).and_then(|vehicles| vehicles.first()) {
+                return Some(Self::leader(vehicle, &self.vehicles[*other], lane_end));
+            }
+            lane_end += node_manager.get_edge(lane.get_edge()).map_or(0.0, |edge| edge.get_length(node_manager));
+            if let Some(stop) = self.stop_line(node_manager, vehicle, *lane, lane_end) {
+                return Some(stop);
+            }
+        }
+        None
+    }
+
+    /// The stop line at the end of `lane`, `lane_end` along the lane of the vehicle, if the vehicle has to stop there.
+    /// Vehicles stop at red, and at amber when they can do so braking comfortably. The stop line lies before the
+    /// crosswalk over the lane.
+    fn stop_line(&self, node_manager: &NodeManager, vehicle: &Vehicle, lane: DirectedLane, lane_end: f32) -> Option<Leader> {
+        let node = lane.get_to(node_manager);
+        let aspect = self.signals.get(&node)?.get_aspect(lane)?;
+        let gap = lane_end - crosswalk_clearance(node_manager, node) - vehicle.get_front();
+        let stop = match aspect {
+            SignalAspect::Green => false,
+            SignalAspect::Amber => gap >= vehicle.get_speed().powi(2) / (2.0 * vehicle.get_driver().comfortable_deceleration),
+            SignalAspect::Red => gap >= 0.0,
+        };
+        stop.then_some(Leader {
+            gap,
+            speed: 0.0,
+        })
+    }
+
+    /// Puts a signal at the node, replacing the one already there. The signal joins its fixed-time cycle at the point
+    /// given by its offset.
+    pub fn attach_signal(&mut self, node: NodeId, mut signal: SignalController) {
+        signal.synchronise(self.ticks);
+        self.signals.insert(node, signal);
+    }
+
+    pub fn detach_signal(&mut self, node: NodeId) -> Option<SignalController> {
+        self.signals.remove(&node)
+    }
+
+    pub fn get_signal(&self, node: NodeId) -> Option<&SignalController> {
+        self.signals.get(&node)
+    }
+
+    pub fn get_signal_mut(&mut self, node: NodeId) -> Option<&mut SignalController> {
+        self.signals.get_mut(&node)
+    }
+
+    pub fn get_signals(&self) -> impl Iterator<Item=(NodeId, &SignalController)> {
+        self.signals.iter().map(|(node, signal)| (*node, signal))
+    }
+
+    /// Checks that nothing in the simulation refers to the lanes of the edge by index, so they may be renumbered: no
+    /// vehicle is on it or routed over it, no bus line runs along it, no signal phase controls it and no parking spot
+    /// on it is taken.
+    pub fn check_edge_unused(&self, edge: EdgeId) -> Result<(), EdgeInUse> {
+        let on_edge = |lane: &DirectedLane| lane.get_edge() == edge;
+        if self.vehicles.iter().any(|(_, vehicle)| vehicle.get_route()[vehicle.get_route_index()..].iter().any(on_edge)) {
+            return Err(EdgeInUse::Traffic);
+        }
+        if let Some(line) = self.lines.iter().find(|line| line.get_route().iter().any(on_edge)) {
+            return Err(EdgeInUse::BusLine(line.get_name().to_string()));
+        }
+        if let Some((node, _)) = self.signals.iter().find(|(_, signal)| signal.get_plan().get_phases().iter().any(|phase| phase.get_green().iter().any(on_edge))) {
+            return Err(EdgeInUse::Signal(*node));
+        }
+        if self.parking.iter().any(|(lane, spots)| on_edge(lane) && spots.iter().any(|spot| *spot != ParkingSpot::Free)) {
+            return Err(EdgeInUse::Parking);
+        }
+        Ok(())
+    }
+
+    /// Drops the parking spots and lane flows of the edge after its lanes were renumbered.
+    pub fn forget_lanes(&mut self, edge: EdgeId) {
+        self.parking.retain(|lane, _| lane.get_edge() != edge);
+        self.metrics.forget_lanes(edge);
+    }
+
+    /// Describes `leader` as seen from `follower`, where the lane of the leader starts `lane_start` after the start of
+    /// the lane of the follower.
+    fn leader(follower: &Vehicle, leader: &Vehicle, lane_start: f32) -> Leader {
+        Leader {
+            gap: lane_start + leader.get_rear() - follower.get_front(),
+            speed: leader.get_speed(),
+        }
+    }
+
+    /// Vehicles per tick that recently left the lane, over the rolling window of the metrics.
+    pub fn get_lane_flow(&self, lane: DirectedLane) -> f32 {
+        self.metrics.get_lane_flow(lane)
+    }
+
+    /// Number of ticks simulated so far.
+    pub fn get_ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    pub fn get_vehicles(&self) -> impl Iterator<Item=(VehicleId, &Vehicle)> {
+        self.vehicles.iter().map(|(id, vehicle)| (VehicleId(id), vehicle))
+    }
+
+    pub fn get_metrics(&self) -> &TrafficMetrics {
+        &self.metrics
+    }
+
+    pub fn vehicle_count(&self) -> usize {
+        self.vehicles.len()
+    }
+}
+
+/// Why the lanes of an edge may not be renumbered.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum EdgeInUse {
+    /// A vehicle is on the edge or routed over it.
+    Traffic,
+    BusLine(String),
+    Signal(NodeId),
+    /// A parking spot on the edge is reserved or occupied.
+    Parking,
+}
+
+impl Display for EdgeInUse {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EdgeInUse::Traffic => write!(f, "Vehicles are using the edge"),
+            EdgeInUse::BusLine(name) => write!(f, "Bus line {name} runs along the edge"),
+            EdgeInUse::Signal(node) => write!(f, "The signal at {node:?} controls the edge"),
+            EdgeInUse::Parking => write!(f, "Cars are parked on the edge"),
+        }
+    }
+}
+
+impl Error for EdgeInUse {}
@@ -0,0 +1,243 @@
+use crate::node::lane_graph::{DirectedLane, LaneId};
+use crate::node::{NodeId, NodeManager};
+use crate::sim::idm::Leader;
+use crate::sim::priority::STANDSTILL_SPEED;
+use crate::sim::vehicle::{Trip, Vehicle};
+use crate::sim::{Simulation, VehicleId};
+use crate::traffic::{LaneType, RoutingProfile};
+use ggez::glam::Vec2;
+
+/// Length of a parking spot along the lane.
+pub const SPOT_LENGTH: f32 = 6.0;
+/// Drivers start looking for a spot once this close to their destination.
+const SEARCH_RADIUS: f32 = 150.0;
+/// While cruising, drivers prefer streets ending this close to their destination.
+const CRUISE_RADIUS: f32 = 250.0;
+/// Drivers searching longer than this give up and leave the network.
+const MAX_SEARCH_TIME: u64 = 6000;
+/// How close to the centre of its spot a vehicle has to stop to park.
+const PARK_REACH: f32 = 1.0;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ParkingSpot {
+    Free,
+    /// Claimed by the vehicle with the given index, which is on its way.
+    Reserved(usize),
+    /// A parked car leaving at the given tick.
+    Occupied {
+        until: u64,
+    },
+}
+
+/// A trip that ends by finding a parking spot near the destination rather than at the destination node.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ParkingTrip {
+    /// Ticks the car stays parked.
+    duration: u32,
+    destination: Vec2,
+    /// The tick the driver started looking for a spot.
+    searching_since: Option<u64>,
+    /// The spot the driver is heading for, as a parking lane and the index of the spot on it.
+    reservation: Option<(DirectedLane, usize)>,
+    /// Turns taken while cruising for a spot.
+    cruise_steps: u32,
+}
+
+impl ParkingTrip {
+    pub fn new(duration: u32, destination: Vec2) -> Self {
+        ParkingTrip {
+            duration,
+            destination,
+            searching_since: None,
+            reservation: None,
+            cruise_steps: 0,
+        }
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.searching_since.is_some()
+    }
+}
+
+/// Totals over all parking trips that ended, to study cruising for parking.
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub struct ParkingStats {
+    pub parked: u32,
+    /// Trips that found no spot in time.
+    pub gave_up: u32,
+    /// Ticks spent looking for a spot by the trips that parked.
+    pub search_time: u64,
+}
+
+impl ParkingStats {
+    /// Mean ticks the trips that parked spent looking for a spot.
+    pub fn mean_search_time(&self) -> f32 {
+        self.search_time as f32 / self.parked.max(1) as f32
+    }
+}
+
+pub fn is_parking(lane_type: LaneType) -> bool {
+    matches!(lane_type, LaneType::ParkingForward | LaneType::ParkingReverse)
+}
+
+/// The parking lanes of the edge of `lane` that are entered in its direction of travel.
+pub fn parking_lanes(node_manager: &NodeManager, lane: DirectedLane) -> Vec<DirectedLane> {
+    let Some(edge) = node_manager.get_edge(lane.get_edge()) else {
+        return vec![];
+    };
+    let lane_def = edge.get_lane_def();
+    (0..lane_def.lane_count())
+        .filter(|index| lane_def.get(*index).is_some_and(|lane_type| is_parking(lane_type) && lane_type.direction() == Some(lane.get_direction())))
+        .map(|index| DirectedLane::new(LaneId::new(lane.get_edge(), index), lane.get_direction()))
+        .collect()
+}
+
+/// Every parking lane of the network, in the direction cars park on it.
+pub fn all_parking_lanes(node_manager: &NodeManager) -> impl Iterator<Item=DirectedLane> + '_ {
+    node_manager.get_edges().flat_map(|edge| edge.get_lane_def().lanes().enumerate().filter_map(|(index, lane_type)| {
+        let direction = lane_type.direction().filter(|_| is_parking(lane_type))?;
+        Some(DirectedLane::new(LaneId::new(edge.get_id(), index as u8), direction))
+    }))
+}
+
+/// Number of spots fitting along the lane.
+pub fn spot_count(node_manager: &NodeManager, lane: DirectedLane) -> usize {
+    node_manager.get_edge(lane.get_edge()).map_or(0, |edge| (edge.get_length(node_manager) / SPOT_LENGTH) as usize)
+}
+
+/// Distance of the centre of a spot from the start of its lane.
+pub fn spot_distance(index: usize) -> f32 {
+    (index as f32 + 0.5) * SPOT_LENGTH
+}
+
+impl Simulation {
+    /// Adds a car travelling from `start` that parks near `goal` for `duration` ticks, if it can reach the goal.
+    pub fn spawn_parking(&mut self, node_manager: &NodeManager, start: NodeId, goal: NodeId, profile: RoutingProfile, duration: u32) -> Option<VehicleId> {
+        let id = self.spawn(node_manager, start, goal, profile)?;
+        self.vehicles[id.0].set_trip(Trip::Parking(ParkingTrip::new(duration, node_manager.get_node_pos(goal)?)));
+        Some(id)
+    }
+
+    /// The spots of a parking lane, [`None`] while no driver looked at the lane yet, which means all spots are free.
+    pub fn get_parking_spots(&self, lane: DirectedLane) -> Option<&[ParkingSpot]> {
+        self.parking.get(&lane).map(Vec::as_slice)
+    }
+
+    pub fn get_parking_stats(&self) -> ParkingStats {
+        self.parking_stats
+    }
+
+    /// Parked cars and spots over the whole network.
+    pub fn parking_occupancy(&self, node_manager: &NodeManager) -> (usize, usize) {
+        let mut occupied = 0;
+        let mut capacity = 0;
+        for lane in all_parking_lanes(node_manager) {
+            capacity += spot_count(node_manager, lane);
+            occupied += self.get_parking_spots(lane).map_or(0, |spots| spots.iter().filter(|spot| matches!(spot, ParkingSpot::Occupied { .. })).count());
+        }
+        (occupied, capacity)
+    }
+
+    /// Frees the spots of cars that left and of drivers no longer heading for them, then lets every driver on a
+    /// parking trip look for a spot, park or cruise on.
+    pub(super) fn update_parking(&mut self, node_manager: &NodeManager) {
+        let now = self.ticks;
+        for (lane, spots) in &mut self.parking {
+            for (index, spot) in spots.iter_mut().enumerate() {
+                let free = match *spot {
+                    ParkingSpot::Free => false,
+                    ParkingSpot::Reserved(id) => self.vehicles.get(id).and_then(|vehicle| vehicle.get_parking()?.reservation) != Some((*lane, index)),
+                    ParkingSpot::Occupied { until } => until <= now,
+                };
+                if free {
+                    *spot = ParkingSpot::Free;
+                }
+            }
+        }
+        let ids = self.vehicles.iter().filter(|(_, vehicle)| vehicle.get_parking().is_some()).map(|(id, _)| id).collect::<Vec<_>>();
+        for id in ids {
+            self.search_parking(node_manager, id, now);
+        }
+    }
+
+    fn search_parking(&mut self, node_manager: &NodeManager, id: usize, now: u64) {
+        let vehicle = &self.vehicles[id];
+        let mut trip = *vehicle.get_parking().unwrap();
+        let lane = vehicle.get_lane();
+        if let Some((spot_lane, index)) = trip.reservation {
+            let target = spot_distance(index);
+            if spot_lane.get_edge() == lane.get_edge() && spot_lane.get_direction() == lane.get_direction() {
+                if (target - vehicle.get_distance()).abs() < PARK_REACH && vehicle.get_speed() < STANDSTILL_SPEED {
+                    self.parking.get_mut(&spot_lane).unwrap()[index] = ParkingSpot::Occupied {
+                        until: now + trip.duration as u64,
+                    };
+                    self.parking_stats.parked += 1;
+                    self.parking_stats.search_time += now - trip.searching_since.unwrap_or(now);
+                    self.remove_vehicle(id);
+                    return;
+                }
+                if vehicle.get_distance() <= target + PARK_REACH {
+                    return;
+                }
+            }
+            //Drove past the spot
+            trip.reservation = None;
+            if let Some(spot) = self.parking.get_mut(&spot_lane).and_then(|spots| spots.get_mut(index)) {
+                *spot = ParkingSpot::Free;
+            }
+        }
+        if trip.searching_since.is_none() && (vehicle.get_pos().distance(trip.destination) < SEARCH_RADIUS || vehicle.get_upcoming_lanes().is_empty()) {
+            trip.searching_since = Some(now);
+        }
+        if let Some(since) = trip.searching_since {
+            if now - since > MAX_SEARCH_TIME {
+                self.parking_stats.gave_up += 1;
+                self.remove_vehicle(id);
+                return;
+            }
+            //Only spots the driver can still stop at braking comfortably
+            let reachable = vehicle.get_distance() + vehicle.get_speed().powi(2) / (2.0 * vehicle.get_driver().comfortable_deceleration) + PARK_REACH;
+            for parking_lane in parking_lanes(node_manager, lane) {
+                let spots = self.parking.entry(parking_lane).or_insert_with(|| vec![ParkingSpot::Free; spot_count(node_manager, parking_lane)]);
+                if let Some(index) = (0..spots.len()).find(|index| spots[*index] == ParkingSpot::Free && spot_distance(*index) >= reachable) {
+                    spots[index] = ParkingSpot::Reserved(id);
+                    trip.reservation = Some((parking_lane, index));
+                    break;
+                }
+            }
+            if trip.reservation.is_none() && vehicle.get_upcoming_lanes().is_empty() {
+                let Some(next) = cruise_lane(node_manager, vehicle, &trip, id) else {
+                    self.parking_stats.gave_up += 1;
+                    self.remove_vehicle(id);
+                    return;
+                };
+                trip.cruise_steps += 1;
+                self.vehicles[id].set_route(node_manager, vec![lane, next]);
+            }
+        }
+        self.vehicles[id].set_trip(Trip::Parking(trip));
+    }
+
+    /// Where a vehicle heading for a reserved spot on its current edge has to stop, as a standing leader.
+    pub(super) fn parking_stop(vehicle: &Vehicle) -> Option<Leader> {
+        let (spot_lane, index) = vehicle.get_parking()?.reservation?;
+        let lane = vehicle.get_lane();
+        (spot_lane.get_edge() == lane.get_edge() && spot_lane.get_direction() == lane.get_direction()).then(|| Leader {
+            gap: spot_distance(index) - vehicle.get_distance() + vehicle.get_driver().min_gap,
+            speed: 0.0,
+        })
+    }
+}
+
+/// The lane a driver cruising for parking turns onto at the end of its lane. Drivers do not know where spots are free,
+/// so they take turns in an order of their own among the streets close to the destination, or head back towards it.
+fn cruise_lane(node_manager: &NodeManager, vehicle: &Vehicle, trip: &ParkingTrip, id: usize) -> Option<DirectedLane> {
+    let profile = vehicle.get_profile();
+    let successors = node_manager.get_lane_graph().get_successors(vehicle.get_lane()).iter().copied().filter(|lane| profile.permits(lane.get_type(node_manager))).collect::<Vec<_>>();
+    let end_distance = |lane: DirectedLane| node_manager.get_node_pos(lane.get_to(node_manager)).unwrap().distance(trip.destination);
+    let nearby = successors.iter().copied().filter(|lane| end_distance(*lane) < CRUISE_RADIUS).collect::<Vec<_>>();
+    if nearby.is_empty() {
+        return successors.into_iter().min_by(|a, b| end_distance(*a).total_cmp(&end_distance(*b)));
+    }
+    Some(nearby[(id + trip.cruise_steps as usize) % nearby.len()])
+}
@@ -0,0 +1,365 @@
+use crate::node::lane_graph::DirectedLane;
+use crate::node::sidewalk::{Crosswalk, SidewalkLink};
+use crate::node::{NodeId, NodeManager};
+use crate::sim::idm::{DriverParams, Leader};
+use crate::sim::priority::STANDSTILL_SPEED;
+use crate::sim::vehicle::Vehicle;
+use crate::sim::{Occupancy, Simulation};
+use crate::traffic::RoutingProfile;
+use ggez::glam::Vec2;
+
+/// At crosswalks without a signal, pedestrians wait until no vehicle reaches the crosswalk within this many ticks.
+const CROSSING_GAP: f32 = 100.0;
+/// Vehicles further than this from a crosswalk are never in the way of pedestrians.
+const CONFLICT_DISTANCE: f32 = 120.0;
+/// Vehicles closer than this to the end of their lane give way to pedestrians on the crosswalk they drive onto.
+const YIELD_DISTANCE: f32 = 40.0;
+/// Half the width of a crosswalk along its edge, vehicles stop this far before its centre.
+pub const CROSSWALK_HALF_WIDTH: f32 = 1.5;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct PedestrianId(usize);
+
+/// A straight part of the walk of a pedestrian.
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct WalkSegment {
+    from: Vec2,
+    to: Vec2,
+    crosswalk: Option<Crosswalk>,
+}
+
+impl WalkSegment {
+    fn length(&self) -> f32 {
+        self.from.distance(self.to)
+    }
+}
+
+/// A pedestrian walking along sidewalks and over crosswalks. Pedestrians do not interact with each other.
+pub struct Pedestrian {
+    segments: Vec<WalkSegment>,
+    segment: usize,
+    /// Distance walked along the current segment.
+    distance: f32,
+    /// Walking speed in world units per tick.
+    speed: f32,
+    pos: Vec2,
+    previous_pos: Vec2,
+    /// Tick the pedestrian started waiting at the kerb of the crosswalk ahead.
+    waiting_since: Option<u64>,
+}
+
+impl Pedestrian {
+    /// Places a pedestrian at the start of a walk along the links, which must not be empty. Corners are walked across
+    /// in a straight line.
+    fn new(links: &[SidewalkLink]) -> Self {
+        let mut segments = Vec::with_capacity(links.len() * 2);
+        for link in links {
+            if let Some(previous) = segments.last().map(|segment: &WalkSegment| segment.to) && previous != link.from_pos {
+                segments.push(WalkSegment {
+                    from: previous,
+                    to: link.from_pos,
+                    crosswalk: None,
+                });
+            }
+            segments.push(WalkSegment {
+                from: link.from_pos,
+                to: link.to_pos,
+                crosswalk: link.crosswalk,
+            });
+        }
+        let pos = segments[0].from;
+        Pedestrian {
+            segments,
+            segment: 0,
+            distance: 0.0,
+            speed: DriverParams::for_profile(RoutingProfile::Pedestrian).max_speed,
+            pos,
+            previous_pos: pos,
+            waiting_since: None,
+        }
+    }
+
+    pub fn get_pos(&self) -> Vec2 {
+        self.pos
+    }
+
+    pub fn get_previous_pos(&self) -> Vec2 {
+        self.previous_pos
+    }
+
+    pub fn is_waiting(&self) -> bool {
+        self.waiting_since.is_some()
+    }
+
+    /// The crosswalk the pedestrian is walking on, not counting the one it waits at.
+    pub fn get_crosswalk(&self) -> Option<Crosswalk> {
+        self.segments[self.segment].crosswalk.filter(|_| self.distance > 0.0)
+    }
+
+    /// The crosswalk ahead if the pedestrian stands at its kerb.
+    fn get_kerb(&self) -> Option<(Crosswalk, f32)> {
+        let segment = &self.segments[self.segment];
+        segment.crosswalk.filter(|_| self.distance == 0.0).map(|crosswalk| (crosswalk, segment.length()))
+    }
+
+    /// Walks on for a tick, stopping at the kerb of every crosswalk. Returns false once the walk is over.
+    fn walk(&mut self) -> bool {
+        self.previous_pos = self.pos;
+        self.distance += self.speed;
+        loop {
+            let length = self.segments[self.segment].length();
+            if self.distance < length {
+                break;
+            }
+            self.distance -= length;
+            self.segment += 1;
+            if self.segment == self.segments.len() {
+                return false;
+            }
+            if self.segments[self.segment].crosswalk.is_some() {
+                self.distance = 0.0;
+            }
+        }
+        let segment = &self.segments[self.segment];
+        self.pos = segment.from + (segment.to - segment.from).normalize_or_zero() * self.distance;
+        true
+    }
+}
+
+/// Totals over the crossings and walks of all pedestrians.
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub struct PedestrianStats {
+    pub arrived: u32,
+    pub crossings: u32,
+    /// Ticks pedestrians waited at the kerb before crossing.
+    pub wait_time: u64,
+}
+
+impl PedestrianStats {
+    /// Mean ticks a pedestrian waited before crossing a road.
+    pub fn mean_wait(&self) -> f32 {
+        self.wait_time as f32 / self.crossings.max(1) as f32
+    }
+}
+
+/// How far before the node vehicles on a lane ending at it stop to keep its crosswalks clear.
+pub(super) fn crosswalk_clearance(node_manager: &NodeManager, node: NodeId) -> f32 {
+    node_manager.get_sidewalk_graph().get_setback(node).map_or(0.0, |setback| setback + CROSSWALK_HALF_WIDTH)
+}
+
+/// Ticks a pedestrian takes to walk over a crosswalk of the given length.
+pub fn crossing_time(length: f32) -> u32 {
+    (length / DriverParams::for_profile(RoutingProfile::Pedestrian).max_speed).ceil() as u32
+}
+
+impl Simulation {
+    /// Adds a pedestrian walking from `start` to `goal` on the sidewalk network, if the goal can be reached on foot.
+    pub fn spawn_pedestrian(&mut self, node_manager: &NodeManager, start: NodeId, goal: NodeId) -> Option<PedestrianId> {
+        let links = node_manager.get_sidewalk_graph().route(node_manager, start, goal)?;
+        if links.is_empty() {
+            return None;
+        }
+        Some(PedestrianId(self.pedestrians.insert(Pedestrian::new(&links))))
+    }
+
+    pub fn get_pedestrians(&self) -> impl Iterator<Item=(PedestrianId, &Pedestrian)> {
+        self.pedestrians.iter().map(|(id, pedestrian)| (PedestrianId(id), pedestrian))
+    }
+
+    pub fn get_pedestrian_stats(&self) -> PedestrianStats {
+        self.pedestrian_stats
+    }
+
+    /// Moves every pedestrian, letting the ones at a kerb cross once the crosswalk signal shows walk or, without a
+    /// signal, once there is a gap in the traffic. Then notes the crosswalks pedestrians are on.
+    pub(super) fn update_pedestrians(&mut self, node_manager: &NodeManager, occupancy: &Occupancy) {
+        let now = self.ticks;
+        let mut walking = vec![false; self.pedestrians.capacity()];
+        for (id, pedestrian) in &self.pedestrians {
+            walking[id] = pedestrian.get_kerb().is_none_or(|(crosswalk, length)| self.may_cross(node_manager, occupancy, crosswalk, length));
+        }
+        let stats = &mut self.pedestrian_stats;
+        self.pedestrians.retain(|id, pedestrian| {
+            if !walking[id] {
+                pedestrian.previous_pos = pedestrian.pos;
+                pedestrian.waiting_since.get_or_insert(now);
+                return true;
+            }
+            if pedestrian.get_kerb().is_some() {
+                stats.crossings += 1;
+                stats.wait_time += now - pedestrian.waiting_since.take().unwrap_or(now);
+            }
+            let alive = pedestrian.walk();
+            if !alive {
+                stats.arrived += 1;
+            }
+            alive
+        });
+        self.busy_crosswalks = self.pedestrians.iter().filter_map(|(_, pedestrian)| pedestrian.get_crosswalk()).collect();
+    }
+
+    /// Whether a pedestrian at the kerb may start walking over the crosswalk of the given length. No pedestrian walks
+    /// into a vehicle standing on the crosswalk, even when the signal shows walk.
+    fn may_cross(&self, node_manager: &NodeManager, occupancy: &Occupancy, crosswalk: Crosswalk, length: f32) -> bool {
+        let node = crosswalk.get_node();
+        let clearance = crosswalk_clearance(node_manager, node);
+        let lane_graph = node_manager.get_lane_graph();
+        let vehicles = |lanes: &[DirectedLane]| lanes.iter()
+            .filter(|lane| lane.get_edge() == crosswalk.get_edge() && lane.get_type(node_manager).is_carriageway())
+            .filter_map(|lane| occupancy.get(lane))
+            .flatten()
+            .map(|id| &self.vehicles[*id])
+            .collect::<Vec<_>>();
+        let (arriving, leaving) = (vehicles(lane_graph.get_arrivals(node)), vehicles(lane_graph.get_departures(node)));
+        //Distance left to the crosswalk, negative once on it
+        let remaining = |vehicle: &Vehicle| vehicle.get_lane_length() - clearance - vehicle.get_front();
+        let blocked = arriving.iter().any(|vehicle| (-vehicle.get_length() - 2.0 * CROSSWALK_HALF_WIDTH..0.0).contains(&remaining(vehicle)))
+            || leaving.iter().any(|vehicle| vehicle.get_rear() < clearance);
+        if blocked {
+            return false;
+        }
+        if let Some(signal) = self.signals.get(&node) {
+            return signal.permits_crossing(crosswalk.get_edge(), crossing_time(length));
+        }
+        !arriving.iter().any(|vehicle| {
+            (0.0..CONFLICT_DISTANCE).contains(&remaining(vehicle)) && vehicle.get_speed() > STANDSTILL_SPEED && remaining(vehicle) / vehicle.get_speed() < CROSSING_GAP
+        })
+    }
+
+    /// Where the vehicle has to stop for pedestrians on a crosswalk if it was on `lane`, as a standing leader: before the
+    /// crosswalk over its own edge at the end of the lane, or at the end of the lane if it is about to drive onto an
+    /// edge whose crosswalk is in use, which makes turning vehicles give way to pedestrians.
+    pub(super) fn crosswalk_stop(&self, node_manager: &NodeManager, vehicle: &Vehicle, lane: DirectedLane) -> Option<Leader> {
+        if self.busy_crosswalks.is_empty() {
+            return None;
+        }
+        let node = lane.get_to(node_manager);
+        if !lane.get_type(node_manager).is_carriageway() {
+            return None;
+        }
+        let crosswalk_start = vehicle.get_lane_length() - crosswalk_clearance(node_manager, node);
+        if vehicle.get_front() <= crosswalk_start && self.busy_crosswalks.contains(&Crosswalk::new(node, lane.get_edge())) {
+            return Some(Leader {
+                gap: crosswalk_start - vehicle.get_front(),
+                speed: 0.0,
+            });
+        }
+        let next = vehicle.get_upcoming_lanes().first()?;
+        let remaining = vehicle.get_lane_length() - vehicle.get_front();
+        (remaining < YIELD_DISTANCE && self.busy_crosswalks.contains(&Crosswalk::new(node, next.get_edge()))).then_some(Leader {
+            gap: remaining,
+            speed: 0.0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traffic::LaneDefinition;
+    use crate::traffic::LaneType::{Grass, NormalForward, NormalReverse, Sidewalk};
+
+    /// A wide road over three nodes away from the default grid, with edges of the given length whose only sidewalk
+    /// changes sides at the middle node. Returns the manager and the nodes in order.
+    fn kinked_sidewalk(length: f32) -> (NodeManager, [NodeId; 3]) {
+        let mut node_manager = NodeManager::new();
+        let nodes = [0.0, length, 2.0 * length].map(|x| node_manager.add_node(Vec2::new(x, 5000.0)));
+        node_manager.make_edge(nodes[0], nodes[1], 1.0, LaneDefinition::from_lanes(&[Sidewalk, NormalReverse, NormalReverse, NormalReverse, NormalForward, NormalForward, NormalForward, Grass]).unwrap());
+        node_manager.make_edge(nodes[1], nodes[2], 1.0, LaneDefinition::from_lanes(&[Grass, NormalReverse, NormalReverse, NormalReverse, NormalForward, NormalForward, NormalForward, Sidewalk]).unwrap());
+        (node_manager, nodes)
+    }
+
+    /// Lets a pedestrian walk from the first to the last node and returns it with the lane of vehicles arriving at the
+    /// crosswalk it takes.
+    fn spawn_crossing(simulation: &mut Simulation, node_manager: &NodeManager, nodes: [NodeId; 3]) -> (usize, DirectedLane) {
+        let id = simulation.spawn_pedestrian(node_manager, nodes[0], nodes[2]).unwrap().0;
+        let crosswalk = simulation.pedestrians[id].segments.iter().find_map(|segment| segment.crosswalk).unwrap();
+        assert_eq!(crosswalk.get_node(), nodes[1]);
+        let lane = *node_manager.get_lane_graph().get_arrivals(nodes[1]).iter().find(|lane| lane.get_edge() == crosswalk.get_edge() && RoutingProfile::Car.permits(lane.get_type(node_manager))).unwrap();
+        (id, lane)
+    }
+
+    fn place(simulation: &mut Simulation, node_manager: &NodeManager, lane: DirectedLane) -> usize {
+        simulation.vehicles.insert(Vehicle::new(node_manager, RoutingProfile::Car, vec![lane], simulation.ticks))
+    }
+
+    #[test]
+    fn pedestrians_walk_the_route_and_cross_where_the_sidewalk_changes_side() {
+        let (node_manager, nodes) = kinked_sidewalk(100.0);
+        let links = node_manager.get_sidewalk_graph().route(&node_manager, nodes[0], nodes[2]).unwrap();
+        let routed = links.iter().map(|link| link.from_pos.distance(link.to_pos)).sum::<f32>() + links.windows(2).map(|pair| pair[0].to_pos.distance(pair[1].from_pos)).sum::<f32>();
+        let mut simulation = Simulation::new();
+        let (id, _) = spawn_crossing(&mut simulation, &node_manager, nodes);
+        let pedestrian = &simulation.pedestrians[id];
+        let walked = pedestrian.segments.iter().map(WalkSegment::length).sum::<f32>();
+        assert!((walked - routed).abs() < 1e-3, "walks {walked} of {routed}");
+        let segments = pedestrian.segments.len() as f32;
+        let mut ticks = 0;
+        while !simulation.pedestrians.is_empty() {
+            simulation.tick(&node_manager);
+            ticks += 1;
+        }
+        let expected = walked / pedestrian_speed();
+        assert!((expected..expected + segments + 1.0).contains(&(ticks as f32)), "{ticks} ticks for {expected}");
+        assert_eq!(simulation.get_pedestrian_stats(), PedestrianStats {
+            arrived: 1,
+            crossings: 1,
+            wait_time: 0,
+        });
+    }
+
+    #[test]
+    fn pedestrians_wait_for_a_gap_in_the_traffic() {
+        let (node_manager, nodes) = kinked_sidewalk(200.0);
+        let mut simulation = Simulation::new();
+        let (id, lane) = spawn_crossing(&mut simulation, &node_manager, nodes);
+        //A vehicle every 50 ticks, much less than the gap pedestrians wait for
+        for tick in 0..5000 {
+            if tick % 50 == 0 {
+                place(&mut simulation, &node_manager, lane);
+            }
+            simulation.tick(&node_manager);
+            assert_eq!(simulation.get_pedestrian_stats().crossings, 0);
+        }
+        assert!(simulation.pedestrians[id].is_waiting());
+        while simulation.get_pedestrian_stats().crossings == 0 {
+            simulation.tick(&node_manager);
+        }
+        assert!(simulation.get_pedestrian_stats().wait_time > 2000);
+    }
+
+    #[test]
+    fn vehicles_give_way_to_pedestrians_on_the_crosswalk() {
+        let (node_manager, nodes) = kinked_sidewalk(80.0);
+        let mut simulation = Simulation::new();
+        let (id, lane) = spawn_crossing(&mut simulation, &node_manager, nodes);
+        while simulation.pedestrians[id].get_crosswalk().is_none() {
+            simulation.tick(&node_manager);
+        }
+        let vehicle = place(&mut simulation, &node_manager, lane);
+        let crosswalk_start = simulation.vehicles[vehicle].get_lane_length() - crosswalk_clearance(&node_manager, nodes[1]);
+        let mut ticks = 0;
+        while simulation.pedestrians.get(id).is_some_and(|pedestrian| pedestrian.get_crosswalk().is_some()) {
+            simulation.tick(&node_manager);
+            ticks += 1;
+            let vehicle = &simulation.vehicles[vehicle];
+            assert!(vehicle.get_front() <= crosswalk_start + 1e-3, "{} past {crosswalk_start}", vehicle.get_front());
+        }
+        //Without the pedestrian, the vehicle would have been over the crosswalk by then
+        let mut alone = Simulation::new();
+        let control = place(&mut alone, &node_manager, lane);
+        for _ in 0..ticks {
+            alone.tick(&node_manager);
+        }
+        assert!(alone.vehicles.get(control).is_none_or(|vehicle| vehicle.get_front() > crosswalk_start));
+        //Drives on once the crosswalk is clear
+        for _ in 0..1000 {
+            simulation.tick(&node_manager);
+        }
+        assert!(!simulation.vehicles.contains(vehicle));
+    }
+
+    fn pedestrian_speed() -> f32 {
+        DriverParams::for_profile(RoutingProfile::Pedestrian).max_speed
+    }
+}
@@ -0,0 +1,204 @@
+use crate::math::if_else;
+use crate::node::junction::JunctionControl;
+use crate::node::lane_graph::DirectedLane;
+use crate::node::{EdgeId, NodeManager};
+use crate::sim::vehicle::Vehicle;
+use crate::sim::{Occupancy, Simulation};
+use crate::traffic::side::Turn;
+
+/// Vehicles further than this from an unsignalised junction do not yet decide whether they may enter it.
+const DECISION_DISTANCE: f32 = 40.0;
+/// Vehicles with priority further than this from the junction are never in the way.
+const CONFLICT_DISTANCE: f32 = 120.0;
+/// Vehicles slower than this are standing, either waiting to enter or queued, and do not block others.
+pub(super) const STANDSTILL_SPEED: f32 = 0.01;
+
+impl Simulation {
+    /// Whether `vehicle` has to wait at the end of `lane`, its own or one of its edge it considers changing to, before
+    /// entering the unsignalised junction there, because it has to stop first or a vehicle with priority is too close
+    /// to let it in.
+    pub(super) fn must_give_way(&self, node_manager: &NodeManager, occupancy: &Occupancy, id: usize, vehicle: &Vehicle, lane: DirectedLane) -> bool {
+        let Some(next) = vehicle.get_upcoming_lanes().first() else {
+            return false;
+        };
+        let node = lane.get_to(node_manager);
+        if self.signals.contains_key(&node) || !lane.get_type(node_manager).is_carriageway() || vehicle.get_lane_length() - vehicle.get_front() > DECISION_DISTANCE {
+            return false;
+        }
+        let control = node_manager.get_junction_control(node);
+        let majors = if_else!(control.has_major_road() => node_manager.get_major_approaches(node) ; vec![]);
+        let own_major = majors.contains(&lane.get_edge());
+        let must_stop = match control {
+            JunctionControl::Stop => !own_major,
+            JunctionControl::AllWayStop => true,
+            _ => false,
+        };
+        match vehicle.get_stopped_at() {
+            None if must_stop => return true,
+            Some(stopped_at) if control == JunctionControl::AllWayStop => return self.has_waiting_precedence(node_manager, occupancy, id, lane, stopped_at),
+            _ => {}
+        }
+        let crosses = node_manager.classify_turn(lane, *next) == node_manager.get_traffic_side().far_turn();
+        let critical_gap = critical_gap(control, own_major);
+        node_manager.get_lane_graph().get_arrivals(node).iter()
+            .filter(|approach| approach.get_edge() != lane.get_edge() && approach.get_type(node_manager).is_carriageway())
+            .filter(|approach| has_priority(node_manager, control, &majors, lane, crosses, **approach))
+            .any(|approach| occupancy.get(approach).is_some_and(|vehicles| vehicles.iter().rev().map(|other| &self.vehicles[*other]).any(|other| {
+                let remaining = other.get_lane_length() - other.get_front();
+                !other.get_upcoming_lanes().is_empty() && remaining < CONFLICT_DISTANCE && other.get_speed() > STANDSTILL_SPEED && remaining / other.get_speed() < critical_gap
+            })))
+    }
+
+    /// At an all-way stop, whether a vehicle on another approach stopped before vehicle `id` and is still waiting.
+    fn has_waiting_precedence(&self, node_manager: &NodeManager, occupancy: &Occupancy, id: usize, lane: DirectedLane, stopped_at: u64) -> bool {
+        node_manager.get_lane_graph().get_arrivals(lane.get_to(node_manager)).iter()
+            .filter(|approach| approach.get_edge() != lane.get_edge())
+            .filter_map(|approach| occupancy.get(approach)?.last())
+            .any(|other| self.vehicles[*other].get_stopped_at().is_some_and(|other_stopped_at| (other_stopped_at, *other) < (stopped_at, id)))
+    }
+}
+
+/// Ticks a vehicle with priority has to be away from the junction for a vehicle giving way to enter in front of it.
+fn critical_gap(control: JunctionControl, own_major: bool) -> f32 {
+    match control {
+        JunctionControl::Uncontrolled | JunctionControl::AllWayStop => 60.0,
+        //Only turns across the major road give way there
+        _ if own_major => 70.0,
+        JunctionControl::PriorityRoad => 80.0,
+        JunctionControl::Yield => 90.0,
+        JunctionControl::Stop => 100.0,
+    }
+}
+
+/// Whether traffic arriving along `other` has priority over a vehicle on `own`, `crosses` telling whether that
+/// vehicle turns across the opposing traffic. On a major road only turns across oncoming traffic give way, minor
+/// approaches give way to the major road, and between equal approaches traffic from the near side goes first.
+fn has_priority(node_manager: &NodeManager, control: JunctionControl, majors: &[EdgeId], own: DirectedLane, crosses: bool, other: DirectedLane) -> bool {
+    let from = node_manager.get_node_pos(own.get_from(node_manager)).unwrap();
+    let junction = node_manager.get_node_pos(own.get_to(node_manager)).unwrap();
+    let other_from = node_manager.get_node_pos(other.get_from(node_manager)).unwrap();
+    let relation = Turn::classify(junction - from, other_from - junction);
+    let oncoming = crosses && relation == Turn::Straight;
+    let near_side = relation == node_manager.get_traffic_side().near_turn();
+    if !control.has_major_road() {
+        return near_side || oncoming;
+    }
+    match (majors.contains(&own.get_edge()), majors.contains(&other.get_edge())) {
+        (true, false) => false,
+        (false, true) => true,
+        (true, true) => oncoming,
+        (false, false) => near_side || oncoming,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::NodeId;
+    use crate::traffic::LaneType::{NormalForward, NormalReverse, Sidewalk};
+    use crate::traffic::{LaneDefinition, RoutingProfile};
+    use ggez::glam::Vec2;
+
+    /// A junction away from the default grid with an arm towards each of the positions, relative to the junction,
+    /// at the given speeds. Returns the manager, the junction, and the far node and edge of every arm.
+    fn junction(arms: &[(Vec2, f32)], control: JunctionControl) -> (NodeManager, NodeId, Vec<(NodeId, EdgeId)>) {
+        let mut node_manager = NodeManager::new();
+        let centre = node_manager.add_node(Vec2::new(0.0, 5000.0));
+        let arms = arms.iter().map(|(offset, speed)| {
+            let node = node_manager.add_node(Vec2::new(0.0, 5000.0) + *offset);
+            (node, node_manager.make_edge(node, centre, *speed, LaneDefinition::from_lanes(&[Sidewalk, NormalReverse, NormalForward, Sidewalk]).unwrap()))
+        }).collect();
+        node_manager.set_junction_control(centre, Some(control));
+        (node_manager, centre, arms)
+    }
+
+    /// Spawns cars driving between the nodes, each after the given delay, and returns the ticks at which they left
+    /// their first edge, and whether they stopped before doing so.
+    fn entries(node_manager: &NodeManager, trips: &[(NodeId, NodeId, u64)]) -> Vec<(u64, bool)> {
+        let mut simulation = Simulation::new();
+        let mut vehicles = vec![None; trips.len()];
+        let mut entries = vec![None; trips.len()];
+        while entries.iter().any(Option::is_none) && simulation.ticks < 5000 {
+            for (index, (start, goal, delay)) in trips.iter().enumerate() {
+                if simulation.ticks == *delay {
+                    let id = simulation.spawn(node_manager, *start, *goal, RoutingProfile::Car).unwrap();
+                    vehicles[index] = Some((id.0, simulation.vehicles[id.0].get_lane().get_edge(), false));
+                }
+                if entries[index].is_none() && let Some((id, edge, stopped)) = &mut vehicles[index] {
+                    let vehicle = &simulation.vehicles[*id];
+                    *stopped |= vehicle.get_stopped_at().is_some();
+                    if vehicle.get_lane().get_edge() != *edge {
+                        entries[index] = Some((simulation.ticks, *stopped));
+                    }
+                }
+            }
+            simulation.tick(node_manager);
+        }
+        entries.into_iter().map(|entry| entry.expect("A vehicle never entered the junction")).collect()
+    }
+
+    #[test]
+    fn the_major_road_of_a_t_junction_is_its_fastest_straight_through_road() {
+        let (node_manager, centre, arms) = junction(&[(Vec2::new(-500.0, 0.0), 2.0), (Vec2::new(500.0, 0.0), 2.0), (Vec2::new(0.0, 300.0), 1.0)], JunctionControl::PriorityRoad);
+        let mut majors = node_manager.get_major_approaches(centre);
+        majors.sort_by_key(|edge| arms.iter().position(|(_, arm)| arm == edge));
+        assert_eq!(majors, [arms[0].1, arms[1].1]);
+        //A single fastest edge continues along the straightest of the next fastest
+        let (node_manager, centre, arms) = junction(&[(Vec2::new(-500.0, 0.0), 3.0), (Vec2::new(500.0, 100.0), 1.0), (Vec2::new(0.0, 300.0), 1.0)], JunctionControl::PriorityRoad);
+        assert_eq!(node_manager.get_major_approaches(centre), [arms[0].1, arms[1].1]);
+    }
+
+    #[test]
+    fn priority_follows_the_major_road() {
+        let (node_manager, centre, arms) = junction(&[(Vec2::new(-500.0, 0.0), 2.0), (Vec2::new(500.0, 0.0), 2.0), (Vec2::new(0.0, 300.0), 1.0)], JunctionControl::PriorityRoad);
+        let arrival = |arm: usize| *node_manager.get_lane_graph().get_arrivals(centre).iter().find(|lane| lane.get_edge() == arms[arm].1 && lane.get_type(&node_manager).is_carriageway()).unwrap();
+        let majors = node_manager.get_major_approaches(centre);
+        let has_priority = |own: usize, crosses: bool, other: usize| has_priority(&node_manager, JunctionControl::PriorityRoad, &majors, arrival(own), crosses, arrival(other));
+        //The minor road gives way to both directions of the major road, which never gives way to it
+        assert!(has_priority(2, false, 0) && has_priority(2, true, 0) && has_priority(2, false, 1));
+        assert!(!has_priority(0, false, 2) && !has_priority(0, true, 2) && !has_priority(1, true, 2));
+        //On the major road, only turns across traffic give way to oncoming traffic
+        assert!(!has_priority(0, false, 1));
+        assert!(has_priority(0, true, 1));
+        assert_eq!(critical_gap(JunctionControl::PriorityRoad, true), critical_gap(JunctionControl::Stop, true));
+        assert!(critical_gap(JunctionControl::Stop, false) > critical_gap(JunctionControl::PriorityRoad, false));
+    }
+
+    #[test]
+    fn minor_roads_give_way_at_a_t_junction() {
+        //Without priority, the car on the minor road goes first, the other one coming from its far side
+        let arms = [(Vec2::new(-500.0, 0.0), 2.0), (Vec2::new(500.0, 0.0), 2.0), (Vec2::new(0.0, -300.0), 1.0)];
+        let trips = |arms: &[(NodeId, EdgeId)]| [(arms[2].0, arms[1].0, 0), (arms[0].0, arms[1].0, 50)];
+        let (node_manager, _, nodes) = junction(&arms, JunctionControl::Uncontrolled);
+        let [(minor, false), (major, false)] = entries(&node_manager, &trips(&nodes))[..] else {
+            panic!("Vehicles stopped at an uncontrolled junction");
+        };
+        assert!(minor < major);
+        for control in [JunctionControl::PriorityRoad, JunctionControl::Yield, JunctionControl::Stop] {
+            let (node_manager, _, nodes) = junction(&arms, control);
+            let [(minor, minor_stopped), (major, false)] = entries(&node_manager, &trips(&nodes))[..] else {
+                panic!("The major road stopped with {control:?}");
+            };
+            assert!(major < minor, "{control:?}");
+            assert_eq!(minor_stopped, control == JunctionControl::Stop);
+        }
+    }
+
+    #[test]
+    fn all_way_stops_serve_vehicles_in_the_order_they_stopped() {
+        let arms = [-500.0, 500.0].map(|x| (Vec2::new(x, 0.0), 1.0)).into_iter().chain([-500.0, 500.0].map(|y| (Vec2::new(0.0, y), 1.0))).collect::<Vec<_>>();
+        let (node_manager, _, nodes) = junction(&arms, JunctionControl::AllWayStop);
+        let [(alone, true)] = entries(&node_manager, &[(nodes[0].0, nodes[1].0, 0)])[..] else {
+            panic!("A vehicle entered an all-way stop without stopping");
+        };
+        //Whoever stops first goes first, whichever side the other comes from, and the other one waits for it
+        for delays in [(0, 1), (1, 0)] {
+            let [(west, true), (south, true)] = entries(&node_manager, &[(nodes[0].0, nodes[1].0, delays.0), (nodes[3].0, nodes[2].0, delays.1)])[..] else {
+                panic!("Vehicles entered an all-way stop without stopping");
+            };
+            assert_eq!(west < south, delays.0 < delays.1, "{delays:?}");
+            assert_eq!(west.min(south), alone);
+            assert!(west.max(south) > alone + 1, "{delays:?}");
+        }
+    }
+}
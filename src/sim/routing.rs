@@ -0,0 +1,84 @@
+use crate::node::lane_graph::DirectedLane;
+use crate::node::{NodeManager, TravelTimes};
+use crate::sim::vehicle::Trip;
+use crate::sim::{route_heuristic, Simulation};
+
+/// Weight of a new sample in the smoothed travel time of its edge.
+const SMOOTHING: f32 = 0.2;
+/// Ticks between updates of the travel times the router uses, and between checks whether vehicles should reroute.
+const ROUTING_INTERVAL: u64 = 200;
+/// Vehicles take a new route once the rest of their route takes this many times as long as the fastest one.
+const REROUTE_FACTOR: f32 = 1.3;
+/// Ticks a new route has to save at least before vehicles take it.
+const MIN_SAVING: f32 = 100.0;
+
+impl Simulation {
+    /// Whether new trips and rerouting use the measured travel times instead of the static ones of the edges.
+    pub fn is_dynamic_routing(&self) -> bool {
+        self.dynamic_routing
+    }
+
+    pub fn set_dynamic_routing(&mut self, dynamic_routing: bool) {
+        self.dynamic_routing = dynamic_routing;
+    }
+
+    /// How many times vehicles took a faster route on the way.
+    pub fn get_reroutes(&self) -> u32 {
+        self.reroutes
+    }
+
+    /// The travel times routes are searched with, [`None`] when routing statically.
+    pub(super) fn get_travel_times(&self) -> Option<&TravelTimes> {
+        self.dynamic_routing.then_some(&self.travel_times)
+    }
+
+    /// Folds the time a vehicle took to travel the edge of the lane into its smoothed travel time.
+    pub(super) fn record_travel_time(&mut self, node_manager: &NodeManager, lane: DirectedLane, ticks: f32) {
+        let Some(edge) = node_manager.get_edge(lane.get_edge()) else {
+            return;
+        };
+        let smoothed = self.smoothed_travel_times.entry((lane.get_edge(), lane.get_direction())).or_insert_with(|| edge.get_travel_time(node_manager));
+        *smoothed += SMOOTHING * (ticks - *smoothed);
+    }
+
+    /// Every [`ROUTING_INTERVAL`] ticks, updates the travel times the router uses and, when routing dynamically, moves
+    /// through trips whose remaining route got much slower than the fastest one onto that route. Vehicles stuck on an
+    /// edge for longer than its smoothed travel time count as a sample of their own, so jams show before vehicles
+    /// leave them.
+    pub(super) fn update_routing(&mut self, node_manager: &NodeManager) {
+        if !self.ticks.is_multiple_of(ROUTING_INTERVAL) {
+            return;
+        }
+        self.travel_times = self.smoothed_travel_times.clone();
+        self.travel_times.retain(|(edge, _), _| node_manager.get_edge(*edge).is_some());
+        for (_, vehicle) in &self.vehicles {
+            let lane = vehicle.get_lane();
+            let Some(edge) = node_manager.get_edge(lane.get_edge()) else {
+                continue;
+            };
+            let travel_time = self.travel_times.entry((lane.get_edge(), lane.get_direction())).or_insert_with(|| edge.get_travel_time(node_manager));
+            *travel_time = travel_time.max((self.ticks - vehicle.get_edge_entered_at()) as f32);
+        }
+        if !self.dynamic_routing {
+            return;
+        }
+        let mut reroutes = vec![];
+        for (id, vehicle) in &self.vehicles {
+            if !matches!(vehicle.get_trip(), Trip::Through) {
+                continue;
+            }
+            let remaining = &vehicle.get_route()[vehicle.get_route_index()..];
+            let Some(route) = node_manager.lane_route_from(vehicle.get_lane(), vehicle.get_goal(), vehicle.get_profile(), Some(&self.travel_times), route_heuristic(node_manager, vehicle.get_profile())) else {
+                continue;
+            };
+            let (current, fastest) = (node_manager.route_cost(remaining, vehicle.get_profile(), Some(&self.travel_times)), node_manager.route_cost(&route, vehicle.get_profile(), Some(&self.travel_times)));
+            if current > fastest * REROUTE_FACTOR && current - fastest > MIN_SAVING {
+                reroutes.push((id, route));
+            }
+        }
+        self.reroutes += reroutes.len() as u32;
+        for (id, route) in reroutes {
+            self.vehicles[id].set_route(node_manager, route);
+        }
+    }
+}
@@ -0,0 +1,476 @@
+use crate::math::if_else;
+use crate::node::lane_graph::DirectedLane;
+use crate::node::{EdgeId, NodeId, NodeManager};
+use std::error::Error;
+use std::f32::consts::PI;
+use std::fmt::{Display, Formatter};
+
+/// Incoming edges whose directions differ by more than this from opposite ones get separate phases by default.
+const OPPOSITE_TOLERANCE: f32 = PI / 6.0;
+const DEFAULT_CYCLE_LENGTH: u32 = 1200;
+const DEFAULT_AMBER: u32 = 60;
+const DEFAULT_ALL_RED: u32 = 20;
+const DEFAULT_MIN_GREEN: u32 = 100;
+const DEFAULT_MAX_GREEN: u32 = 800;
+const DEFAULT_GAP: u32 = 40;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum SignalAspect {
+    Green,
+    Amber,
+    Red,
+}
+
+/// A set of incoming lanes that have green at the same time, and for how long in fixed-time operation.
+#[derive(Clone, Debug)]
+pub struct SignalPhase {
+    green: Vec<DirectedLane>,
+    green_time: u32,
+}
+
+impl SignalPhase {
+    pub fn new(green: Vec<DirectedLane>, green_time: u32) -> Self {
+        SignalPhase {
+            green,
+            green_time,
+        }
+    }
+
+    /// A phase giving green to every lane arriving at `node` along one of the edges.
+    pub fn for_edges(node_manager: &NodeManager, node: NodeId, edges: &[EdgeId], green_time: u32) -> Self {
+        let green = node_manager.get_lane_graph().get_arrivals(node).iter().copied().filter(|lane| edges.contains(&lane.get_edge())).collect();
+        SignalPhase::new(green, green_time)
+    }
+
+    pub fn get_green(&self) -> &[DirectedLane] {
+        &self.green
+    }
+
+    pub fn get_green_time(&self) -> u32 {
+        self.green_time
+    }
+}
+
+/// The phases of a signal in the order they are served. Every phase ends with amber and then all-red.
+#[derive(Clone, Debug)]
+pub struct SignalPlan {
+    phases: Vec<SignalPhase>,
+    amber: u32,
+    all_red: u32,
+}
+
+impl SignalPlan {
+    pub fn new(phases: Vec<SignalPhase>, amber: u32, all_red: u32) -> Result<Self, SignalPlanError> {
+        if phases.is_empty() {
+            return Err(SignalPlanError::NoPhases);
+        }
+        Ok(SignalPlan {
+            phases,
+            amber,
+            all_red,
+        })
+    }
+
+    /// Divides the green time left in a cycle of the given length between the lane groups, proportionally to the
+    /// splits.
+    pub fn with_splits(groups: Vec<Vec<DirectedLane>>, cycle_length: u32, splits: &[f32], amber: u32, all_red: u32) -> Result<Self, SignalPlanError> {
+        if groups.len() != splits.len() {
+            return Err(SignalPlanError::SplitCountMismatch {
+                phases: groups.len(),
+                splits: splits.len(),
+            });
+        }
+        if splits.iter().any(|split| !split.is_finite() || *split <= 0.0) {
+            return Err(SignalPlanError::InvalidSplit);
+        }
+        let lost_time = groups.len() as u32 * (amber + all_red);
+        if cycle_length <= lost_time {
+            return Err(SignalPlanError::CycleTooShort {
+                cycle_length,
+                lost_time,
+            });
+        }
+        let total = splits.iter().sum::<f32>();
+        let effective_green = (cycle_length - lost_time) as f32;
+        let phases = groups.into_iter().zip(splits).map(|(green, split)| SignalPhase::new(green, (effective_green * split / total).round().max(1.0) as u32)).collect();
+        SignalPlan::new(phases, amber, all_red)
+    }
+
+    /// Gives one phase to each pair of opposing incoming edges, and one to every edge without an opposite, sharing the
+    /// cycle equally.
+    pub fn default_for(node_manager: &NodeManager, node: NodeId) -> Result<Self, SignalPlanError> {
+        let pos = node_manager.get_node_pos(node).unwrap();
+        let mut edges = Vec::new();
+        for lane in node_manager.get_lane_graph().get_arrivals(node) {
+            if !edges.contains(&lane.get_edge()) {
+                edges.push(lane.get_edge());
+            }
+        }
+        let direction = |edge: EdgeId| (node_manager.get_node_pos(node_manager.get_edge(edge).unwrap().get_other_node(node)).unwrap() - pos).normalize_or_zero();
+        let mut groups = Vec::<Vec<EdgeId>>::new();
+        for edge in edges {
+            if groups.iter().any(|group| group.contains(&edge)) {
+                continue;
+            }
+            let opposite = groups.iter_mut().find(|group| group.len() == 1 && direction(group[0]).angle_to(direction(edge)).abs() > PI - OPPOSITE_TOLERANCE);
+            match opposite {
+                Some(group) => group.push(edge),
+                None => groups.push(vec![edge]),
+            }
+        }
+        let groups = groups.iter().map(|edges| SignalPhase::for_edges(node_manager, node, edges, 0).green).collect::<Vec<_>>();
+        let splits = vec![1.0; groups.len()];
+        SignalPlan::with_splits(groups, DEFAULT_CYCLE_LENGTH, &splits, DEFAULT_AMBER, DEFAULT_ALL_RED)
+    }
+
+    pub fn get_phases(&self) -> &[SignalPhase] {
+        &self.phases
+    }
+
+    pub fn get_amber(&self) -> u32 {
+        self.amber
+    }
+
+    pub fn get_all_red(&self) -> u32 {
+        self.all_red
+    }
+
+    /// Ticks from the start of the cycle to the start of green of the phase.
+    pub fn phase_start(&self, phase: usize) -> u32 {
+        self.phases[..phase].iter().map(|phase| phase.green_time + self.amber + self.all_red).sum()
+    }
+
+    /// Length of a full cycle in fixed-time operation, in ticks.
+    pub fn cycle_length(&self) -> u32 {
+        self.phases.iter().map(|phase| phase.green_time + self.amber + self.all_red).sum()
+    }
+
+    /// Whether the lane is given green by any phase, lanes that are not are not signalled.
+    pub fn controls(&self, lane: DirectedLane) -> bool {
+        self.phases.iter().any(|phase| phase.green.contains(&lane))
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SignalMode {
+    /// Every phase lasts the green time of the plan.
+    FixedTime,
+    /// Green is extended while vehicles keep arriving, ending once no vehicle was detected for `gap` ticks or after
+    /// `max_green` ticks. It always lasts at least `min_green` ticks.
+    Actuated {
+        min_green: u32,
+        max_green: u32,
+        gap: u32,
+    },
+}
+
+impl SignalMode {
+    pub fn actuated() -> Self {
+        SignalMode::Actuated {
+            min_green: DEFAULT_MIN_GREEN,
+            max_green: DEFAULT_MAX_GREEN,
+            gap: DEFAULT_GAP,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            SignalMode::FixedTime => SignalMode::actuated(),
+            SignalMode::Actuated { .. } => SignalMode::FixedTime,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Stage {
+    Green,
+    Amber,
+    AllRed,
+}
+
+pub struct SignalController {
+    plan: SignalPlan,
+    mode: SignalMode,
+    /// Ticks after the start of the simulation at which fixed-time cycles start.
+    offset: u32,
+    phase: usize,
+    stage: Stage,
+    /// Ticks spent in the current stage.
+    elapsed: u32,
+    /// Ticks since a vehicle was last detected on a lane with green.
+    since_detection: u32,
+}
+
+impl SignalController {
+    pub fn new(plan: SignalPlan, mode: SignalMode) -> Self {
+        SignalController {
+            plan,
+            mode,
+            offset: 0,
+            phase: 0,
+            stage: Stage::Green,
+            elapsed: 0,
+            since_detection: 0,
+        }
+    }
+
+    pub fn get_plan(&self) -> &SignalPlan {
+        &self.plan
+    }
+
+    /// Replaces the plan, continuing at the point of the new cycle given by the offset.
+    pub fn set_plan(&mut self, plan: SignalPlan, now: u64) {
+        self.plan = plan;
+        self.synchronise(now);
+    }
+
+    pub fn get_mode(&self) -> SignalMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: SignalMode) {
+        self.mode = mode;
+    }
+
+    pub fn get_offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// Shifts the fixed-time cycle so it starts `offset` ticks after the start of the simulation, `now` being the
+    /// current tick.
+    pub fn set_offset(&mut self, offset: u32, now: u64) {
+        self.offset = offset % self.plan.cycle_length().max(1);
+        self.synchronise(now);
+    }
+
+    /// Jumps to the stage the fixed-time cycle is in at tick `now`.
+    pub(super) fn synchronise(&mut self, now: u64) {
+        let cycle_length = self.plan.cycle_length().max(1) as u64;
+        let mut position = ((now + cycle_length - self.offset as u64 % cycle_length) % cycle_length) as u32;
+        self.since_detection = 0;
+        for (index, phase) in self.plan.phases.iter().enumerate() {
+            self.phase = index;
+            for (stage, length) in [(Stage::Green, phase.green_time), (Stage::Amber, self.plan.amber), (Stage::AllRed, self.plan.all_red)] {
+                if position < length {
+                    self.stage = stage;
+                    self.elapsed = position;
+                    return;
+                }
+                position -= length;
+            }
+        }
+    }
+
+    pub fn get_phase(&self) -> usize {
+        self.phase
+    }
+
+    /// What the lane is shown, or [`None`] if the signal does not control it.
+    pub fn get_aspect(&self, lane: DirectedLane) -> Option<SignalAspect> {
+        if !self.plan.controls(lane) {
+            return None;
+        }
+        let current = self.plan.phases[self.phase].green.contains(&lane);
+        Some(match self.stage {
+            Stage::Green if current => SignalAspect::Green,
+            Stage::Amber if current => SignalAspect::Amber,
+            _ => SignalAspect::Red,
+        })
+    }
+
+    /// Advances the signal by one tick, `detected` telling whether a vehicle is close to the stop line of a lane with
+    /// green.
+    pub fn tick(&mut self, detected: bool) {
+        self.elapsed += 1;
+        self.since_detection = if_else!(detected => 0 ; self.since_detection + 1);
+        let done = match self.stage {
+            Stage::Green => match self.mode {
+                SignalMode::FixedTime => self.elapsed >= self.plan.phases[self.phase].green_time,
+                SignalMode::Actuated { min_green, max_green, gap } => self.elapsed >= max_green || (self.elapsed >= min_green && self.since_detection >= gap),
+            },
+            Stage::Amber => self.elapsed >= self.plan.amber,
+            Stage::AllRed => self.elapsed >= self.plan.all_red,
+        };
+        if done {
+            self.elapsed = 0;
+            self.stage = match self.stage {
+                Stage::Green => Stage::Amber,
+                Stage::Amber => Stage::AllRed,
+                Stage::AllRed => {
+                    self.phase = (self.phase + 1) % self.plan.phases.len();
+                    self.since_detection = 0;
+                    Stage::Green
+                }
+            };
+        }
+    }
+
+    /// Whether pedestrians may start crossing the edge with a crosswalk taking `crossing_time` ticks. Crosswalks get
+    /// walk during the green of phases giving no green to traffic arriving along their edge, for as long as the
+    /// green lasts long enough to cross.
+    pub fn permits_crossing(&self, edge: EdgeId, crossing_time: u32) -> bool {
+        let green_time = match self.mode {
+            SignalMode::FixedTime => self.plan.phases[self.phase].green_time,
+            SignalMode::Actuated { max_green, .. } => max_green,
+        };
+        self.stage == Stage::Green && self.elapsed + crossing_time <= green_time && self.plan.phases[self.phase].green.iter().all(|lane| lane.get_edge() != edge)
+    }
+
+    /// The lanes currently shown green.
+    pub fn get_green_lanes(&self) -> &[DirectedLane] {
+        if_else!(self.stage == Stage::Green => &self.plan.phases[self.phase].green ; &[])
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SignalPlanError {
+    NoPhases,
+    SplitCountMismatch {
+        phases: usize,
+        splits: usize,
+    },
+    InvalidSplit,
+    CycleTooShort {
+        cycle_length: u32,
+        lost_time: u32,
+    },
+}
+
+impl Display for SignalPlanError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignalPlanError::NoPhases => write!(f, "A signal plan needs at least one phase"),
+            SignalPlanError::SplitCountMismatch { phases, splits } => write!(f, "Expected {phases} green splits, got {splits}"),
+            SignalPlanError::InvalidSplit => write!(f, "Green splits must be positive numbers"),
+            SignalPlanError::CycleTooShort { cycle_length, lost_time } => write!(f, "Cycle of {cycle_length} ticks leaves no green time after {lost_time} ticks of amber and all-red"),
+        }
+    }
+}
+
+impl Error for SignalPlanError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::lane_graph::LaneId;
+    use crate::traffic::LaneType::{Grass, NormalForward};
+    use crate::traffic::{LaneDefinition, LaneDirection};
+    use ggez::glam::Vec2;
+    use SignalAspect::{Amber, Green, Red};
+
+    const AMBER: u32 = 3;
+    const ALL_RED: u32 = 2;
+
+    /// Lanes on two separate roads far away from the default grid.
+    fn lanes() -> (DirectedLane, DirectedLane) {
+        let mut node_manager = NodeManager::new();
+        let [a, b] = [0.0, 1000.0].map(|x| {
+            let from = node_manager.add_node(Vec2::new(x, 5000.0));
+            let to = node_manager.add_node(Vec2::new(x + 500.0, 5000.0));
+            let edge = node_manager.make_edge(from, to, 1.0, LaneDefinition::from_lanes(&[Grass, NormalForward, Grass]).unwrap());
+            DirectedLane::new(LaneId::new(edge, 1), LaneDirection::Forward)
+        });
+        (a, b)
+    }
+
+    /// A controller giving green to `a` and then to `b`.
+    fn controller(a: DirectedLane, b: DirectedLane, green_time: u32, mode: SignalMode) -> SignalController {
+        let plan = SignalPlan::new(vec![SignalPhase::new(vec![a], green_time), SignalPhase::new(vec![b], green_time)], AMBER, ALL_RED).unwrap();
+        SignalController::new(plan, mode)
+    }
+
+    /// Ticks until the lane, which has green, stops having it, with vehicles detected on the ticks `detected` is true.
+    fn green_ticks(signal: &mut SignalController, lane: DirectedLane, detected: impl Fn(u32) -> bool) -> u32 {
+        let mut ticks = 0;
+        while signal.get_aspect(lane) == Some(Green) {
+            signal.tick(detected(ticks));
+            ticks += 1;
+        }
+        assert_eq!(signal.get_aspect(lane), Some(Amber));
+        ticks
+    }
+
+    #[test]
+    fn fixed_time_signals_cycle_through_the_phases() {
+        let (a, b) = lanes();
+        let mut signal = controller(a, b, 4, SignalMode::FixedTime);
+        let mut aspects = vec![];
+        for _ in 0..2 * signal.get_plan().cycle_length() {
+            aspects.push((signal.get_aspect(a).unwrap(), signal.get_aspect(b).unwrap()));
+            //Detections do not matter in fixed-time operation
+            signal.tick(true);
+        }
+        let cycle = [(Green, Red, 4), (Amber, Red, 3), (Red, Red, 2), (Red, Green, 4), (Red, Amber, 3), (Red, Red, 2)].iter().flat_map(|(a, b, ticks)| vec![(*a, *b); *ticks]).collect::<Vec<_>>();
+        assert_eq!(aspects, [cycle.clone(), cycle].concat());
+    }
+
+    #[test]
+    fn signals_only_control_lanes_of_their_phases() {
+        let (a, b) = lanes();
+        let signal = controller(a, a, 4, SignalMode::FixedTime);
+        assert_eq!(signal.get_aspect(b), None);
+        assert_eq!(signal.get_green_lanes(), [a]);
+    }
+
+    #[test]
+    fn offsets_shift_the_cycle() {
+        let (a, b) = lanes();
+        let mut signal = controller(a, b, 4, SignalMode::FixedTime);
+        //The cycle started 10 ticks ago, 1 tick into the green of `b`
+        signal.set_offset(0, 10);
+        assert_eq!(signal.get_phase(), 1);
+        assert_eq!(green_ticks(&mut signal, b, |_| false), 3);
+        signal.set_offset(19 + 5, 20);
+        assert_eq!(signal.get_offset(), 6);
+        assert_eq!((signal.get_aspect(a), signal.get_aspect(b)), (Some(Red), Some(Amber)));
+    }
+
+    #[test]
+    fn actuated_signals_gap_out_after_the_minimum_green() {
+        let (a, b) = lanes();
+        let mode = SignalMode::Actuated {
+            min_green: 10,
+            max_green: 30,
+            gap: 4,
+        };
+        assert_eq!(green_ticks(&mut controller(a, b, 4, mode), a, |_| false), 10);
+        //The gap is measured from the last detection
+        assert_eq!(green_ticks(&mut controller(a, b, 4, mode), a, |tick| tick < 15), 19);
+        assert_eq!(green_ticks(&mut controller(a, b, 4, mode), a, |tick| tick % 4 == 0), 30);
+        //Gaps of four ticks between detections end the green as soon as the minimum is reached
+        assert_eq!(green_ticks(&mut controller(a, b, 4, mode), a, |tick| tick % 5 == 0), 10);
+    }
+
+    #[test]
+    fn actuated_signals_extend_green_up_to_the_maximum() {
+        let (a, b) = lanes();
+        let mut signal = controller(a, b, 4, SignalMode::actuated());
+        assert_eq!(green_ticks(&mut signal, a, |_| true), DEFAULT_MAX_GREEN);
+        for _ in 0..AMBER + ALL_RED {
+            signal.tick(true);
+        }
+        //Detections are counted again for the next phase
+        assert_eq!(green_ticks(&mut signal, b, |tick| tick == 0), DEFAULT_MIN_GREEN);
+    }
+
+    #[test]
+    fn crossings_get_walk_while_their_edge_has_red() {
+        let (a, b) = lanes();
+        let mut signal = controller(a, b, 10, SignalMode::FixedTime);
+        assert!(signal.permits_crossing(b.get_edge(), 10));
+        assert!(!signal.permits_crossing(a.get_edge(), 10));
+        //Too late to cross within the green
+        signal.tick(false);
+        assert!(!signal.permits_crossing(b.get_edge(), 10));
+        assert!(signal.permits_crossing(b.get_edge(), 9));
+        for _ in 1..10 {
+            signal.tick(false);
+        }
+        assert!(!signal.permits_crossing(b.get_edge(), 0));
+        //Actuated signals may extend green up to the maximum
+        let mut signal = controller(a, b, 10, SignalMode::actuated());
+        signal.tick(false);
+        assert!(signal.permits_crossing(b.get_edge(), DEFAULT_MAX_GREEN - 1));
+        assert!(!signal.permits_crossing(b.get_edge(), DEFAULT_MAX_GREEN));
+    }
+}
@@ -0,0 +1,343 @@
+use crate::math::if_else;
+use crate::node::lane_graph::DirectedLane;
+use crate::node::{EdgeId, NodeId, NodeManager};
+use crate::sim::idm::DriverParams;
+use crate::sim::signal::{SignalController, SignalMode, SignalPhase, SignalPlan, SignalPlanError};
+use crate::sim::Simulation;
+use crate::traffic::RoutingProfile;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// Vehicles per tick a lane discharges at during green, one vehicle every two seconds.
+const SATURATION_FLOW: f32 = 1.0 / 40.0;
+/// The constant term of Webster's optimal cycle length, five seconds.
+const WEBSTER_CONSTANT: f32 = 100.0;
+const MIN_CYCLE_LENGTH: f32 = 600.0;
+const MAX_CYCLE_LENGTH: f32 = 2400.0;
+/// Flow ratio assumed for phases without measured traffic, so they keep a short green.
+const MIN_FLOW_RATIO: f32 = 0.01;
+
+/// Mean delay per vehicle in ticks before and after signal timings changed. Oversaturated signals have infinite delay.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TimingReport {
+    pub before: f32,
+    pub after: f32,
+}
+
+/// Builds a plan with the phases of `plan`, using Webster's optimal cycle length for the critical flow ratios of the
+/// phases and splitting the green time proportionally to them. `flow` gives the vehicles per tick arriving on a lane.
+pub fn webster_plan(plan: &SignalPlan, flow: impl Fn(DirectedLane) -> f32) -> Result<SignalPlan, SignalPlanError> {
+    let ratios = plan.get_phases().iter().map(|phase| phase.get_green().iter().map(|lane| flow(*lane) / SATURATION_FLOW).fold(0.0, f32::max)).collect::<Vec<_>>();
+    let total = ratios.iter().sum::<f32>();
+    let lost_time = (plan.get_phases().len() as u32 * (plan.get_amber() + plan.get_all_red())) as f32;
+    let cycle_length = if_else!(total < 1.0 => ((1.5 * lost_time + WEBSTER_CONSTANT) / (1.0 - total)).clamp(MIN_CYCLE_LENGTH, MAX_CYCLE_LENGTH) ; MAX_CYCLE_LENGTH);
+    let groups = plan.get_phases().iter().map(|phase| phase.get_green().to_vec()).collect();
+    let splits = ratios.iter().map(|ratio| ratio.max(MIN_FLOW_RATIO)).collect::<Vec<_>>();
+    SignalPlan::with_splits(groups, cycle_length.round() as u32, &splits, plan.get_amber(), plan.get_all_red())
+}
+
+/// Mean delay per vehicle at a fixed-time signal from Webster's delay formula, weighted by the flow on every lane.
+pub fn expected_delay(plan: &SignalPlan, flow: impl Fn(DirectedLane) -> f32) -> f32 {
+    let cycle_length = plan.cycle_length() as f32;
+    let mut total_flow = 0.0;
+    let mut total_delay = 0.0;
+    for phase in plan.get_phases() {
+        let green_share = phase.get_green_time() as f32 / cycle_length;
+        for lane in phase.get_green() {
+            let flow = flow(*lane);
+            if flow <= 0.0 {
+                continue;
+            }
+            let saturation = flow / (green_share * SATURATION_FLOW);
+            if saturation >= 1.0 {
+                return f32::INFINITY;
+            }
+            let uniform = cycle_length * (1.0 - green_share).powi(2) / (2.0 * (1.0 - green_share * saturation));
+            let random = saturation.powi(2) / (2.0 * flow * (1.0 - saturation));
+            total_flow += flow;
+            total_delay += flow * (uniform + random);
+        }
+    }
+    if_else!(total_flow > 0.0 => total_delay / total_flow ; 0.0)
+}
+
+/// A signal along a coordinated route, with the phase serving the route and the travel time from the previous one.
+struct RouteSignal {
+    node: NodeId,
+    phase: usize,
+    travel_time: f32,
+}
+
+impl Simulation {
+    /// Retimes the signal at the node with [`webster_plan`], using the lane flows over the rolling window of the
+    /// metrics, so the plan follows the current demand. The report compares [`expected_delay`] under the old and the
+    /// new plan.
+    pub fn optimise_signal(&mut self, node: NodeId) -> Result<TimingReport, TimingError> {
+        let signal = self.signals.get(&node).ok_or(TimingError::NoSignal(node))?;
+        let flow = |lane| self.get_lane_flow(lane);
+        let plan = webster_plan(signal.get_plan(), flow)?;
+        let report = TimingReport {
+            before: expected_delay(signal.get_plan(), flow),
+            after: expected_delay(&plan, flow),
+        };
+        let now = self.ticks;
+        self.signals.get_mut(&node).unwrap().set_plan(plan, now);
+        Ok(report)
+    }
+
+    /// Turns the signals along the path from `start` into a green wave: they switch to fixed time with the longest
+    /// of their cycle lengths, and every one starts the green of the phase serving the path when a car leaving the
+    /// previous one at the start of green arrives. The report compares the delay of such cars at the following
+    /// signals, averaged over the green at the first one.
+    pub fn coordinate_signals(&mut self, node_manager: &NodeManager, start: NodeId, path: &[EdgeId]) -> Result<TimingReport, TimingError> {
+        let max_speed = DriverParams::for_profile(RoutingProfile::Car).max_speed;
+        let mut route = Vec::new();
+        let mut node = start;
+        let mut travel_time = 0.0;
+        for edge_id in path {
+            let edge = node_manager.get_edge(*edge_id).ok_or(TimingError::NoSignals)?;
+            travel_time += edge.get_length(node_manager) / edge.get_speed().min(max_speed);
+            node = edge.get_other_node(node);
+            if let Some(signal) = self.signals.get(&node) && let Some(phase) = signal.get_plan().get_phases().iter().position(|phase| phase.get_green().iter().any(|lane| lane.get_edge() == *edge_id)) {
+                route.push(RouteSignal {
+                    node,
+                    phase,
+                    travel_time,
+                });
+                travel_time = 0.0;
+            }
+        }
+        if route.is_empty() {
+            return Err(TimingError::NoSignals);
+        }
+        let before = self.progression_delay(&route);
+        let cycle_length = route.iter().map(|signal| self.signals[&signal.node].get_plan().cycle_length()).max().unwrap();
+        let now = self.ticks;
+        let mut green_start = None::<f32>;
+        for route_signal in &route {
+            let signal = self.signals.get_mut(&route_signal.node).unwrap();
+            if signal.get_plan().cycle_length() != cycle_length {
+                let plan = stretch_plan(signal.get_plan(), cycle_length)?;
+                signal.set_plan(plan, now);
+            }
+            signal.set_mode(SignalMode::FixedTime);
+            let phase_start = signal.get_plan().phase_start(route_signal.phase) as f32;
+            let start = green_start.map_or(signal.get_offset() as f32 + phase_start, |previous| previous + route_signal.travel_time);
+            signal.set_offset((start - phase_start).rem_euclid(cycle_length as f32).round() as u32, now);
+            green_start = Some(start);
+        }
+        Ok(TimingReport {
+            before,
+            after: self.progression_delay(&route),
+        })
+    }
+
+    /// Mean time a car leaving the first signal of the route during its green waits at the following ones, assuming
+    /// fixed-time operation.
+    fn progression_delay(&self, route: &[RouteSignal]) -> f32 {
+        let first = &self.signals[&route[0].node];
+        let green_time = first.get_plan().get_phases()[route[0].phase].get_green_time();
+        let green_start = first.get_offset() + first.get_plan().phase_start(route[0].phase);
+        let mut total = 0.0;
+        for departure in 0..green_time {
+            let mut time = (green_start + departure) as f32;
+            for route_signal in &route[1..] {
+                time += route_signal.travel_time;
+                let wait = wait_for_green(&self.signals[&route_signal.node], route_signal.phase, time);
+                time += wait;
+                total += wait;
+            }
+        }
+        total / green_time.max(1) as f32
+    }
+}
+
+/// Ticks from `time` until the phase has green, if the signal runs its fixed-time cycle.
+fn wait_for_green(signal: &SignalController, phase: usize, time: f32) -> f32 {
+    let plan = signal.get_plan();
+    let cycle_length = plan.cycle_length() as f32;
+    let position = (time - signal.get_offset() as f32 - plan.phase_start(phase) as f32).rem_euclid(cycle_length);
+    if_else!(position < plan.get_phases()[phase].get_green_time() as f32 => 0.0 ; cycle_length - position)
+}
+
+/// Lengthens the plan to exactly `cycle_length` ticks, keeping the ratios of the green times. The last phase absorbs
+/// rounding, so coordinated signals never drift apart.
+fn stretch_plan(plan: &SignalPlan, cycle_length: u32) -> Result<SignalPlan, SignalPlanError> {
+    let clearance = plan.get_amber() + plan.get_all_red();
+    let lost_time = plan.get_phases().len() as u32 * clearance;
+    if cycle_length <= lost_time {
+        return Err(SignalPlanError::CycleTooShort {
+            cycle_length,
+            lost_time,
+        });
+    }
+    let green_total = plan.get_phases().iter().map(SignalPhase::get_green_time).sum::<u32>().max(1) as f32;
+    let available = cycle_length - lost_time;
+    let mut assigned = 0;
+    let last = plan.get_phases().len() - 1;
+    let phases = plan.get_phases().iter().enumerate().map(|(index, phase)| {
+        let green_time = if_else!(index == last => available - assigned ; ((phase.get_green_time() as f32 * available as f32 / green_total) as u32).max(1));
+        assigned += green_time;
+        SignalPhase::new(phase.get_green().to_vec(), green_time)
+    }).collect();
+    SignalPlan::new(phases, plan.get_amber(), plan.get_all_red())
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum TimingError {
+    NoSignal(NodeId),
+    /// No signal along the path controls the lanes of the path.
+    NoSignals,
+    InvalidPlan(SignalPlanError),
+}
+
+impl From<SignalPlanError> for TimingError {
+    fn from(error: SignalPlanError) -> Self {
+        TimingError::InvalidPlan(error)
+    }
+}
+
+impl Display for TimingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimingError::NoSignal(node) => write!(f, "There is no signal at {node:?}"),
+            TimingError::NoSignals => write!(f, "No signal along the path serves it"),
+            TimingError::InvalidPlan(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl Error for TimingError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::lane_graph::LaneId;
+    use crate::traffic::LaneType::{Grass, NormalForward};
+    use crate::traffic::{LaneDefinition, LaneDirection};
+    use ggez::glam::Vec2;
+
+    const SPEED: f32 = 2.0;
+    const SPACING: f32 = 900.0;
+
+    /// A manager with the default grid and a separate one-way road through `count` nodes far away from it. Returns the
+    /// nodes and the edges between them in order.
+    fn corridor(count: usize) -> (NodeManager, Vec<NodeId>, Vec<EdgeId>) {
+        let mut node_manager = NodeManager::new();
+        let nodes = (0..count).map(|index| node_manager.add_node(Vec2::new(index as f32 * SPACING, 5000.0))).collect::<Vec<_>>();
+        let edges = nodes.windows(2).map(|pair| node_manager.make_edge(pair[0], pair[1], SPEED, LaneDefinition::from_lanes(&[Grass, NormalForward, Grass]).unwrap())).collect();
+        (node_manager, nodes, edges)
+    }
+
+    fn lane(edge: EdgeId) -> DirectedLane {
+        DirectedLane::new(LaneId::new(edge, 1), LaneDirection::Forward)
+    }
+
+    /// A flow giving the lanes the flow ratios.
+    fn flow_ratios(ratios: &[(DirectedLane, f32)]) -> impl Fn(DirectedLane) -> f32 {
+        move |lane| ratios.iter().find(|(other, _)| *other == lane).map_or(0.0, |(_, ratio)| ratio * SATURATION_FLOW)
+    }
+
+    fn green_times(plan: &SignalPlan) -> Vec<u32> {
+        plan.get_phases().iter().map(SignalPhase::get_green_time).collect()
+    }
+
+    /// Two phases of the lanes, with four seconds of amber and all-red each.
+    fn two_phase_plan(a: DirectedLane, b: DirectedLane) -> SignalPlan {
+        SignalPlan::new(vec![SignalPhase::new(vec![a], 500), SignalPhase::new(vec![b], 500)], 60, 20).unwrap()
+    }
+
+    #[test]
+    fn webster_plan_matches_the_textbook() {
+        let (_, _, edges) = corridor(3);
+        let (a, b) = (lane(edges[0]), lane(edges[1]));
+        let flow = [(a, 0.3), (b, 0.2)];
+        //L = 8s and Y = 0.5, so C = (1.5 * 8 + 5) / (1 - 0.5) = 34s, leaving 26s of green split 3:2
+        let plan = webster_plan(&two_phase_plan(a, b), flow_ratios(&flow)).unwrap();
+        assert_eq!(plan.cycle_length(), 680);
+        assert_eq!(green_times(&plan), [312, 208]);
+        assert_eq!((plan.get_amber(), plan.get_all_red()), (60, 20));
+    }
+
+    #[test]
+    fn webster_plan_keeps_cycles_within_bounds() {
+        let (_, _, edges) = corridor(3);
+        let (a, b) = (lane(edges[0]), lane(edges[1]));
+        let plan = webster_plan(&two_phase_plan(a, b), flow_ratios(&[(a, 0.05)])).unwrap();
+        assert_eq!(plan.cycle_length(), MIN_CYCLE_LENGTH as u32);
+        assert_eq!(green_times(&plan), [367, 73]);
+        let plan = webster_plan(&two_phase_plan(a, b), flow_ratios(&[(a, 0.45), (b, 0.45)])).unwrap();
+        assert_eq!(plan.cycle_length(), MAX_CYCLE_LENGTH as u32);
+    }
+
+    #[test]
+    fn saturated_signals_get_the_longest_cycle_and_infinite_delay() {
+        let (_, _, edges) = corridor(3);
+        let (a, b) = (lane(edges[0]), lane(edges[1]));
+        for (flow, greens) in [([(a, 0.5), (b, 0.5)], [1120, 1120]), ([(a, 0.6), (b, 0.5)], [1222, 1018])] {
+            let plan = webster_plan(&two_phase_plan(a, b), flow_ratios(&flow)).unwrap();
+            assert_eq!(plan.cycle_length(), MAX_CYCLE_LENGTH as u32);
+            assert_eq!(green_times(&plan), greens);
+            assert_eq!(expected_delay(&plan, flow_ratios(&flow)), f32::INFINITY);
+        }
+    }
+
+    #[test]
+    fn expected_delay_follows_webster() {
+        let (_, _, edges) = corridor(3);
+        let (a, b) = (lane(edges[0]), lane(edges[1]));
+        let plan = SignalPlan::new(vec![SignalPhase::new(vec![a], 400), SignalPhase::new(vec![b], 400)], 0, 0).unwrap();
+        assert_eq!(expected_delay(&plan, flow_ratios(&[])), 0.0);
+        //Half the cycle green at 40% saturation: 800 * 0.25 / (2 * 0.8) + 0.16 / (2 * 0.005 * 0.6)
+        let delay = expected_delay(&plan, flow_ratios(&[(a, 0.2)]));
+        assert!((delay - (125.0 + 0.16 / 0.006)).abs() < 1e-3, "{delay}");
+        let retimed = webster_plan(&plan, flow_ratios(&[(a, 0.2), (b, 0.05)])).unwrap();
+        assert!(expected_delay(&retimed, flow_ratios(&[(a, 0.2), (b, 0.05)])) < expected_delay(&plan, flow_ratios(&[(a, 0.2), (b, 0.05)])));
+    }
+
+    #[test]
+    fn stretched_plans_keep_their_splits() {
+        let (_, _, edges) = corridor(3);
+        let plan = SignalPlan::new(vec![SignalPhase::new(vec![lane(edges[0])], 300), SignalPhase::new(vec![lane(edges[1])], 100)], 60, 20).unwrap();
+        let stretched = stretch_plan(&plan, 1001).unwrap();
+        assert_eq!(stretched.cycle_length(), 1001);
+        assert_eq!(green_times(&stretched), [630, 211]);
+        assert_eq!(stretch_plan(&plan, 160).err(), Some(SignalPlanError::CycleTooShort {
+            cycle_length: 160,
+            lost_time: 160,
+        }));
+    }
+
+    #[test]
+    fn coordinated_signals_form_a_green_wave() {
+        let (node_manager, nodes, edges) = corridor(3);
+        let mut simulation = Simulation::new();
+        //The path is served by the first phase at the first signal and by the second one at the shorter second signal
+        let first = SignalPlan::new(vec![SignalPhase::new(vec![lane(edges[0])], 500), SignalPhase::new(vec![], 500)], 60, 20).unwrap();
+        let second = SignalPlan::new(vec![SignalPhase::new(vec![], 300), SignalPhase::new(vec![lane(edges[1])], 300)], 60, 20).unwrap();
+        simulation.attach_signal(nodes[1], SignalController::new(first, SignalMode::actuated()));
+        simulation.attach_signal(nodes[2], SignalController::new(second, SignalMode::FixedTime));
+        let report = simulation.coordinate_signals(&node_manager, nodes[0], &edges).unwrap();
+        assert!(report.before > 0.0);
+        assert_eq!(report.after, 0.0);
+        let (first, second) = (simulation.get_signal(nodes[1]).unwrap(), simulation.get_signal(nodes[2]).unwrap());
+        assert_eq!(first.get_mode(), SignalMode::FixedTime);
+        assert_eq!(second.get_plan().cycle_length(), 1160);
+        assert_eq!(green_times(second.get_plan()), [500, 500]);
+        //Green at the second signal starts when a car leaving the first one at the start of green arrives
+        let travel_time = (SPACING / SPEED) as u32;
+        assert_eq!((second.get_offset() + second.get_plan().phase_start(1)) % 1160, (first.get_offset() + travel_time) % 1160);
+    }
+
+    #[test]
+    fn coordination_needs_signals_along_the_path() {
+        let (node_manager, nodes, edges) = corridor(3);
+        let mut simulation = Simulation::new();
+        assert_eq!(simulation.coordinate_signals(&node_manager, nodes[0], &edges).err(), Some(TimingError::NoSignals));
+        //A signal not serving the path does not count
+        let plan = SignalPlan::new(vec![SignalPhase::new(vec![], 500)], 60, 20).unwrap();
+        simulation.attach_signal(nodes[1], SignalController::new(plan, SignalMode::FixedTime));
+        assert_eq!(simulation.coordinate_signals(&node_manager, nodes[0], &edges).err(), Some(TimingError::NoSignals));
+        assert_eq!(simulation.optimise_signal(nodes[2]).err(), Some(TimingError::NoSignal(nodes[2])));
+    }
+}
@@ -0,0 +1,567 @@
+use crate::node::lane_graph::DirectedLane;
+use crate::node::{EdgeId, NodeManager};
+use crate::sim::idm::{DriverParams, Leader};
+use crate::sim::priority::STANDSTILL_SPEED;
+use crate::sim::vehicle::{Trip, Vehicle};
+use crate::sim::{route_heuristic, Simulation, LOOK_AHEAD};
+use crate::math::if_else;
+use crate::traffic::{LaneDirection, RoutingProfile};
+use ggez::glam::Vec2;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// How close to a stop the front of a standing bus has to be to serve it.
+const STOP_REACH: f32 = 1.5;
+/// Scheduled running times are this much longer than driving the route at the speed limit.
+const SCHEDULE_SLACK: f32 = 1.25;
+/// Buses arriving more than a minute before their scheduled time are early.
+const EARLY_TOLERANCE: u64 = 1200;
+/// Buses arriving more than three minutes after their scheduled time are late.
+const LATE_TOLERANCE: u64 = 3600;
+/// Buses following the previous bus of their line closer than this share of the scheduled headway are bunched.
+const BUNCHING_SHARE: f32 = 0.5;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct LineId(usize);
+
+/// A bus stop on an edge, `position` being the share of the edge before the stop in the direction of travel.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BusStop {
+    pub edge: EdgeId,
+    pub direction: LaneDirection,
+    pub position: f32,
+}
+
+/// When the buses of a line leave their first stop, in ticks of the simulation.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Schedule {
+    /// A bus every `headway` ticks from `first` on.
+    Headway {
+        first: u64,
+        headway: u32,
+    },
+    /// Buses at the given ticks, in ascending order.
+    Timetable(Vec<u64>),
+}
+
+impl Schedule {
+    pub fn departs_at(&self, tick: u64) -> bool {
+        match self {
+            Schedule::Headway { first, headway } => tick >= *first && (tick - first).is_multiple_of((*headway).max(1) as u64),
+            Schedule::Timetable(departures) => departures.binary_search(&tick).is_ok(),
+        }
+    }
+}
+
+/// Punctuality and regularity of the buses of a line at its stops.
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub struct LineStats {
+    pub departures: u32,
+    /// Buses serving a stop.
+    pub arrivals: u32,
+    pub early: u32,
+    pub late: u32,
+    /// Ticks buses arrived after their scheduled time, summed over all arrivals. Negative when they were early.
+    pub total_deviation: i64,
+    /// Arrivals at a stop the previous bus of the line served before.
+    pub headways: u32,
+    pub bunched: u32,
+    /// Stops buses could not serve because they had to leave the route of their line.
+    pub missed: u32,
+}
+
+impl LineStats {
+    /// Share of the arrivals that were neither early nor late.
+    pub fn on_time_share(&self) -> f32 {
+        (self.arrivals - self.early - self.late) as f32 / self.arrivals.max(1) as f32
+    }
+
+    /// Mean ticks buses arrived after their scheduled time.
+    pub fn mean_deviation(&self) -> f32 {
+        self.total_deviation as f32 / self.arrivals.max(1) as f32
+    }
+
+    /// Share of the arrivals following the previous bus so closely the two run as a bunch.
+    pub fn bunching_share(&self) -> f32 {
+        self.bunched as f32 / self.headways.max(1) as f32
+    }
+}
+
+/// A stop resolved to the route of its line.
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct RouteStop {
+    /// Index of the lane of the stop in the route.
+    route_index: usize,
+    /// Distance of the stop from the start of its lane.
+    distance: f32,
+    /// Ticks after leaving the first stop a bus is due.
+    due: u64,
+    /// When the latest bus served the stop, together with its departure from the first stop.
+    last_arrival: Option<(u64, u64)>,
+}
+
+pub struct BusLine {
+    name: String,
+    schedule: Schedule,
+    /// Ticks a bus stands at every stop.
+    dwell_time: u32,
+    route: Vec<DirectedLane>,
+    route_stops: Vec<RouteStop>,
+    stats: LineStats,
+}
+
+impl BusLine {
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_route(&self) -> &[DirectedLane] {
+        &self.route
+    }
+
+    /// The lane every stop is served from and the distance of the stop along it.
+    pub fn get_stop_positions(&self) -> impl Iterator<Item=(DirectedLane, f32)> + '_ {
+        self.route_stops.iter().map(|stop| (self.route[stop.route_index], stop.distance))
+    }
+
+    pub fn get_stats(&self) -> LineStats {
+        self.stats
+    }
+
+    /// Records a bus that left the first stop at `departure` serving stop `stop` at `now`.
+    fn record_arrival(&mut self, stop: usize, departure: u64, now: u64) {
+        let route_stop = &mut self.route_stops[stop];
+        let due = departure + route_stop.due;
+        self.stats.arrivals += 1;
+        self.stats.total_deviation += now as i64 - due as i64;
+        if now + EARLY_TOLERANCE < due {
+            self.stats.early += 1;
+        } //
+        else if now > due + LATE_TOLERANCE {
+            self.stats.late += 1;
+        }
+        match route_stop.last_arrival {
+            //Overtook the bus ahead, which then counts as the next one
+            Some((_, previous_departure)) if previous_departure > departure => return,
+            Some((previous_arrival, previous_departure)) => {
+                self.stats.headways += 1;
+                if ((now - previous_arrival) as f32) < BUNCHING_SHARE * (departure - previous_departure) as f32 {
+                    self.stats.bunched += 1;
+                }
+            }
+            None => {}
+        }
+        route_stop.last_arrival = Some((now, departure));
+    }
+}
+
+/// A bus in service on a line.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BusRun {
+    line: LineId,
+    /// The tick the bus was scheduled to leave the first stop.
+    departure: u64,
+    /// Index of the stop the bus serves next.
+    next_stop: usize,
+    /// The tick the bus leaves the stop it stands at.
+    dwelling_until: Option<u64>,
+}
+
+impl Simulation {
+    /// Adds a bus line serving the stops in order, on a route preferring bus lanes. Buses start at the first edge and
+    /// leave the network after the last stop.
+    pub fn add_line(&mut self, node_manager: &NodeManager, name: String, stops: &[BusStop], schedule: Schedule, dwell_time: u32) -> Result<LineId, LineError> {
+        if stops.len() < 2 {
+            return Err(LineError::TooFewStops);
+        }
+        let mut route_stops = Vec::with_capacity(stops.len());
+        let route = line_route(node_manager, stops, &mut route_stops)?;
+        let max_speed = DriverParams::for_profile(RoutingProfile::Bus).max_speed;
+        let lane_times = route.iter().map(|lane| {
+            let edge = node_manager.get_edge(lane.get_edge()).unwrap();
+            (edge.get_length(node_manager), edge.get_speed().min(max_speed))
+        }).collect::<Vec<_>>();
+        for (index, stop) in route_stops.iter_mut().enumerate() {
+            let running_time = lane_times[..stop.route_index].iter().map(|(length, speed)| length / speed).sum::<f32>() + stop.distance / lane_times[stop.route_index].1;
+            stop.due = (running_time * SCHEDULE_SLACK).round() as u64 + index as u64 * dwell_time as u64;
+        }
+        self.lines.push(BusLine {
+            name,
+            schedule,
+            dwell_time,
+            route,
+            route_stops,
+            stats: LineStats::default(),
+        });
+        Ok(LineId(self.lines.len() - 1))
+    }
+
+    /// Adds the bus lines described in `source`, one per line:
+    /// ```text
+    /// name; dwell_seconds; schedule; stops
+    /// 1; 15; every 120; 0 -500 0 -400 0.5, 0 -300 0 -200 0.5
+    /// 2; 15; at 30 330 450; 0 500 0 400 0.5, 0 300 0 200 0.5
+    /// ```
+    /// Schedules are either `every` followed by a headway or `at` followed by departure times, in seconds since the
+    /// start of the simulation. A stop is given by the positions of the nodes its edge runs from and to in the
+    /// direction of travel, and the share of the edge before the stop. Empty lines and lines starting with `#` are
+    /// ignored.
+    pub fn load_lines(&mut self, node_manager: &NodeManager, source: &str, ticks_per_second: u32) -> Result<Vec<LineId>, LineLoadError> {
+        let mut lines = vec![];
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |kind| LineLoadError {
+                line: index + 1,
+                kind,
+            };
+            let sections = line.split(';').map(str::trim).collect::<Vec<_>>();
+            if sections.len() != 4 {
+                return Err(error(LineLoadErrorKind::SectionCount(sections.len())));
+            }
+            let number = |field: &str| field.parse::<f32>().ok().filter(|number| number.is_finite()).ok_or_else(|| error(LineLoadErrorKind::InvalidNumber(field.to_string())));
+            let ticks = |field: &str| match number(field)? {
+                seconds if seconds >= 0.0 => Ok((seconds * ticks_per_second as f32).round() as u64),
+                _ => Err(error(LineLoadErrorKind::InvalidNumber(field.to_string()))),
+            };
+            let dwell_time = ticks(sections[1])? as u32;
+            let schedule = match sections[2].split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["every", headway] => Schedule::Headway {
+                    first: 0,
+                    headway: ticks(headway)?.max(1) as u32,
+                },
+                ["at", departures @ ..] => {
+                    let mut departures = departures.iter().map(|departure| ticks(departure)).collect::<Result<Vec<_>, _>>()?;
+                    departures.sort_unstable();
+                    departures.dedup();
+                    Schedule::Timetable(departures)
+                }
+                _ => return Err(error(LineLoadErrorKind::InvalidSchedule(sections[2].to_string()))),
+            };
+            let mut stops = vec![];
+            for stop in sections[3].split(',') {
+                let fields = stop.split_whitespace().collect::<Vec<_>>();
+                if fields.len() != 5 {
+                    return Err(error(LineLoadErrorKind::StopFieldCount(fields.len())));
+                }
+                let from = Vec2::new(number(fields[0])?, number(fields[1])?);
+                let to = Vec2::new(number(fields[2])?, number(fields[3])?);
+                let from_node = node_manager.try_node_collision(from);
+                let edge = from_node.zip(node_manager.try_node_collision(to))
+                    .and_then(|(from, to)| node_manager.get_node(from)?.find_edge(to, node_manager))
+                    .ok_or(error(LineLoadErrorKind::NoEdge(from, to)))?;
+                stops.push(BusStop {
+                    edge,
+                    direction: if_else!(from_node == Some(node_manager.get_edge(edge).unwrap().get_nodes().0) => LaneDirection::Forward ; LaneDirection::Reverse),
+                    position: number(fields[4])?,
+                });
+            }
+            lines.push(self.add_line(node_manager, sections[0].to_string(), &stops, schedule, dwell_time).map_err(|e| error(LineLoadErrorKind::Line(e)))?);
+        }
+        Ok(lines)
+    }
+
+    pub fn get_lines(&self) -> impl Iterator<Item=(LineId, &BusLine)> {
+        self.lines.iter().enumerate().map(|(index, line)| (LineId(index), line))
+    }
+
+    /// Buses of the line currently in service.
+    pub fn buses_in_service(&self, line: LineId) -> usize {
+        self.vehicles.iter().filter(|(_, vehicle)| vehicle.get_bus_run().is_some_and(|run| run.line == line)).count()
+    }
+
+    /// Puts a bus on the road for every line with a departure this tick.
+    pub(super) fn dispatch_buses(&mut self, node_manager: &NodeManager) {
+        let now = self.ticks;
+        for (index, line) in self.lines.iter_mut().enumerate() {
+            if !line.schedule.departs_at(now) {
+                continue;
+            }
+            let mut bus = Vehicle::new(node_manager, RoutingProfile::Bus, line.route.clone(), now);
+            bus.set_trip(Trip::Bus(BusRun {
+                line: LineId(index),
+                departure: now,
+                next_stop: 0,
+                dwelling_until: None,
+            }));
+            self.vehicles.insert(bus);
+            line.stats.departures += 1;
+        }
+    }
+
+    /// Lets buses standing at their next stop start dwelling there, and sends on the ones that dwelled long enough.
+    pub(super) fn update_buses(&mut self) {
+        let now = self.ticks;
+        for (_, vehicle) in &mut self.vehicles {
+            let Some(mut run) = vehicle.get_bus_run().copied() else {
+                continue;
+            };
+            let line = &mut self.lines[run.line.0];
+            match run.dwelling_until {
+                Some(until) if until <= now => {
+                    run.next_stop += 1;
+                    run.dwelling_until = None;
+                }
+                Some(_) => {}
+                None => if let Some(stop) = line.route_stops.get(run.next_stop) {
+                    if !on_line_route(vehicle, &line.route, stop.route_index) {
+                        line.stats.missed += 1;
+                        run.next_stop += 1;
+                    } //
+                    else if vehicle.get_route_index() == stop.route_index && vehicle.get_speed() < STANDSTILL_SPEED && vehicle.get_front() >= stop.distance - STOP_REACH {
+                        line.record_arrival(run.next_stop, run.departure, now);
+                        run.dwelling_until = Some(now + line.dwell_time as u64);
+                    }
+                }
+            }
+            vehicle.set_trip(Trip::Bus(run));
+        }
+    }
+
+    /// The next stop of a bus in service as a standing leader, so the bus pulls up and stays there while dwelling.
+    pub(super) fn bus_stop(&self, node_manager: &NodeManager, vehicle: &Vehicle, run: &BusRun) -> Option<Leader> {
+        let line = &self.lines[run.line.0];
+        let stop = line.route_stops.get(run.next_stop)?;
+        if !on_line_route(vehicle, &line.route, stop.route_index) {
+            return None;
+        }
+        let lane_start = vehicle.get_route()[vehicle.get_route_index()..stop.route_index].iter()
+            .map(|lane| node_manager.get_edge(lane.get_edge()).map_or(0.0, |edge| edge.get_length(node_manager)))
+            .sum::<f32>();
+        (lane_start - vehicle.get_distance() <= LOOK_AHEAD).then(|| Leader {
+            gap: lane_start + stop.distance - vehicle.get_front() + vehicle.get_driver().min_gap,
+            speed: 0.0,
+        })
+    }
+}
+
+/// Whether the bus has yet to pass the lane at `route_index` of the route of its line and still follows that route.
+/// Lane changes keep a bus on its route as long as it stays on the same edge.
+fn on_line_route(vehicle: &Vehicle, route: &[DirectedLane], route_index: usize) -> bool {
+    let line_lane = route[route_index];
+    vehicle.get_route_index() <= route_index && vehicle.get_route().get(route_index).is_some_and(|lane| lane.get_edge() == line_lane.get_edge() && lane.get_direction() == line_lane.get_direction())
+}
+
+/// The route through the stops, starting on the edge of the first one. Adds every stop to `route_stops`, not yet
+/// knowing when buses are due there.
+fn line_route(node_manager: &NodeManager, stops: &[BusStop], route_stops: &mut Vec<RouteStop>) -> Result<Vec<DirectedLane>, LineError> {
+    let profile = RoutingProfile::Bus;
+    let mut route = Vec::<DirectedLane>::new();
+    for (index, stop) in stops.iter().enumerate() {
+        let edge = node_manager.get_edge(stop.edge).filter(|_| (0.0..=1.0).contains(&stop.position)).ok_or(LineError::InvalidStop(index))?;
+        let distance = stop.position * edge.get_length(node_manager);
+        let same_lane = route_stops.last().is_some_and(|previous| previous.distance <= distance)
+            && route.last().is_some_and(|lane| lane.get_edge() == stop.edge && lane.get_direction() == stop.direction);
+        if !same_lane {
+            match route.last() {
+                Some(last) => {
+                    let leg = node_manager.lane_route_onto(*last, stop.edge, stop.direction, profile, route_heuristic(node_manager, profile)).ok_or(LineError::Unreachable(index))?;
+                    route.extend_from_slice(&leg[1..]);
+                }
+                None => {
+                    let first = edge.get_routable_lanes()
+                        .filter(|lane| lane.get_direction() == stop.direction && profile.permits(lane.get_type(node_manager)))
+                        .min_by(|a, b| profile.lane_cost_factor(a.get_type(node_manager)).total_cmp(&profile.lane_cost_factor(b.get_type(node_manager))))
+                        .ok_or(LineError::InvalidStop(index))?;
+                    route.push(first);
+                }
+            }
+        }
+        route_stops.push(RouteStop {
+            route_index: route.len() - 1,
+            distance,
+            due: 0,
+            last_arrival: None,
+        });
+    }
+    Ok(route)
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LineError {
+    TooFewStops,
+    /// The stop with the given index is not on an edge buses may travel in its direction.
+    InvalidStop(usize),
+    /// Buses cannot get from the previous stop to the stop with the given index.
+    Unreachable(usize),
+}
+
+impl Display for LineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LineError::TooFewStops => write!(f, "A line needs at least two stops"),
+            LineError::InvalidStop(index) => write!(f, "Stop {} is not on a road buses may use", index + 1),
+            LineError::Unreachable(index) => write!(f, "Buses cannot reach stop {} from the one before", index + 1),
+        }
+    }
+}
+
+impl Error for LineError {}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct LineLoadError {
+    line: usize,
+    kind: LineLoadErrorKind,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum LineLoadErrorKind {
+    SectionCount(usize),
+    InvalidNumber(String),
+    InvalidSchedule(String),
+    StopFieldCount(usize),
+    /// There is no edge between the nodes at the given positions.
+    NoEdge(Vec2, Vec2),
+    Line(LineError),
+}
+
+impl Display for LineLoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Line {}: ", self.line)?;
+        match &self.kind {
+            LineLoadErrorKind::SectionCount(count) => write!(f, "Expected 4 sections separated by `;`, got {count}"),
+            LineLoadErrorKind::InvalidNumber(number) => write!(f, "`{number}` is not a valid number"),
+            LineLoadErrorKind::InvalidSchedule(schedule) => write!(f, "`{schedule}` is neither `every <seconds>` nor `at <seconds>...`"),
+            LineLoadErrorKind::StopFieldCount(count) => write!(f, "Expected 5 numbers for a stop, got {count}"),
+            LineLoadErrorKind::NoEdge(from, to) => write!(f, "There is no edge from {}, {} to {}, {}", from.x, from.y, to.x, to.y),
+            LineLoadErrorKind::Line(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl Error for LineLoadError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICKS_PER_SECOND: u32 = 20;
+
+    fn load(source: &str) -> Result<Vec<LineId>, LineLoadError> {
+        Simulation::new().load_lines(&NodeManager::new(), source, TICKS_PER_SECOND)
+    }
+
+    fn error(line: usize, kind: LineLoadErrorKind) -> Option<LineLoadError> {
+        Some(LineLoadError {
+            line,
+            kind,
+        })
+    }
+
+    /// A simulation with a single line along the two stops of the first line of the example.
+    fn line() -> (Simulation, LineId) {
+        let mut simulation = Simulation::new();
+        let lines = simulation.load_lines(&NodeManager::new(), "1; 15; every 120; 0 -500 0 -400 0.5, 0 -300 0 -200 0.5", TICKS_PER_SECOND).unwrap();
+        (simulation, lines[0])
+    }
+
+    #[test]
+    fn reads_lines() {
+        let node_manager = NodeManager::new();
+        let mut simulation = Simulation::new();
+        let source = "# name; dwell_seconds; schedule; stops\n\n1; 15; every 120; 0 -500 0 -400 0.5, 0 -300 0 -200 0.5\n 2 ;1.5;at 450 30 330 30; 0 -200 0 -300 0.25 , 0 -400 0 -500 1";
+        let lines = simulation.load_lines(&node_manager, source, TICKS_PER_SECOND).unwrap();
+        assert_eq!(lines.len(), 2);
+        let edge = |from: (f32, f32), to: (f32, f32)| {
+            let from = node_manager.try_node_collision(Vec2::new(from.0, from.1)).unwrap();
+            node_manager.get_node(from).unwrap().find_edge(node_manager.try_node_collision(Vec2::new(to.0, to.1)).unwrap(), &node_manager).unwrap()
+        };
+        let first = &simulation.lines[0];
+        assert_eq!(first.get_name(), "1");
+        assert_eq!(first.dwell_time, 300);
+        assert_eq!(first.schedule, Schedule::Headway {
+            first: 0,
+            headway: 2400,
+        });
+        let second = &simulation.lines[1];
+        assert_eq!((second.get_name(), second.dwell_time), ("2", 30));
+        assert_eq!(second.schedule, Schedule::Timetable(vec![600, 6600, 9000]));
+        //Stops are served in order, in the direction they are given in
+        for (line, stops) in [(first, [((0.0, -500.0), (0.0, -400.0)), ((0.0, -300.0), (0.0, -200.0))]), (second, [((0.0, -200.0), (0.0, -300.0)), ((0.0, -400.0), (0.0, -500.0))])] {
+            let positions = line.get_stop_positions().collect::<Vec<_>>();
+            assert_eq!(positions.len(), 2);
+            for ((lane, _), (from, to)) in positions.into_iter().zip(stops) {
+                assert_eq!(lane.get_edge(), edge(from, to));
+                assert_eq!(lane.get_from(&node_manager), node_manager.try_node_collision(Vec2::new(from.0, from.1)).unwrap());
+            }
+            assert!(line.route_stops[0].due < line.route_stops[1].due);
+        }
+        assert_eq!(simulation.lines[0].get_stop_positions().map(|(_, distance)| distance).collect::<Vec<_>>(), [50.0, 50.0]);
+        assert_eq!(simulation.lines[1].get_stop_positions().map(|(_, distance)| distance).collect::<Vec<_>>(), [25.0, 100.0]);
+    }
+
+    #[test]
+    fn rejects_wrong_section_and_field_counts() {
+        assert_eq!(load("1; 15; every 120").err(), error(1, LineLoadErrorKind::SectionCount(3)));
+        assert_eq!(load("# Comment\n1; 15; every 120; 0 -500 0 -400 0.5; 0 -300 0 -200 0.5").err(), error(2, LineLoadErrorKind::SectionCount(5)));
+        assert_eq!(load("1; 15; every 120; 0 -500 0 -400 0.5, 0 -300 0 -200").err(), error(1, LineLoadErrorKind::StopFieldCount(4)));
+    }
+
+    #[test]
+    fn rejects_invalid_numbers_and_schedules() {
+        for (source, number) in [("1; long; every 120; 0 -500 0 -400 0.5, 0 -300 0 -200 0.5", "long"), ("1; -15; every 120; 0 -500 0 -400 0.5, 0 -300 0 -200 0.5", "-15"),
+            ("1; 15; at 30 inf; 0 -500 0 -400 0.5, 0 -300 0 -200 0.5", "inf"), ("1; 15; every 120; 0 -500 0 -400 half, 0 -300 0 -200 0.5", "half")] {
+            assert_eq!(load(source).err(), error(1, LineLoadErrorKind::InvalidNumber(number.to_string())), "{source}");
+        }
+        for schedule in ["every", "every 60 120", "hourly", ""] {
+            assert_eq!(load(&format!("1; 15; {schedule}; 0 -500 0 -400 0.5, 0 -300 0 -200 0.5")).err(), error(1, LineLoadErrorKind::InvalidSchedule(schedule.to_string())), "{schedule}");
+        }
+    }
+
+    #[test]
+    fn rejects_stops_without_edges_and_invalid_lines() {
+        assert_eq!(load("1; 15; every 120; 0 -500 0 -300 0.5, 0 -300 0 -200 0.5").err(), error(1, LineLoadErrorKind::NoEdge(Vec2::new(0.0, -500.0), Vec2::new(0.0, -300.0))));
+        assert_eq!(load("1; 15; every 120; 0 -500 0 -400 0.5, 0 -300 0 -250 0.5").err(), error(1, LineLoadErrorKind::NoEdge(Vec2::new(0.0, -300.0), Vec2::new(0.0, -250.0))));
+        assert_eq!(load("1; 15; every 120; 0 -500 0 -400 0.5").err(), error(1, LineLoadErrorKind::Line(LineError::TooFewStops)));
+        assert_eq!(load("1; 15; every 120; 0 -500 0 -400 0.5, 0 -300 0 -200 1.5").err(), error(1, LineLoadErrorKind::Line(LineError::InvalidStop(1))));
+    }
+
+    #[test]
+    fn schedules_depart_on_time() {
+        let headway = Schedule::Headway {
+            first: 100,
+            headway: 50,
+        };
+        assert_eq!((0..260).filter(|tick| headway.departs_at(*tick)).collect::<Vec<_>>(), [100, 150, 200, 250]);
+        let timetable = Schedule::Timetable(vec![5, 30, 31]);
+        assert_eq!((0..100).filter(|tick| timetable.departs_at(*tick)).collect::<Vec<_>>(), [5, 30, 31]);
+    }
+
+    #[test]
+    fn arrivals_are_early_on_time_or_late() {
+        let (mut simulation, id) = line();
+        let line = &mut simulation.lines[id.0];
+        let due = line.route_stops[1].due;
+        let departure = 10_000;
+        for deviation in [-(EARLY_TOLERANCE as i64) - 1, -(EARLY_TOLERANCE as i64), 0, LATE_TOLERANCE as i64, LATE_TOLERANCE as i64 + 1] {
+            line.record_arrival(1, departure, (departure + due).checked_add_signed(deviation).unwrap());
+        }
+        let stats = line.get_stats();
+        assert_eq!((stats.arrivals, stats.early, stats.late), (5, 1, 1));
+        assert_eq!(stats.on_time_share(), 0.6);
+        assert_eq!(stats.mean_deviation(), (LATE_TOLERANCE as f32 * 2.0 - EARLY_TOLERANCE as f32 * 2.0) / 5.0);
+        assert_eq!(LineStats::default().on_time_share(), 0.0);
+    }
+
+    #[test]
+    fn buses_following_closely_are_bunched() {
+        let (mut simulation, id) = line();
+        let line = &mut simulation.lines[id.0];
+        let due = line.route_stops[0].due;
+        //Buses leaving 2000 ticks apart arrive 1500, then 900 ticks apart
+        line.record_arrival(0, 0, due);
+        line.record_arrival(0, 2000, due + 1500);
+        line.record_arrival(0, 4000, due + 2400);
+        //The bus left behind arrives after the one overtaking it and is not compared with it
+        line.record_arrival(0, 8000, due + 8000);
+        line.record_arrival(0, 6000, due + 8100);
+        let stats = line.get_stats();
+        assert_eq!((stats.headways, stats.bunched), (3, 1));
+        assert_eq!(stats.bunching_share(), 1.0 / 3.0);
+        //Stops keep separate headways
+        line.record_arrival(1, 0, 0);
+        assert_eq!(line.get_stats().headways, 3);
+    }
+}
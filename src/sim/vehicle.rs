@@ -0,0 +1,311 @@
+use crate::node::lane_graph::DirectedLane;
+use crate::node::{NodeId, NodeManager, TravelTimes};
+use crate::sim::idm::{DriverParams, Leader};
+use crate::sim::parking::ParkingTrip;
+use crate::sim::priority::STANDSTILL_SPEED;
+use crate::sim::route_heuristic;
+use crate::sim::transit::BusRun;
+use crate::traffic::RoutingProfile;
+use ggez::glam::Vec2;
+
+/// A vehicle standing still closer than this to the end of its lane stopped at the stop line.
+const STOP_LINE_REACH: f32 = 4.0;
+
+/// What a vehicle does on its way and at the end of its route.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Trip {
+    /// Leaves the network at its goal.
+    Through,
+    Parking(ParkingTrip),
+    Bus(BusRun),
+}
+
+pub struct Vehicle {
+    profile: RoutingProfile,
+    driver: DriverParams,
+    route: Vec<DirectedLane>,
+    route_index: usize,
+    goal: NodeId,
+    trip: Trip,
+    /// Ticks left before the vehicle may change lanes again.
+    lane_change_cooldown: u32,
+    /// Tick the vehicle came to a standstill at the end of its current lane.
+    stopped_at: Option<u64>,
+    /// Tick the vehicle drove onto its current edge, or was placed on it.
+    edge_entered_at: u64,
+    /// Distance travelled along the current lane.
+    distance: f32,
+    /// Speed in world units per tick.
+    speed: f32,
+    centre_line: (Vec2, Vec2),
+    pos: Vec2,
+    previous_pos: Vec2,
+}
+
+impl Vehicle {
+    /// Places a vehicle at the start of its route, which must not be empty, at tick `now`.
+    pub fn new(node_manager: &NodeManager, profile: RoutingProfile, route: Vec<DirectedLane>, now: u64) -> Self {
+        let centre_line = route[0].get_centre_line(node_manager);
+        let goal = route[route.len() - 1].get_to(node_manager);
+        Vehicle {
+            profile,
+            driver: DriverParams::for_profile(profile),
+            route,
+            route_index: 0,
+            goal,
+            trip: Trip::Through,
+            lane_change_cooldown: 0,
+            stopped_at: None,
+            edge_entered_at: now,
+            distance: 0.0,
+            speed: 0.0,
+            centre_line,
+            pos: centre_line.0,
+            previous_pos: centre_line.0,
+        }
+    }
+
+    pub fn get_profile(&self) -> RoutingProfile {
+        self.profile
+    }
+
+    pub fn get_lane(&self) -> DirectedLane {
+        self.route[self.route_index]
+    }
+
+    /// The lanes of the route after the current one.
+    pub fn get_upcoming_lanes(&self) -> &[DirectedLane] {
+        &self.route[self.route_index + 1..]
+    }
+
+    /// Index of the current lane in the route.
+    pub fn get_route_index(&self) -> usize {
+        self.route_index
+    }
+
+    pub fn get_route(&self) -> &[DirectedLane] {
+        &self.route
+    }
+
+    pub fn get_goal(&self) -> NodeId {
+        self.goal
+    }
+
+    pub fn get_trip(&self) -> &Trip {
+        &self.trip
+    }
+
+    pub fn set_trip(&mut self, trip: Trip) {
+        self.trip = trip;
+    }
+
+    /// The parking trip the vehicle is on, if it is on one.
+    pub fn get_parking(&self) -> Option<&ParkingTrip> {
+        match &self.trip {
+            Trip::Parking(parking) => Some(parking),
+            _ => None,
+        }
+    }
+
+    /// The bus run the vehicle serves, if it is a bus in service.
+    pub fn get_bus_run(&self) -> Option<&BusRun> {
+        match &self.trip {
+            Trip::Bus(run) => Some(run),
+            _ => None,
+        }
+    }
+
+    /// Replaces the route, which has to start with the current lane.
+    pub fn set_route(&mut self, node_manager: &NodeManager, route: Vec<DirectedLane>) {
+        debug_assert_eq!(route.first(), Some(&self.get_lane()));
+        self.goal = route[route.len() - 1].get_to(node_manager);
+        self.route = route;
+        self.route_index = 0;
+    }
+
+    /// Distance travelled along the current lane by the centre of the vehicle.
+    pub fn get_distance(&self) -> f32 {
+        self.distance
+    }
+
+    /// Distance along the current lane of the front of the vehicle.
+    pub fn get_front(&self) -> f32 {
+        self.distance + self.get_length() / 2.0
+    }
+
+    /// Distance along the current lane of the rear of the vehicle.
+    pub fn get_rear(&self) -> f32 {
+        self.distance - self.get_length() / 2.0
+    }
+
+    pub fn get_speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn get_driver(&self) -> &DriverParams {
+        &self.driver
+    }
+
+    pub fn can_change_lane(&self) -> bool {
+        self.lane_change_cooldown == 0
+    }
+
+    /// The tick the vehicle drove onto its current edge, or was placed on it.
+    pub fn get_edge_entered_at(&self) -> u64 {
+        self.edge_entered_at
+    }
+
+    /// The tick the vehicle stopped at the end of its current lane, if it did.
+    pub fn get_stopped_at(&self) -> Option<u64> {
+        self.stopped_at
+    }
+
+    pub fn get_lane_length(&self) -> f32 {
+        self.centre_line.0.distance(self.centre_line.1)
+    }
+
+    pub fn get_pos(&self) -> Vec2 {
+        self.pos
+    }
+
+    pub fn get_previous_pos(&self) -> Vec2 {
+        self.previous_pos
+    }
+
+    /// Unit vector pointing in the direction of travel.
+    pub fn get_heading(&self) -> Vec2 {
+        (self.centre_line.1 - self.centre_line.0).normalize_or(Vec2::X)
+    }
+
+    pub fn get_length(&self) -> f32 {
+        match self.profile {
+            RoutingProfile::Car => 4.5,
+            RoutingProfile::Bus => 12.0,
+            RoutingProfile::Pedestrian => 0.6,
+            RoutingProfile::Cyclist => 1.8,
+            RoutingProfile::Truck => 10.0,
+        }
+    }
+
+    pub fn get_width(&self) -> f32 {
+        match self.profile {
+            RoutingProfile::Car => 1.8,
+            RoutingProfile::Bus | RoutingProfile::Truck => 2.5,
+            RoutingProfile::Pedestrian => 0.6,
+            RoutingProfile::Cyclist => 0.7,
+        }
+    }
+
+    /// How hard the driver wants to accelerate given the speed limit and the vehicle ahead.
+    pub fn get_acceleration(&self, node_manager: &NodeManager, leader: Option<Leader>) -> f32 {
+        let limit = node_manager.get_edge(self.get_lane().get_edge()).map_or(0.0, |edge| edge.get_speed());
+        self.driver.acceleration(self.speed, limit.min(self.driver.max_speed), leader)
+    }
+
+    /// Moves the vehicle by one tick with the given acceleration, never reversing. Returns false once it reached the
+    /// end of its route, or when its route no longer exists. `now` is the number of the tick, and `travel_times` the
+    /// ones to search a new route with after a lane change leaving the route.
+    pub fn tick(&mut self, node_manager: &NodeManager, acceleration: f32, travel_times: Option<&TravelTimes>, now: u64) -> bool {
+        self.previous_pos = self.pos;
+        self.lane_change_cooldown = self.lane_change_cooldown.saturating_sub(1);
+        let speed = self.speed + acceleration;
+        if speed < 0.0 {
+            //Stops within the tick
+            self.distance += -self.speed * self.speed / (2.0 * acceleration);
+            self.speed = 0.0;
+        } //
+        else {
+            self.distance += self.speed + acceleration / 2.0;
+            self.speed = speed;
+        }
+        loop {
+            let length = self.get_lane_length();
+            if self.distance < length {
+                break;
+            }
+            self.distance -= length;
+            if let Some(next) = self.route.get(self.route_index + 1) && !node_manager.get_lane_graph().get_successors(self.get_lane()).contains(next) {
+                //Changed to a lane that does not lead on along the route
+                let Some(route) = node_manager.lane_route_from(self.get_lane(), self.goal, self.profile, travel_times, route_heuristic(node_manager, self.profile)) else {
+                    return false;
+                };
+                self.route = route;
+                self.route_index = 0;
+            }
+            let edge = self.get_lane().get_edge();
+            self.route_index += 1;
+            if self.route_index == self.route.len() || !self.enter_lane(node_manager) {
+                return false;
+            }
+            if self.get_lane().get_edge() != edge {
+                self.edge_entered_at = now;
+            }
+        }
+        if self.stopped_at.is_none() && self.speed < STANDSTILL_SPEED && self.get_lane_length() - self.get_front() < STOP_LINE_REACH {
+            self.stopped_at = Some(now);
+        }
+        self.pos = self.centre_line.0 + self.get_heading() * self.distance;
+        true
+    }
+
+    /// Moves the vehicle sideways onto a parallel lane of the same edge, keeping its distance and speed.
+    pub fn change_lane(&mut self, node_manager: &NodeManager, lane: DirectedLane, cooldown: u32) {
+        self.route[self.route_index] = lane;
+        self.centre_line = lane.get_centre_line(node_manager);
+        self.lane_change_cooldown = cooldown;
+    }
+
+    fn enter_lane(&mut self, node_manager: &NodeManager) -> bool {
+        let lane = self.get_lane();
+        let Some(edge) = node_manager.get_edge(lane.get_edge()) else {
+            return false;
+        };
+        if edge.get_lane_def().get(lane.get_lane().get_index()).is_none_or(|lane_type| !self.profile.permits(lane_type)) {
+            return false;
+        }
+        self.centre_line = lane.get_centre_line(node_manager);
+        self.stopped_at = None;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::lane_graph::LaneId;
+    use crate::node::EdgeId;
+    use crate::traffic::LaneType::{Grass, NormalForward};
+    use crate::traffic::{LaneDefinition, LaneDirection};
+
+    fn lane(edge: EdgeId, index: u8) -> DirectedLane {
+        DirectedLane::new(LaneId::new(edge, index), LaneDirection::Forward)
+    }
+
+    #[test]
+    fn lane_changes_off_the_route_reroute_with_the_given_travel_times() {
+        //A two-lane road into a crossroads away from the default grid, every arm of which leads on to the goal
+        let mut node_manager = NodeManager::new();
+        let [west, centre, goal] = [Vec2::new(-500.0, 5000.0), Vec2::new(0.0, 5000.0), Vec2::new(1000.0, 5000.0)].map(|pos| node_manager.add_node(pos));
+        let road = |lanes: &[_]| LaneDefinition::from_lanes(lanes).unwrap();
+        let street = node_manager.make_edge(west, centre, 1.0, road(&[Grass, NormalForward, NormalForward, Grass]));
+        let arms = [Vec2::new(500.0, 5000.0), Vec2::new(0.0, 4500.0), Vec2::new(0.0, 5500.0)].map(|pos| {
+            let node = node_manager.add_node(pos);
+            (node_manager.make_edge(centre, node, 1.0, road(&[Grass, NormalForward, Grass])), node_manager.make_edge(node, goal, 1.0, road(&[Grass, NormalForward, Grass])))
+        });
+        let turns = |index| node_manager.get_lane_graph().get_successors(lane(street, index)).iter().map(|lane| lane.get_edge()).collect::<Vec<_>>();
+        let (far, near) = (turns(1), turns(2));
+        let near_arm = arms.iter().find(|(arm, _)| near.contains(arm) && !far.contains(arm)).unwrap();
+        let far_arm = arms.iter().find(|(arm, _)| far.contains(arm) && !near.contains(arm)).unwrap();
+        let straight = arms[0];
+        //The far lane cannot turn onto the planned arm, and the straight arm is the shortest way on
+        let drive = |travel_times: Option<&TravelTimes>| {
+            let mut vehicle = Vehicle::new(&node_manager, RoutingProfile::Car, vec![lane(street, 2), lane(near_arm.0, 1), lane(near_arm.1, 1)], 0);
+            vehicle.change_lane(&node_manager, lane(street, 1), 0);
+            assert!(vehicle.tick(&node_manager, 1000.0, travel_times, 0));
+            vehicle.get_route()[vehicle.get_route_index()..].iter().map(|lane| lane.get_edge()).collect::<Vec<_>>()
+        };
+        assert_eq!(drive(None), [straight.0, straight.1]);
+        let jammed = TravelTimes::from_iter([((straight.1, LaneDirection::Forward), 100_000.0)]);
+        assert_eq!(drive(Some(&jammed)), [far_arm.0, far_arm.1]);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::math::if_else;
+use crate::traffic::{LaneDefinition, LaneError, LaneType, LaneTypeManager};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+const SEPARATOR: char = '|';
+
+impl LaneTypeManager {
+    /// Parses a cross-section such as `Sidewalk|NormalReverse|NormalForward|Sidewalk`, listing lanes from left to right.
+    /// The size of the definition is the sum of the lane widths.
+    pub fn parse(&self, layout: &str) -> Result<LaneDefinition, LayoutParseError> {
+        let lanes = self.parse_lanes(layout)?;
+        LaneDefinition::from_lanes(&lanes).map_err(LayoutParseError::Size)
+    }
+
+    /// Like [`LaneTypeManager::parse`], but fails unless the lanes fill exactly the given size.
+    pub fn parse_sized(&self, layout: &str, size: u8) -> Result<LaneDefinition, LayoutParseError> {
+        let lanes = self.parse_lanes(layout)?;
+        LaneDefinition::with_size(size, &lanes).map_err(LayoutParseError::Size)
+    }
+
+    pub fn serialize(&self, lane_def: &LaneDefinition) -> String {
+        lane_def.lanes().map(LaneType::name_internal).collect::<Vec<_>>().join(&SEPARATOR.to_string())
+    }
+
+    fn parse_lanes(&self, layout: &str) -> Result<Vec<LaneType>, LayoutParseError> {
+        let layout = layout.trim();
+        if layout.is_empty() {
+            return Err(LayoutParseError::Empty);
+        }
+        let mut lanes = vec![];
+        for (position, name) in layout.split(SEPARATOR).map(str::trim).enumerate() {
+            if name.is_empty() {
+                return Err(LayoutParseError::EmptyLane {
+                    position,
+                });
+            }
+            match self.name_to_variant.get(name) {
+                Some(lane) => lanes.push(*lane),
+                None => return Err(LayoutParseError::UnknownLane {
+                    position,
+                    name: name.to_string(),
+                    suggestion: self.suggest(name),
+                }),
+            }
+        }
+        Ok(lanes)
+    }
+
+    fn suggest(&self, name: &str) -> Option<String> {
+        let lower = name.to_lowercase();
+        if let Some(exact) = self.name_to_variant.keys().find(|known| known.to_lowercase() == lower) {
+            return Some(exact.clone());
+        }
+        let max_distance = (name.chars().count() / 3).max(2);
+        self.name_to_variant.keys()
+            .map(|known| (edit_distance(&lower, &known.to_lowercase()), known))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min_by(|(a, a_name), (b, b_name)| a.cmp(b).then_with(|| a_name.cmp(b_name)))
+            .map(|(_, known)| known.clone())
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = diagonal + if_else!(a_char == *b_char => 0 ; 1);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum LayoutParseError {
+    Empty,
+    EmptyLane {
+        position: usize,
+    },
+    UnknownLane {
+        position: usize,
+        name: String,
+        suggestion: Option<String>,
+    },
+    Size(LaneError),
+}
+
+impl Display for LayoutParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayoutParseError::Empty => write!(f, "Lane layout is empty"),
+            LayoutParseError::EmptyLane { position } => write!(f, "Lane {position} of the layout is empty"),
+            LayoutParseError::UnknownLane { position, name, suggestion } => {
+                write!(f, "Unknown lane type `{name}` at lane {position}")?;
+                if let Some(suggestion) = suggestion {
+                    write!(f, ", did you mean `{suggestion}`?")?;
+                }
+                Ok(())
+            }
+            LayoutParseError::Size(error) => write!(f, "Invalid lane layout: {error}"),
+        }
+    }
+}
+
+impl Error for LayoutParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::IntoEnumIterator;
+
+    fn all_lanes() -> String {
+        LaneType::iter().map(LaneType::name_internal).collect::<Vec<_>>().join("|")
+    }
+
+    #[test]
+    fn round_trips() {
+        let lane_types = LaneTypeManager::new();
+        let layouts = [
+            "Sidewalk|ParkingForward|NormalForward|NormalReverse|Sidewalk".to_string(),
+            "Grass".to_string(),
+            all_lanes(),
+            //Enough lanes to spill over from the inline storage
+            ["BikeForward"; 40].join("|"),
+        ];
+        for layout in layouts {
+            let lane_def = lane_types.parse(&layout).unwrap();
+            let serialized = lane_types.serialize(&lane_def);
+            assert_eq!(serialized, layout);
+            let reparsed = lane_types.parse(&serialized).unwrap();
+            assert_eq!(reparsed.get_size(), lane_def.get_size());
+            assert_eq!(reparsed.lanes().collect::<Vec<_>>(), lane_def.lanes().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn ignores_whitespace_around_lanes() {
+        let lane_types = LaneTypeManager::new();
+        let lane_def = lane_types.parse(" Sidewalk | NormalForward ").unwrap();
+        assert_eq!(lane_types.serialize(&lane_def), "Sidewalk|NormalForward");
+        assert_eq!(lane_def.get_size(), 3);
+    }
+
+    #[test]
+    fn suggests_known_lanes() {
+        let lane_types = LaneTypeManager::new();
+        assert_eq!(lane_types.parse("Sidewalk|NormalForwrd|Sidewalk").err(), Some(LayoutParseError::UnknownLane {
+            position: 1,
+            name: "NormalForwrd".to_string(),
+            suggestion: Some("NormalForward".to_string()),
+        }));
+        assert_eq!(lane_types.parse("sidewalk").err(), Some(LayoutParseError::UnknownLane {
+            position: 0,
+            name: "sidewalk".to_string(),
+            suggestion: Some("Sidewalk".to_string()),
+        }));
+        assert_eq!(lane_types.parse("Motorway").err(), Some(LayoutParseError::UnknownLane {
+            position: 0,
+            name: "Motorway".to_string(),
+            suggestion: None,
+        }));
+        let message = lane_types.parse("Sidewalk|Gras").err().unwrap().to_string();
+        assert_eq!(message, "Unknown lane type `Gras` at lane 1, did you mean `Grass`?");
+    }
+
+    #[test]
+    fn rejects_empty_layouts() {
+        let lane_types = LaneTypeManager::new();
+        assert_eq!(lane_types.parse("  ").err(), Some(LayoutParseError::Empty));
+        assert_eq!(lane_types.parse("Sidewalk||Sidewalk").err(), Some(LayoutParseError::EmptyLane {
+            position: 1,
+        }));
+    }
+
+    #[test]
+    fn rejects_width_mismatch() {
+        let lane_types = LaneTypeManager::new();
+        assert_eq!(lane_types.parse_sized("Sidewalk|NormalForward", 4).err(), Some(LayoutParseError::Size(LaneError::WidthMismatch {
+            expected: 4,
+            actual: 3,
+        })));
+        assert_eq!(lane_types.parse_sized("Sidewalk|NormalForward", 3).unwrap().get_size(), 3);
+    }
+
+    #[test]
+    fn rejects_layouts_wider_than_the_maximum() {
+        let lane_types = LaneTypeManager::new();
+        let widest = ["Sidewalk"; crate::traffic::MAX_LANE_SIZE as usize].join("|");
+        assert_eq!(lane_types.parse(&widest).unwrap().get_size(), crate::traffic::MAX_LANE_SIZE);
+        let too_wide = ["NormalForward"; 128].join("|");
+        assert_eq!(lane_types.parse(&too_wide).err(), Some(LayoutParseError::Size(LaneError::TooWide(256))));
+    }
+}
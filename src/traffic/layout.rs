@@ -0,0 +1,202 @@
+use crate::traffic::side::TrafficSide;
+use crate::traffic::{LaneDefinition, LaneSeparator, LaneType};
+
+/// World width of one lane width unit, a [`crate::traffic::LaneWidth::Half`] lane is one unit wide.
+pub const WIDTH_PER_UNIT: f32 = 1.25;
+/// What lies beyond both sides of an edge when deciding the outermost separators.
+const OUTSIDE: LaneType = LaneType::Grass;
+
+#[derive(Copy, Clone, Debug)]
+pub struct LaneSlot {
+    lane_type: LaneType,
+    offset: f32,
+    width: f32,
+    pre_border: f32,
+    pre_separator: LaneSeparator,
+}
+
+impl LaneSlot {
+    pub fn get_type(&self) -> LaneType {
+        self.lane_type
+    }
+
+    /// Lateral offset of the centre of the lane from the centreline, positive to the left of the forward direction.
+    pub fn get_offset(&self) -> f32 {
+        self.offset
+    }
+
+    pub fn get_width(&self) -> f32 {
+        self.width
+    }
+
+    /// Offset of the border shared with the previous lane, where [`LaneSlot::get_pre_separator`] lies.
+    pub fn get_pre_border(&self) -> f32 {
+        self.pre_border
+    }
+
+    pub fn get_post_border(&self) -> f32 {
+        2.0 * self.offset - self.pre_border
+    }
+
+    pub fn get_pre_separator(&self) -> LaneSeparator {
+        self.pre_separator
+    }
+}
+
+pub struct LaneLayout {
+    lanes: Vec<LaneSlot>,
+    post_separator: LaneSeparator,
+    width: f32,
+}
+
+impl LaneLayout {
+    pub fn lanes(&self) -> &[LaneSlot] {
+        &self.lanes
+    }
+
+    pub fn get(&self, index: u8) -> Option<&LaneSlot> {
+        self.lanes.get(index as usize)
+    }
+
+    pub fn get_width(&self) -> f32 {
+        self.width
+    }
+
+    /// Every separator of the cross-section together with its lateral offset, in lane order, ending with the one after
+    /// the last lane at the outer border of the edge.
+    pub fn separators(&self) -> impl Iterator<Item=(f32, LaneSeparator)> + '_ {
+        let last = self.lanes.last().map(|lane| (lane.get_post_border(), self.post_separator));
+        self.lanes.iter().map(|lane| (lane.get_pre_border(), lane.pre_separator)).chain(last)
+    }
+}
+
+impl LaneDefinition {
+    /// Computes the cross-section of the edge, laying the lanes out from left to right, or from right to left under
+    /// left-hand traffic.
+    pub fn layout(&self, side: TrafficSide) -> LaneLayout {
+        let sign = side.lateral_sign();
+        let width = self.get_size() as f32 * WIDTH_PER_UNIT;
+        let mut border = width / 2.0;
+        let mut previous = OUTSIDE;
+        let mut first_lane = true;
+        let mut lanes = Vec::with_capacity(self.lane_count() as usize);
+        for lane in self.lanes() {
+            let lane_width = lane.width().units() as f32 * WIDTH_PER_UNIT;
+            lanes.push(LaneSlot {
+                lane_type: lane,
+                offset: sign * (border - lane_width / 2.0),
+                width: lane_width,
+                pre_border: sign * border,
+                pre_separator: lane.pre_separator(previous, first_lane),
+            });
+            border -= lane_width;
+            previous = lane;
+            first_lane &= !lane.is_carriageway();
+        }
+        //Looking back from outside the edge, the last lane is the first one
+        let post_separator = previous.pre_separator(OUTSIDE, true);
+        LaneLayout {
+            lanes,
+            post_separator,
+            width,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(lanes: &[LaneType], side: TrafficSide) -> LaneLayout {
+        LaneDefinition::from_lanes(lanes).unwrap().layout(side)
+    }
+
+    /// Checks that the lanes tile the edge from its left border to its right one without gaps.
+    fn assert_contiguous(layout: &LaneLayout, side: TrafficSide) {
+        let sign = side.lateral_sign();
+        let mut border = sign * layout.get_width() / 2.0;
+        for lane in layout.lanes() {
+            assert_eq!(lane.get_pre_border(), border);
+            assert_eq!(lane.get_width(), lane.get_type().width().units() as f32 * WIDTH_PER_UNIT);
+            assert_eq!(lane.get_offset(), border - sign * lane.get_width() / 2.0);
+            border = lane.get_post_border();
+        }
+        assert_eq!(border, -sign * layout.get_width() / 2.0);
+    }
+
+    #[test]
+    fn two_way() {
+        use crate::traffic::LaneBorder::Edge;
+        use crate::traffic::LaneCrossing::DoubleContinuous;
+        use crate::traffic::LaneFlow::Divergent;
+        use crate::traffic::LaneSeparator::{BorderStrip, Curb, Nothing, SeparationStrip};
+        use crate::traffic::LaneType::{NormalForward, NormalReverse, Sidewalk};
+        let layout = layout(&[Sidewalk, NormalReverse, NormalForward, Sidewalk], TrafficSide::Right);
+        assert_eq!(layout.get_width(), 7.5);
+        assert_contiguous(&layout, TrafficSide::Right);
+        assert_eq!(layout.lanes().iter().map(LaneSlot::get_offset).collect::<Vec<_>>(), [3.125, 1.25, -1.25, -3.125]);
+        assert_eq!(layout.separators().collect::<Vec<_>>(), [
+            (3.75, Nothing),
+            (2.5, BorderStrip(Edge)),
+            (0.0, SeparationStrip(Divergent, DoubleContinuous)),
+            (-2.5, Curb),
+            (-3.75, Nothing),
+        ]);
+    }
+
+    #[test]
+    fn one_way() {
+        use crate::traffic::LaneBorder::Edge;
+        use crate::traffic::LaneCrossing::SingleDashed;
+        use crate::traffic::LaneFlow::Convergent;
+        use crate::traffic::LaneSeparator::{BorderStrip, Curb, Nothing, SeparationStrip};
+        use crate::traffic::LaneType::{Grass, NormalForward};
+        let layout = layout(&[Grass, NormalForward, NormalForward, Grass], TrafficSide::Right);
+        assert_contiguous(&layout, TrafficSide::Right);
+        assert_eq!(layout.separators().collect::<Vec<_>>(), [
+            (3.75, Nothing),
+            (2.5, BorderStrip(Edge)),
+            (0.0, SeparationStrip(Convergent, SingleDashed)),
+            (-2.5, Curb),
+            (-3.75, Nothing),
+        ]);
+    }
+
+    #[test]
+    fn median_split() {
+        use crate::traffic::LaneBorder::{Edge, Middle};
+        use crate::traffic::LaneSeparator::{BorderStrip, Curb, Nothing};
+        use crate::traffic::LaneType::{Grass, Median, NormalForward, NormalReverse};
+        let layout = layout(&[Grass, NormalReverse, Median, NormalForward, Grass], TrafficSide::Right);
+        assert_contiguous(&layout, TrafficSide::Right);
+        assert_eq!(layout.separators().map(|(_, separator)| separator).collect::<Vec<_>>(), [
+            Nothing,
+            BorderStrip(Edge),
+            Curb,
+            //The carriageway beyond the median is not at the edge of the road
+            BorderStrip(Middle),
+            Curb,
+            Nothing,
+        ]);
+    }
+
+    #[test]
+    fn left_hand_traffic_mirrors_the_layout() {
+        use crate::traffic::LaneType::{Grass, Median, NormalForward, NormalReverse, Sidewalk};
+        for lanes in [&[Sidewalk, NormalReverse, NormalForward, Sidewalk][..], &[Grass, NormalReverse, Median, NormalForward, Grass]] {
+            let right = layout(lanes, TrafficSide::Right);
+            let left = layout(lanes, TrafficSide::Left);
+            assert_contiguous(&left, TrafficSide::Left);
+            assert_eq!(left.get_width(), right.get_width());
+            for (left, right) in left.lanes().iter().zip(right.lanes()) {
+                assert_eq!(left.get_type(), right.get_type());
+                assert_eq!(left.get_offset(), -right.get_offset());
+                assert_eq!(left.get_width(), right.get_width());
+            }
+            for ((left_offset, left), (right_offset, right)) in left.separators().zip(right.separators()) {
+                assert_eq!(left_offset, -right_offset);
+                assert_eq!(left, right);
+            }
+        }
+    }
+}
@@ -0,0 +1,641 @@
+use crate::math::if_else;
+use rustc_hash::FxHashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use strum::IntoEnumIterator;
+use strum::EnumCount;
+use strum_macros::{EnumCount, EnumIter, FromRepr};
+use LaneCrossing::{DoubleContinuous, DoubleDashed, SingleContinuous, SingleDashed};
+use LaneDirection::{Forward, Reverse};
+use LaneFlow::{Convergent, Divergent};
+use LaneSeparator::{BorderStrip, Curb, Nothing, ParkingStrip, SeparationStrip};
+use LaneType::{BikeForward, BikeReverse, BusForward, BusReverse, DirtForward, DirtReverse, Grass, Median, NormalForward, NormalReverse, ParkingForward, ParkingReverse, ShoulderForward, ShoulderReverse, Sidewalk, TramForward, TramReverse, TurnLane};
+use LaneWidth::Full;
+use LaneWidth::Half;
+
+pub mod lane_dsl;
+pub mod layout;
+pub mod preset;
+pub mod side;
+
+/// Routes treat lanes reserved for their kind of traffic as this much faster than other lanes.
+const RESERVED_LANE_FACTOR: f32 = 0.8;
+/// Vehicles per hour a paved traffic lane carries at most, one vehicle every two seconds.
+const LANE_CAPACITY: f32 = 1800.0;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, EnumIter, EnumCount, FromRepr)]
+#[repr(u8)]
+pub enum LaneType {
+    Grass,
+    Sidewalk,
+    NormalForward,
+    NormalReverse,
+    DirtForward,
+    DirtReverse,
+    BusForward,
+    BusReverse,
+    ParkingForward,
+    ParkingReverse,
+    ShoulderForward,
+    ShoulderReverse,
+    BikeForward,
+    BikeReverse,
+    TramForward,
+    TramReverse,
+    Median,
+    /// A centre lane shared by both directions, entered from the lanes beside it to wait for a turn across the
+    /// opposing traffic at the end of the edge.
+    TurnLane,
+}
+
+impl LaneType {
+    pub fn width(self) -> LaneWidth {
+        match self {
+            Grass | Sidewalk | BikeForward | BikeReverse => Half,
+            NormalForward | NormalReverse | DirtForward | DirtReverse | BusForward | BusReverse | ParkingForward | ParkingReverse | ShoulderForward | ShoulderReverse | TramForward | TramReverse | Median | TurnLane => Full,
+        }
+    }
+
+    fn name_internal(self) -> String {
+        format!("{:?}", self)
+    }
+
+    /// Vehicles per hour the lane carries at most, 0 for lanes nobody drives along.
+    pub fn capacity(self) -> f32 {
+        match self {
+            NormalForward | NormalReverse | BusForward | BusReverse | TramForward | TramReverse | BikeForward | BikeReverse => LANE_CAPACITY,
+            DirtForward | DirtReverse => LANE_CAPACITY / 2.0,
+            ShoulderForward | ShoulderReverse => LANE_CAPACITY / 3.0,
+            Grass | Sidewalk | Median | ParkingForward | ParkingReverse | TurnLane => 0.0,
+        }
+    }
+
+    pub fn direction(self) -> Option<LaneDirection> {
+        match self {
+            Grass | Sidewalk | Median | TurnLane => None,
+            NormalForward | DirtForward | BusForward | ParkingForward | ShoulderForward | BikeForward | TramForward => Some(Forward),
+            NormalReverse | DirtReverse | BusReverse | ParkingReverse | ShoulderReverse | BikeReverse | TramReverse => Some(Reverse),
+        }
+    }
+
+    /// The same kind of lane running the other way, or the lane itself if it has no direction.
+    pub fn opposite(self) -> Self {
+        match self {
+            Grass | Sidewalk | Median | TurnLane => self,
+            NormalForward => NormalReverse,
+            NormalReverse => NormalForward,
+            DirtForward => DirtReverse,
+            DirtReverse => DirtForward,
+            BusForward => BusReverse,
+            BusReverse => BusForward,
+            ParkingForward => ParkingReverse,
+            ParkingReverse => ParkingForward,
+            ShoulderForward => ShoulderReverse,
+            ShoulderReverse => ShoulderForward,
+            BikeForward => BikeReverse,
+            BikeReverse => BikeForward,
+            TramForward => TramReverse,
+            TramReverse => TramForward,
+        }
+    }
+
+    /// Whether the lane is part of the paved or unpaved surface vehicles use, as opposed to verges and medians.
+    pub fn is_carriageway(self) -> bool {
+        match self {
+            Grass | Sidewalk | Median => false,
+            NormalForward | NormalReverse | DirtForward | DirtReverse | BusForward | BusReverse | ParkingForward | ParkingReverse | ShoulderForward | ShoulderReverse | BikeForward | BikeReverse | TramForward | TramReverse | TurnLane => true,
+        }
+    }
+
+    /// Whether vehicles only use the lane to turn at the end of the edge. Such lanes are never entered from a junction.
+    pub fn is_turn_only(self) -> bool {
+        self == TurnLane
+    }
+
+    pub fn pre_separator(self, previous: Self, first_lane: bool) -> LaneSeparator {
+        match self {
+            Grass | Median => match previous {
+                Grass | Sidewalk | DirtForward | DirtReverse | ShoulderForward | ShoulderReverse | Median => Nothing,
+                NormalForward | NormalReverse | BusForward | BusReverse | ParkingForward | ParkingReverse | BikeForward | BikeReverse | TramForward | TramReverse | TurnLane => Curb,
+            }
+            Sidewalk => match previous {
+                Grass | Sidewalk | DirtForward | DirtReverse | Median => Nothing,
+                NormalForward | NormalReverse | BusForward | BusReverse | ParkingForward | ParkingReverse | ShoulderForward | ShoulderReverse | BikeForward | BikeReverse | TramForward | TramReverse | TurnLane => Curb,
+            }
+            NormalForward => match previous {
+                Grass | Sidewalk | Median => BorderStrip(if_else!(first_lane => LaneBorder::Edge ; LaneBorder::Middle)),
+                NormalForward => SeparationStrip(Convergent, SingleDashed),
+                NormalReverse => SeparationStrip(Divergent, DoubleContinuous),
+                DirtForward | DirtReverse | ParkingForward | ParkingReverse | ShoulderForward | ShoulderReverse => Nothing,
+                BusForward => SeparationStrip(Convergent, DoubleContinuous),
+                BusReverse => SeparationStrip(Divergent, DoubleContinuous),
+                BikeForward | TramForward => SeparationStrip(Convergent, SingleContinuous),
+                BikeReverse => SeparationStrip(Divergent, SingleContinuous),
+                TramReverse => SeparationStrip(Divergent, DoubleContinuous),
+                TurnLane => SeparationStrip(Divergent, DoubleDashed),
+            }
+            NormalReverse => match previous {
+                Grass | Sidewalk | Median => BorderStrip(if_else!(first_lane => LaneBorder::Edge ; LaneBorder::Middle)),
+                NormalForward => SeparationStrip(Divergent, DoubleContinuous),
+                NormalReverse => SeparationStrip(Convergent, SingleDashed),
+                DirtForward | DirtReverse | ParkingForward | ParkingReverse | ShoulderForward | ShoulderReverse => Nothing,
+                BusForward => SeparationStrip(Divergent, DoubleContinuous),
+                BusReverse => SeparationStrip(Convergent, DoubleContinuous),
+                BikeForward => SeparationStrip(Divergent, SingleContinuous),
+                BikeReverse | TramReverse => SeparationStrip(Convergent, SingleContinuous),
+                TramForward => SeparationStrip(Divergent, DoubleContinuous),
+                TurnLane => SeparationStrip(Divergent, DoubleDashed),
+            }
+            DirtForward | DirtReverse => Nothing,
+            BusForward => match previous {
+                Grass | Sidewalk | Median => BorderStrip(if_else!(first_lane => LaneBorder::Edge ; LaneBorder::Middle)),
+                NormalForward => SeparationStrip(Convergent, DoubleContinuous),
+                NormalReverse => SeparationStrip(Divergent, DoubleContinuous),
+                DirtForward | DirtReverse | ParkingForward | ParkingReverse | ShoulderForward | ShoulderReverse => Nothing,
+                BusForward | TramForward => SeparationStrip(Convergent, SingleDashed),
+                BusReverse | TramReverse => SeparationStrip(Divergent, SingleDashed),
+                BikeForward => SeparationStrip(Convergent, SingleContinuous),
+                BikeReverse => SeparationStrip(Divergent, SingleContinuous),
+                TurnLane => SeparationStrip(Divergent, DoubleDashed),
+            }
+            BusReverse => match previous {
+                Grass | Sidewalk | Median => BorderStrip(if_else!(first_lane => LaneBorder::Edge ; LaneBorder::Middle)),
+                NormalForward => SeparationStrip(Divergent, DoubleContinuous),
+                NormalReverse => SeparationStrip(Convergent, DoubleContinuous),
+                DirtForward | DirtReverse | ParkingForward | ParkingReverse | ShoulderForward | ShoulderReverse => Nothing,
+                BusForward | TramForward => SeparationStrip(Divergent, SingleDashed),
+                BusReverse | TramReverse => SeparationStrip(Convergent, SingleDashed),
+                BikeForward => SeparationStrip(Divergent, SingleContinuous),
+                BikeReverse => SeparationStrip(Convergent, SingleContinuous),
+                TurnLane => SeparationStrip(Divergent, DoubleDashed),
+            }
+            ParkingForward | ParkingReverse => match previous {
+                Grass | Sidewalk | Median => Nothing,
+                NormalForward | NormalReverse | DirtForward | DirtReverse | BusForward | BusReverse | ShoulderForward | ShoulderReverse | BikeForward | BikeReverse | TramForward | TramReverse | TurnLane => ParkingStrip,
+                ParkingForward | ParkingReverse => SeparationStrip(Convergent, SingleContinuous),
+            }
+            ShoulderForward | ShoulderReverse => match previous {
+                Grass | Sidewalk | DirtForward | DirtReverse | ShoulderForward | ShoulderReverse | Median => Nothing,
+                NormalForward | NormalReverse | BusForward | BusReverse | ParkingForward | ParkingReverse | BikeForward | BikeReverse | TramForward | TramReverse | TurnLane => SeparationStrip(Convergent, SingleContinuous),
+            }
+            BikeForward => match previous {
+                Grass | Sidewalk | Median => BorderStrip(if_else!(first_lane => LaneBorder::Edge ; LaneBorder::Middle)),
+                NormalForward | BusForward | TramForward => SeparationStrip(Convergent, SingleContinuous),
+                NormalReverse | BusReverse | TramReverse | TurnLane => SeparationStrip(Divergent, SingleContinuous),
+                DirtForward | DirtReverse | ShoulderForward | ShoulderReverse => Nothing,
+                ParkingForward | ParkingReverse => ParkingStrip,
+                BikeForward => SeparationStrip(Convergent, SingleDashed),
+                BikeReverse => SeparationStrip(Divergent, SingleDashed),
+            }
+            BikeReverse => match previous {
+                Grass | Sidewalk | Median => BorderStrip(if_else!(first_lane => LaneBorder::Edge ; LaneBorder::Middle)),
+                NormalForward | BusForward | TramForward | TurnLane => SeparationStrip(Divergent, SingleContinuous),
+                NormalReverse | BusReverse | TramReverse => SeparationStrip(Convergent, SingleContinuous),
+                DirtForward | DirtReverse | ShoulderForward | ShoulderReverse => Nothing,
+                ParkingForward | ParkingReverse => ParkingStrip,
+                BikeForward => SeparationStrip(Divergent, SingleDashed),
+                BikeReverse => SeparationStrip(Convergent, SingleDashed),
+            }
+            TramForward => match previous {
+                Grass | Sidewalk | Median => BorderStrip(if_else!(first_lane => LaneBorder::Edge ; LaneBorder::Middle)),
+                NormalForward | BikeForward => SeparationStrip(Convergent, SingleContinuous),
+                NormalReverse => SeparationStrip(Divergent, DoubleContinuous),
+                DirtForward | DirtReverse | ParkingForward | ParkingReverse | ShoulderForward | ShoulderReverse => Nothing,
+                BusForward | TramForward => SeparationStrip(Convergent, SingleDashed),
+                BusReverse => SeparationStrip(Divergent, SingleDashed),
+                BikeReverse | TramReverse => SeparationStrip(Divergent, SingleContinuous),
+                TurnLane => SeparationStrip(Divergent, DoubleDashed),
+            }
+            TramReverse => match previous {
+                Grass | Sidewalk | Median => BorderStrip(if_else!(first_lane => LaneBorder::Edge ; LaneBorder::Middle)),
+                NormalForward => SeparationStrip(Divergent, DoubleContinuous),
+                NormalReverse | BikeReverse => SeparationStrip(Convergent, SingleContinuous),
+                DirtForward | DirtReverse | ParkingForward | ParkingReverse | ShoulderForward | ShoulderReverse => Nothing,
+                BusForward => SeparationStrip(Divergent, SingleDashed),
+                BusReverse | TramReverse => SeparationStrip(Convergent, SingleDashed),
+                BikeForward | TramForward => SeparationStrip(Divergent, SingleContinuous),
+                TurnLane => SeparationStrip(Divergent, DoubleDashed),
+            }
+            TurnLane => match previous {
+                Grass | Sidewalk | Median => BorderStrip(if_else!(first_lane => LaneBorder::Edge ; LaneBorder::Middle)),
+                NormalForward | NormalReverse | BusForward | BusReverse | TramForward | TramReverse => SeparationStrip(Divergent, DoubleDashed),
+                DirtForward | DirtReverse | ParkingForward | ParkingReverse | ShoulderForward | ShoulderReverse => Nothing,
+                BikeForward | BikeReverse => SeparationStrip(Divergent, SingleContinuous),
+                TurnLane => SeparationStrip(Divergent, DoubleContinuous),
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, EnumIter)]
+pub enum RoutingProfile {
+    Car,
+    Bus,
+    Pedestrian,
+    Cyclist,
+    Truck,
+}
+
+impl RoutingProfile {
+    pub fn permitted_lanes(self) -> &'static [LaneType] {
+        match self {
+            RoutingProfile::Car => &[NormalForward, NormalReverse, DirtForward, DirtReverse, TurnLane],
+            RoutingProfile::Bus => &[NormalForward, NormalReverse, BusForward, BusReverse, TurnLane],
+            RoutingProfile::Pedestrian => &[Sidewalk],
+            RoutingProfile::Cyclist => &[NormalForward, NormalReverse, DirtForward, DirtReverse, ShoulderForward, ShoulderReverse, BikeForward, BikeReverse],
+            RoutingProfile::Truck => &[NormalForward, NormalReverse, TurnLane],
+        }
+    }
+
+    pub fn permits(self, lane: LaneType) -> bool {
+        self.permitted_lanes().contains(&lane)
+    }
+
+    /// Factor on the travel time of a permitted lane when routing, below 1 for lanes reserved for the profile so
+    /// routes prefer them.
+    pub fn lane_cost_factor(self, lane: LaneType) -> f32 {
+        match (self, lane) {
+            (RoutingProfile::Bus, BusForward | BusReverse) | (RoutingProfile::Cyclist, BikeForward | BikeReverse) => RESERVED_LANE_FACTOR,
+            _ => 1.0,
+        }
+    }
+
+    /// The smallest [`RoutingProfile::lane_cost_factor`] of the lanes the profile may use.
+    pub fn min_lane_cost_factor(self) -> f32 {
+        self.permitted_lanes().iter().map(|lane| self.lane_cost_factor(*lane)).fold(1.0, f32::min)
+    }
+
+    /// Whether any profile is allowed to route over the given lane.
+    pub fn any_permits(lane: LaneType) -> bool {
+        Self::iter().any(|profile| profile.permits(lane))
+    }
+
+    /// Whether some profile may continue from lane `a` onto lane `b`.
+    pub fn any_connects(a: LaneType, b: LaneType) -> bool {
+        Self::iter().any(|profile| profile.permits(a) && profile.permits(b))
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            RoutingProfile::Car => RoutingProfile::Bus,
+            RoutingProfile::Bus => RoutingProfile::Pedestrian,
+            RoutingProfile::Pedestrian => RoutingProfile::Cyclist,
+            RoutingProfile::Cyclist => RoutingProfile::Truck,
+            RoutingProfile::Truck => RoutingProfile::Car,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LaneWidth {
+    Half,
+    Full,
+}
+
+impl LaneWidth {
+    pub fn units(self) -> u8 {
+        match self {
+            Half => 1,
+            Full => 2,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum LaneDirection {
+    Forward,
+    Reverse,
+}
+
+pub struct LaneTypeManager {
+    name_to_variant: FxHashMap<String, LaneType>,
+}
+
+impl LaneTypeManager {
+    pub fn new() -> Self {
+        let mut name_to_variant = FxHashMap::default();
+        for lane in LaneType::iter() {
+            name_to_variant.insert(lane.name_internal(), lane);
+        }
+        Self {
+            name_to_variant,
+        }
+    }
+}
+
+pub const MAX_LANE_SIZE: u8 = u8::MAX;
+/// Bits needed to store the code of any [`LaneType`].
+const LANE_CODE_BITS: u32 = u8::BITS - (LaneType::COUNT as u8 - 1).leading_zeros();
+const LANE_CODE_MASK: u64 = (1 << LANE_CODE_BITS) - 1;
+/// How many lanes fit in the inline storage before spilling over to the heap.
+const INLINE_CAPACITY: usize = (u64::BITS / LANE_CODE_BITS) as usize;
+
+#[derive(Clone)]
+pub struct LaneDefinition {
+    lanes: LaneStorage,
+}
+
+#[derive(Clone)]
+enum LaneStorage {
+    Inline {
+        size: u8,
+        len: u8,
+        codes: u64,
+    },
+    //Boxed twice to keep the pointer thin, so the whole definition fits in 16 bytes
+    #[allow(clippy::redundant_allocation)]
+    Spilled {
+        size: u8,
+        lanes: Box<Box<[LaneType]>>,
+    },
+}
+
+impl LaneDefinition {
+    /// Builds a definition as wide as its lanes, failing if they add up to more than [`MAX_LANE_SIZE`].
+    pub fn from_lanes(lanes: &[LaneType]) -> Result<Self, LaneError> {
+        let width = lanes.iter().map(|lane| lane.width().units() as u32).sum::<u32>();
+        let size = u8::try_from(width).map_err(|_| LaneError::TooWide(width))?;
+        Self::with_size(size, lanes)
+    }
+
+    /// Builds a definition for an edge of the given size, checking that the lane widths fill it exactly.
+    pub fn with_size(size: u8, lanes: &[LaneType]) -> Result<Self, LaneError> {
+        if size == 0 {
+            return Err(LaneError::InvalidSize(size));
+        }
+        let actual = lanes.iter().map(|lane| lane.width().units() as u32).sum();
+        if actual != size as u32 {
+            return Err(LaneError::WidthMismatch {
+                expected: size,
+                actual,
+            });
+        }
+        let lanes = if lanes.len() <= INLINE_CAPACITY {
+            let mut codes = 0;
+            for (index, lane) in lanes.iter().enumerate() {
+                codes |= (*lane as u64) << (index as u32 * LANE_CODE_BITS);
+            }
+            LaneStorage::Inline {
+                size,
+                len: lanes.len() as u8,
+                codes,
+            }
+        } //
+        else {
+            LaneStorage::Spilled {
+                size,
+                lanes: Box::new(lanes.into()),
+            }
+        };
+        Ok(Self {
+            lanes,
+        })
+    }
+
+    pub fn get_size(&self) -> u8 {
+        match self.lanes {
+            LaneStorage::Inline { size, .. } | LaneStorage::Spilled { size, .. } => size,
+        }
+    }
+
+    pub fn lane_count(&self) -> u8 {
+        match &self.lanes {
+            LaneStorage::Inline { len, .. } => *len,
+            LaneStorage::Spilled { lanes, .. } => lanes.len() as u8,
+        }
+    }
+
+    pub fn get(&self, index: u8) -> Option<LaneType> {
+        if index >= self.lane_count() {
+            return None;
+        }
+        match &self.lanes {
+            LaneStorage::Inline { codes, .. } => LaneType::from_repr(((codes >> (index as u32 * LANE_CODE_BITS)) & LANE_CODE_MASK) as u8),
+            LaneStorage::Spilled { lanes, .. } => Some(lanes[index as usize]),
+        }
+    }
+
+    /// Iterates over the lanes from left to right.
+    pub fn lanes(&self) -> impl ExactSizeIterator<Item=LaneType> + '_ {
+        (0..self.lane_count()).map(|index| self.get(index).unwrap())
+    }
+
+    /// Vehicles per hour the edge carries at most in the direction, on the lanes the profile may use.
+    pub fn capacity(&self, direction: LaneDirection, profile: RoutingProfile) -> f32 {
+        self.lanes().filter(|lane| lane.direction() == Some(direction) && profile.permits(*lane)).map(LaneType::capacity).sum()
+    }
+
+    pub fn directionality(&self) -> Directionality {
+        let mut directionality = Directionality {
+            forward: false,
+            reverse: false,
+        };
+        for lane in self.lanes().filter(|lane| RoutingProfile::any_permits(*lane)) {
+            match lane.direction() {
+                Some(Forward) => directionality.forward = true,
+                Some(Reverse) => directionality.reverse = true,
+                None => {
+                    directionality.forward = true;
+                    directionality.reverse = true;
+                }
+            }
+        }
+        directionality
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Directionality {
+    forward: bool,
+    reverse: bool,
+}
+
+impl Directionality {
+    pub fn allows(self, direction: LaneDirection) -> bool {
+        match direction {
+            Forward => self.forward,
+            Reverse => self.reverse,
+        }
+    }
+
+    pub fn is_one_way(self) -> bool {
+        self.forward != self.reverse
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LaneError {
+    IndexOutOfBounds {
+        index: usize,
+        len: usize,
+    },
+    WidthMismatch {
+        expected: u8,
+        actual: u32,
+    },
+    InvalidSize(u8),
+    /// The lane widths add up to more than [`MAX_LANE_SIZE`].
+    TooWide(u32),
+}
+
+impl LaneError {
+    pub fn check_index(index: usize, len: usize) -> Result<(), LaneError> {
+        if index >= len {
+            return Err(LaneError::IndexOutOfBounds {
+                index,
+                len,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Display for LaneError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LaneError::IndexOutOfBounds { index, len } => write!(f, "Lane index {index} is out of bounds for {len} lanes"),
+            LaneError::WidthMismatch { expected, actual } => write!(f, "Lane widths add up to {actual}, but the edge has size {expected}"),
+            LaneError::InvalidSize(size) => write!(f, "Size {size} is not between 1 and {MAX_LANE_SIZE}"),
+            LaneError::TooWide(width) => write!(f, "Lane widths add up to {width}, more than the maximum size {MAX_LANE_SIZE}"),
+        }
+    }
+}
+
+impl Error for LaneError {}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LaneSeparator {
+    Nothing,
+    Curb,
+    BorderStrip(LaneBorder),
+    SeparationStrip(LaneFlow, LaneCrossing),
+    ParkingStrip,
+}
+
+impl LaneSeparator {
+    /// Whether vehicles may change lanes across the separator.
+    pub fn is_crossable(self) -> bool {
+        match self {
+            LaneSeparator::Nothing | LaneSeparator::ParkingStrip => true,
+            LaneSeparator::SeparationStrip(_, crossing) => crossing != LaneCrossing::DoubleContinuous,
+            LaneSeparator::Curb | LaneSeparator::BorderStrip(_) => false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LaneFlow {
+    Convergent,
+    Divergent,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LaneCrossing {
+    SingleDashed,
+    SingleContinuous,
+    DoubleDashed,
+    DoubleContinuous,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LaneBorder {
+    Edge,
+    Middle,
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(lanes: &[LaneType]) -> Result<u8, LaneError> {
+        LaneDefinition::from_lanes(lanes).map(|lane_def| lane_def.get_size())
+    }
+
+    #[test]
+    fn from_lanes_sizes_the_definition_by_its_lanes() {
+        let lane_def = LaneDefinition::from_lanes(&[Sidewalk, NormalReverse, NormalForward, Sidewalk]).unwrap();
+        assert_eq!(lane_def.get_size(), 6);
+        assert_eq!(lane_def.lanes().collect::<Vec<_>>(), [Sidewalk, NormalReverse, NormalForward, Sidewalk]);
+    }
+
+    #[test]
+    fn from_lanes_rejects_empty_and_oversized_definitions() {
+        assert_eq!(size(&[]), Err(LaneError::InvalidSize(0)));
+        assert_eq!(size(&[NormalForward; 128]), Err(LaneError::TooWide(256)));
+        assert_eq!(size(&[Sidewalk; MAX_LANE_SIZE as usize]), Ok(MAX_LANE_SIZE));
+    }
+
+    /// Lanes vehicles drive on that are delimited by painted lines.
+    fn is_marked(lane: LaneType) -> bool {
+        matches!(lane, NormalForward | NormalReverse | BusForward | BusReverse | BikeForward | BikeReverse | TramForward | TramReverse | TurnLane)
+    }
+
+    /// Every separator of the matrix as `(lane, previous, first_lane, separator)`.
+    fn matrix() -> impl Iterator<Item=(LaneType, LaneType, bool, LaneSeparator)> {
+        LaneType::iter().flat_map(|lane| LaneType::iter().flat_map(move |previous| {
+            [true, false].map(|first_lane| (lane, previous, first_lane, lane.pre_separator(previous, first_lane)))
+        }))
+    }
+
+    #[test]
+    fn only_carriageway_borders_depend_on_the_first_lane() {
+        for (lane, previous, first_lane, separator) in matrix() {
+            let is_border = is_marked(lane) && matches!(previous, Grass | Sidewalk | Median);
+            let expected = if_else!(is_border => BorderStrip(if_else!(first_lane => LaneBorder::Edge ; LaneBorder::Middle)) ; lane.pre_separator(previous, !first_lane));
+            assert_eq!(separator, expected, "{lane:?} after {previous:?}, first lane {first_lane}");
+        }
+    }
+
+    #[test]
+    fn separators_between_marked_lanes_are_symmetric() {
+        for (lane, previous, first_lane, separator) in matrix().filter(|(lane, previous, _, _)| is_marked(*lane) && is_marked(*previous)) {
+            assert_eq!(separator, previous.pre_separator(lane, first_lane), "{lane:?} and {previous:?}");
+            let SeparationStrip(flow, _) = separator else {
+                panic!("{lane:?} after {previous:?} is separated by {separator:?}");
+            };
+            //Turn lanes run both ways, so traffic beside them always runs in the other direction somewhere
+            let same_direction = lane.direction().is_some() && lane.direction() == previous.direction();
+            assert_eq!(flow, if_else!(same_direction => Convergent ; Divergent), "{lane:?} after {previous:?}");
+            assert_eq!(separator, lane.opposite().pre_separator(previous.opposite(), first_lane), "{lane:?} after {previous:?} mirrored");
+        }
+    }
+
+    #[test]
+    fn medians_separate_like_grass() {
+        for lane in LaneType::iter() {
+            for first_lane in [true, false] {
+                assert_eq!(Median.pre_separator(lane, first_lane), Grass.pre_separator(lane, first_lane), "Median after {lane:?}");
+                assert_eq!(lane.pre_separator(Median, first_lane), lane.pre_separator(Grass, first_lane), "{lane:?} after Median");
+            }
+        }
+    }
+
+    #[test]
+    fn new_lanes_are_separated_sensibly() {
+        //Bike lanes are kept apart from traffic by a continuous line, but may be overtaken on within
+        assert_eq!(BikeForward.pre_separator(NormalForward, false), SeparationStrip(Convergent, SingleContinuous));
+        assert_eq!(NormalForward.pre_separator(BikeForward, false), SeparationStrip(Convergent, SingleContinuous));
+        assert_eq!(BikeReverse.pre_separator(NormalForward, false), SeparationStrip(Divergent, SingleContinuous));
+        assert_eq!(BikeForward.pre_separator(BikeForward, false), SeparationStrip(Convergent, SingleDashed));
+        assert_eq!(BikeReverse.pre_separator(ParkingReverse, false), ParkingStrip);
+        assert_eq!(BikeForward.pre_separator(ShoulderForward, false), Nothing);
+        //Trams are kept apart from traffic, opposing trams and traffic may not be crossed at all
+        assert_eq!(TramForward.pre_separator(NormalReverse, false), SeparationStrip(Divergent, DoubleContinuous));
+        assert_eq!(NormalReverse.pre_separator(TramForward, false), SeparationStrip(Divergent, DoubleContinuous));
+        assert_eq!(TramForward.pre_separator(NormalForward, false), SeparationStrip(Convergent, SingleContinuous));
+        assert_eq!(TramForward.pre_separator(BusForward, false), SeparationStrip(Convergent, SingleDashed));
+        //Medians are raised like the sidewalk, the carriageway beyond them is in the middle of the road
+        assert_eq!(Median.pre_separator(NormalReverse, true), Curb);
+        assert_eq!(Median.pre_separator(BikeForward, false), Curb);
+        assert_eq!(NormalForward.pre_separator(Median, false), BorderStrip(LaneBorder::Middle));
+        assert_eq!(Sidewalk.pre_separator(Median, false), Nothing);
+        assert_eq!(Median.pre_separator(DirtReverse, false), Nothing);
+        //Turn lanes are entered from either side across dashed lines, but never from each other
+        assert_eq!(TurnLane.pre_separator(NormalReverse, false), SeparationStrip(Divergent, DoubleDashed));
+        assert_eq!(NormalForward.pre_separator(TurnLane, false), SeparationStrip(Divergent, DoubleDashed));
+        assert_eq!(TurnLane.pre_separator(TurnLane, false), SeparationStrip(Divergent, DoubleContinuous));
+        assert_eq!(BikeForward.pre_separator(TurnLane, false), SeparationStrip(Divergent, SingleContinuous));
+        assert_eq!(TurnLane.pre_separator(Median, false), BorderStrip(LaneBorder::Middle));
+        assert!(NormalForward.pre_separator(TurnLane, false).is_crossable());
+        assert!(!TurnLane.pre_separator(TurnLane, false).is_crossable());
+    }
+}
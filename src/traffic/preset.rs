@@ -0,0 +1,331 @@
+use crate::traffic::lane_dsl::LayoutParseError;
+use crate::traffic::{LaneDefinition, LaneTypeManager};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+const BUILTIN: &str = "
+[local_street]
+name = Two-lane local street
+speed = 1.0
+lanes = Grass|Sidewalk|NormalReverse|NormalForward|Sidewalk|Grass
+
+[avenue]
+name = Four-lane avenue with parking
+speed = 1.5
+lanes = Sidewalk|ParkingReverse|NormalReverse|NormalReverse|NormalForward|NormalForward|ParkingForward|Sidewalk
+
+[bus_corridor]
+name = Bus corridor
+speed = 1.5
+lanes = Sidewalk|BusReverse|NormalReverse|NormalForward|BusForward|Sidewalk
+
+[rural_dirt_road]
+name = Rural dirt road
+speed = 0.75
+lanes = Grass|DirtReverse|DirtForward|Grass
+
+[highway]
+name = Highway with shoulders
+speed = 3.0
+lanes = Grass|ShoulderReverse|NormalReverse|NormalReverse|Grass|NormalForward|NormalForward|ShoulderForward|Grass
+
+[tram_boulevard]
+name = Tram boulevard with bike lanes
+speed = 1.25
+lanes = Sidewalk|BikeReverse|TramReverse|NormalReverse|Median|NormalForward|TramForward|BikeForward|Sidewalk
+
+[turn_lane_street]
+name = Street with centre turn lane
+speed = 1.0
+lanes = Sidewalk|BikeReverse|NormalReverse|TurnLane|NormalForward|BikeForward|Sidewalk
+";
+
+#[derive(Clone)]
+pub struct RoadPreset {
+    id: String,
+    display_name: String,
+    speed: f32,
+    lane_def: LaneDefinition,
+}
+
+impl RoadPreset {
+    pub fn get_display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    pub fn get_speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn get_lane_def(&self) -> &LaneDefinition {
+        &self.lane_def
+    }
+}
+
+pub struct RoadPresetLibrary {
+    presets: Vec<RoadPreset>,
+}
+
+impl RoadPresetLibrary {
+    /// Creates a library holding the built-in presets.
+    pub fn new(lane_types: &LaneTypeManager) -> Self {
+        let mut library = RoadPresetLibrary {
+            presets: vec![],
+        };
+        library.load(BUILTIN, lane_types).expect("Built-in road presets are invalid!");
+        library
+    }
+
+    /// Adds the presets described in `source`, replacing presets with the same id.
+    ///
+    /// Every preset starts with its id in brackets, followed by `name`, `speed` and `lanes` entries:
+    /// ```text
+    /// [local_street]
+    /// name = Two-lane local street
+    /// speed = 1.0
+    /// lanes = Sidewalk|NormalReverse|NormalForward|Sidewalk
+    /// size = 6
+    /// ```
+    /// The optional `size` entry gives the width of the edge in lane width units, which the lanes then have to fill
+    /// exactly. Empty lines and lines starting with `#` are ignored.
+    pub fn load(&mut self, source: &str, lane_types: &LaneTypeManager) -> Result<(), PresetLoadError> {
+        let mut pending: Option<PendingPreset> = None;
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(id) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                if let Some(preset) = pending.take() {
+                    self.insert(preset.finish(lane_types)?);
+                }
+                pending = Some(PendingPreset::new(id.trim(), line_number));
+                continue;
+            }
+            let error = |kind| PresetLoadError {
+                line: line_number,
+                kind,
+            };
+            let Some(preset) = pending.as_mut() else {
+                return Err(error(PresetErrorKind::MissingSection));
+            };
+            let Some((key, value)) = line.split_once('=').map(|(key, value)| (key.trim(), value.trim())) else {
+                return Err(error(PresetErrorKind::MalformedLine));
+            };
+            match key {
+                "name" => preset.display_name = Some(value.to_string()),
+                "speed" => match value.parse::<f32>() {
+                    Ok(speed) if speed > 0.0 && speed.is_finite() => preset.speed = Some(speed),
+                    _ => return Err(error(PresetErrorKind::InvalidSpeed(value.to_string()))),
+                },
+                "lanes" => preset.lanes = Some((value.to_string(), line_number)),
+                "size" => preset.size = Some(value.parse::<u8>().map_err(|_| error(PresetErrorKind::InvalidSize(value.to_string())))?),
+                _ => return Err(error(PresetErrorKind::UnknownKey(key.to_string()))),
+            }
+        }
+        if let Some(preset) = pending {
+            self.insert(preset.finish(lane_types)?);
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, preset: RoadPreset) {
+        match self.presets.iter_mut().find(|existing| existing.id == preset.id) {
+            Some(existing) => *existing = preset,
+            None => self.presets.push(preset),
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&RoadPreset> {
+        self.presets.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.presets.len()
+    }
+}
+
+struct PendingPreset {
+    id: String,
+    line: usize,
+    display_name: Option<String>,
+    speed: Option<f32>,
+    /// The layout and the line it is on, parsed once the size is known.
+    lanes: Option<(String, usize)>,
+    size: Option<u8>,
+}
+
+impl PendingPreset {
+    fn new(id: &str, line: usize) -> Self {
+        PendingPreset {
+            id: id.to_string(),
+            line,
+            display_name: None,
+            speed: None,
+            lanes: None,
+            size: None,
+        }
+    }
+
+    fn finish(self, lane_types: &LaneTypeManager) -> Result<RoadPreset, PresetLoadError> {
+        let missing = |field| PresetLoadError {
+            line: self.line,
+            kind: PresetErrorKind::MissingField(field),
+        };
+        let display_name = self.display_name.ok_or_else(|| missing("name"))?;
+        let speed = self.speed.ok_or_else(|| missing("speed"))?;
+        let (layout, line) = self.lanes.ok_or_else(|| missing("lanes"))?;
+        let lane_def = match self.size {
+            Some(size) => lane_types.parse_sized(&layout, size),
+            None => lane_types.parse(&layout),
+        };
+        Ok(RoadPreset {
+            display_name,
+            speed,
+            lane_def: lane_def.map_err(|e| PresetLoadError {
+                line,
+                kind: PresetErrorKind::Layout(e),
+            })?,
+            id: self.id,
+        })
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct PresetLoadError {
+    line: usize,
+    kind: PresetErrorKind,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum PresetErrorKind {
+    MissingSection,
+    MalformedLine,
+    UnknownKey(String),
+    InvalidSpeed(String),
+    InvalidSize(String),
+    MissingField(&'static str),
+    Layout(LayoutParseError),
+}
+
+impl Display for PresetLoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Line {}: ", self.line)?;
+        match &self.kind {
+            PresetErrorKind::MissingSection => write!(f, "Expected a preset id in brackets before any entry"),
+            PresetErrorKind::MalformedLine => write!(f, "Expected an entry of the form `key = value`"),
+            PresetErrorKind::UnknownKey(key) => write!(f, "Unknown key `{key}`, expected `name`, `speed`, `lanes` or `size`"),
+            PresetErrorKind::InvalidSpeed(speed) => write!(f, "Speed `{speed}` is not a positive number"),
+            PresetErrorKind::InvalidSize(size) => write!(f, "Size `{size}` is not a whole number of lane widths"),
+            PresetErrorKind::MissingField(field) => write!(f, "Preset is missing `{field}`"),
+            PresetErrorKind::Layout(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl Error for PresetLoadError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads `source` on top of the built-in presets.
+    fn load(source: &str) -> Result<RoadPresetLibrary, PresetLoadError> {
+        let lane_types = LaneTypeManager::new();
+        let mut library = RoadPresetLibrary::new(&lane_types);
+        library.load(source, &lane_types)?;
+        Ok(library)
+    }
+
+    fn error(line: usize, kind: PresetErrorKind) -> Option<PresetLoadError> {
+        Some(PresetLoadError {
+            line,
+            kind,
+        })
+    }
+
+    #[test]
+    fn rejects_entries_before_the_first_preset() {
+        assert_eq!(load("# A comment\n\nname = Street").err(), error(3, PresetErrorKind::MissingSection));
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert_eq!(load("[street]\nname Street").err(), error(2, PresetErrorKind::MalformedLine));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert_eq!(load("[street]\nname = Street\nwidth = 3").err(), error(3, PresetErrorKind::UnknownKey("width".to_string())));
+    }
+
+    #[test]
+    fn rejects_invalid_speeds() {
+        for speed in ["0", "-1.0", "fast", "inf", "NaN", ""] {
+            assert_eq!(load(&format!("[street]\nspeed = {speed}")).err(), error(2, PresetErrorKind::InvalidSpeed(speed.to_string())), "{speed}");
+        }
+    }
+
+    #[test]
+    fn reports_missing_fields_at_the_preset() {
+        let source = "[street]\nname = Street\nlanes = Sidewalk|NormalReverse|NormalForward|Sidewalk\n\n[lane]\nname = Lane\nspeed = 1.0";
+        assert_eq!(load(source).err(), error(1, PresetErrorKind::MissingField("speed")));
+        let source = "[street]\nname = Street\nspeed = 1.0\nlanes = Sidewalk|NormalReverse|NormalForward|Sidewalk\n\n[lane]\nname = Lane\nspeed = 1.0";
+        assert_eq!(load(source).err(), error(6, PresetErrorKind::MissingField("lanes")));
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        for size in ["wide", "-6", "6.0", "256"] {
+            assert_eq!(load(&format!("[street]\nsize = {size}")).err(), error(2, PresetErrorKind::InvalidSize(size.to_string())), "{size}");
+        }
+    }
+
+    #[test]
+    fn lanes_fill_the_given_size() {
+        use crate::traffic::LaneError;
+        let library = load("[street]\nname = Street\nspeed = 1.0\nlanes = Sidewalk|NormalReverse|NormalForward|Sidewalk\nsize = 6").unwrap();
+        assert_eq!(library.presets.last().unwrap().get_lane_def().get_size(), 6);
+        //Reported at the layout, wherever the size is given
+        let source = "[street]\nname = Street\nsize = 8\nspeed = 1.0\nlanes = Sidewalk|NormalReverse|NormalForward|Sidewalk";
+        assert_eq!(load(source).err(), error(5, PresetErrorKind::Layout(LayoutParseError::Size(LaneError::WidthMismatch {
+            expected: 8,
+            actual: 6,
+        }))));
+        assert_eq!(load(&source.replace("size = 8", "size = 0")).err(), error(5, PresetErrorKind::Layout(LayoutParseError::Size(LaneError::InvalidSize(0)))));
+    }
+
+    #[test]
+    fn reports_layout_errors() {
+        let Some(PresetLoadError { line: 2, kind: PresetErrorKind::Layout(LayoutParseError::UnknownLane { name, .. }) }) = load("[street]\nlanes = Sidewalk|Sidwalk\nname = Street\nspeed = 1.0").err() else {
+            panic!("Unknown lanes are not reported");
+        };
+        assert_eq!(name, "Sidwalk");
+    }
+
+    #[test]
+    fn user_presets_replace_built_ins() {
+        let builtin = load("").unwrap();
+        let library = load("[local_street]\nname = Narrow street\nspeed = 0.5\nlanes = Sidewalk|NormalReverse|NormalForward|Sidewalk\n\n[alley]\nname = Alley\nspeed = 0.25\nlanes = DirtForward").unwrap();
+        assert_eq!(library.len(), builtin.len() + 1);
+        let replaced = library.get(0).unwrap();
+        assert_eq!(replaced.id, "local_street");
+        assert_eq!(replaced.get_display_name(), "Narrow street");
+        assert_eq!(replaced.get_speed(), 0.5);
+        assert_eq!(replaced.get_lane_def().lane_count(), 4);
+        assert_eq!(library.get(builtin.len()).unwrap().get_display_name(), "Alley");
+    }
+
+    #[test]
+    fn bundled_presets_fill_their_edges() {
+        let library = load(include_str!("../../resources/road_presets.txt")).unwrap();
+        assert!(library.len() > load("").unwrap().len());
+        for preset in &library.presets {
+            let lane_def = preset.get_lane_def();
+            let width = lane_def.lanes().map(|lane| lane.width().units()).sum::<u8>();
+            assert_eq!(width, lane_def.get_size(), "{}", preset.id);
+            assert!(preset.get_speed() > 0.0, "{}", preset.id);
+        }
+    }
+}
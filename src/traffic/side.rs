@@ -0,0 +1,80 @@
+use ggez::glam::Vec2;
+use std::f32::consts::PI;
+
+/// Turns deviating less than this from the incoming direction count as going straight.
+const STRAIGHT_ANGLE: f32 = PI / 6.0;
+/// Turns deviating more than this from the incoming direction count as turning around.
+const AROUND_ANGLE: f32 = 5.0 * PI / 6.0;
+
+/// The side of the road vehicles drive on. Lane definitions are written for right-hand traffic, so under left-hand
+/// traffic the whole cross-section is mirrored.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum TrafficSide {
+    Right,
+    Left,
+}
+
+impl TrafficSide {
+    pub fn other(self) -> Self {
+        match self {
+            TrafficSide::Right => TrafficSide::Left,
+            TrafficSide::Left => TrafficSide::Right,
+        }
+    }
+
+    /// Sign applied to lateral offsets, which are laid out to the left of the forward direction under right-hand traffic.
+    pub fn lateral_sign(self) -> f32 {
+        match self {
+            TrafficSide::Right => 1.0,
+            TrafficSide::Left => -1.0,
+        }
+    }
+
+    /// The turn that has to cross the opposing traffic.
+    pub fn far_turn(self) -> Turn {
+        match self {
+            TrafficSide::Right => Turn::Left,
+            TrafficSide::Left => Turn::Right,
+        }
+    }
+
+    /// The turn that stays on the side of the road being driven on.
+    pub fn near_turn(self) -> Turn {
+        match self {
+            TrafficSide::Right => Turn::Right,
+            TrafficSide::Left => Turn::Left,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Turn {
+    Straight,
+    Left,
+    Right,
+    Around,
+}
+
+impl Turn {
+    /// Classifies the manoeuvre of leaving a junction along `outgoing` after arriving along `incoming`.
+    pub fn classify(incoming: Vec2, outgoing: Vec2) -> Self {
+        let angle = incoming.angle_to(outgoing);
+        if angle.abs() < STRAIGHT_ANGLE {
+            Turn::Straight
+        } //
+        else if angle.abs() > AROUND_ANGLE {
+            Turn::Around
+        } //
+        else if angle > 0.0 {
+            Turn::Left
+        } //
+        else {
+            Turn::Right
+        }
+    }
+
+    /// Whether the turn crosses the path of the opposing traffic under the given traffic side.
+    pub fn crosses_traffic(self, side: TrafficSide) -> bool {
+        self == side.far_turn() || self == Turn::Around
+    }
+}